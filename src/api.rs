@@ -0,0 +1,284 @@
+//! A JSON API for looking after Neolink, served by the HTTP server under `/api/`.  It takes the
+//! same users as the RTSP server, and each user only sees and manages the cameras that they are
+//! permitted to watch.  The POST actions change the camera, so they are only for users who have
+//! logged in, and are refused to anonymous users even when they may see the camera.
+//!
+//! - `GET /api/cameras`: every camera, with its connection state, firmware and RTSP mounts
+//! - `GET /api/cameras/<camera name>`: one camera
+//! - `POST /api/cameras/<camera name>/reconnect`: drops the connection to the camera and makes a
+//!   new one, or stops waiting to retry and tries now
+//! - `POST /api/cameras/<camera name>/sync-time`: sets the camera's clock to Neolink's, in the same
+//!   time zone as the time_sync config would
+//! - `POST /api/cameras/<camera name>/reboot`: reboots the camera
+//! - `GET /api/mounts`: every RTSP mount, with how many clients are playing it
+use crate::health::CameraStatus;
+use crate::http::HttpCamera;
+use log::*;
+use neolink::gst::RtspServer;
+use serde::Serialize;
+use serde_json::json;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Instant;
+use tiny_http::{Header, Method, Response};
+
+#[derive(Serialize)]
+struct CameraJson<'a> {
+    name: &'a str,
+    state: &'static str,
+    /// How long the wait between retries is now, while the camera is not connected
+    backoff_secs: Option<u64>,
+    retry_in_secs: Option<u64>,
+    ping_ms: Option<u64>,
+    firmware: Option<FirmwareJson<'a>>,
+    device: Option<DeviceJson<'a>>,
+    mounts: Vec<MountJson<'a>>,
+}
+
+#[derive(Serialize)]
+struct FirmwareJson<'a> {
+    model: &'a str,
+    version: &'a str,
+    hardware: &'a str,
+    build: &'a str,
+    serial: &'a str,
+}
+
+#[derive(Serialize)]
+struct DeviceJson<'a> {
+    resolution: &'a str,
+    width: u32,
+    height: u32,
+    sd_card: Option<bool>,
+    disks: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct MountJson<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    camera: Option<&'a str>,
+    path: &'a str,
+    clients: usize,
+}
+
+/// Answers a request for `resource`, the part of the path after `/api/`.  `cameras` are the ones
+/// that `user` may see, and `user` is None if nobody has logged in.
+pub fn response(
+    method: &Method,
+    resource: &str,
+    cameras: &[Arc<HttpCamera>],
+    user: Option<&str>,
+    rtsp: &RtspServer,
+) -> Response<Cursor<Vec<u8>>> {
+    if let Err(response) = check_login(method, user) {
+        return response;
+    }
+    let find = |name: &str| cameras.iter().find(|camera| camera.name == name);
+    let parts: Vec<&str> = resource.trim_end_matches('/').split('/').collect();
+    match (method, parts.as_slice()) {
+        (Method::Get, ["cameras"]) => {
+            let list: Vec<_> = cameras
+                .iter()
+                .map(|camera| camera_value(camera, rtsp))
+                .collect();
+            json_response(200, &list)
+        }
+        (Method::Get, ["cameras", name]) => match find(*name) {
+            Some(camera) => json_response(200, &camera_value(camera, rtsp)),
+            None => error_response(404, "No such camera"),
+        },
+        (Method::Post, ["cameras", name, action]) => match find(*name) {
+            Some(camera) => action_response(camera, action),
+            None => error_response(404, "No such camera"),
+        },
+        (Method::Get, ["mounts"]) => {
+            let mounts: Vec<_> = cameras
+                .iter()
+                .flat_map(|camera| {
+                    camera.paths.iter().map(move |path| MountJson {
+                        camera: Some(camera.name.as_str()),
+                        path,
+                        clients: rtsp.client_count(path),
+                    })
+                })
+                .collect();
+            json_response(200, &mounts)
+        }
+        (_, ["cameras"]) | (_, ["cameras", _]) | (_, ["cameras", _, _]) | (_, ["mounts"]) => {
+            error_response(405, "Method not allowed")
+        }
+        _ => error_response(404, "Not found"),
+    }
+}
+
+/// Only GET requests may be made without logging in
+fn check_login(method: &Method, user: Option<&str>) -> Result<(), Response<Cursor<Vec<u8>>>> {
+    if *method == Method::Get || user.is_some() {
+        Ok(())
+    } else {
+        Err(error_response(403, "A user must log in to do this"))
+    }
+}
+
+fn action_response(camera: &HttpCamera, action: &str) -> Response<Cursor<Vec<u8>>> {
+    let result = match action {
+        "reconnect" => {
+            camera.jobs.reconnect();
+            Ok(())
+        }
+        "sync-time" => {
+            let config = camera.time_sync.clone();
+            camera
+                .jobs
+                .run(move |camera| crate::time_sync::set_time_now(camera, &config))
+        }
+        "reboot" => camera.jobs.run(|camera| camera.reboot()),
+        _ => return error_response(404, "No such action"),
+    };
+    match result {
+        Ok(()) => {
+            info!("{}: {} was asked for over the API", camera.name, action);
+            json_response(200, &json!({ "status": "ok" }))
+        }
+        Err(e) => {
+            warn!("{}: Could not {}: {}", camera.name, action, e);
+            error_response(503, &format!("Could not {}: {}", action, e))
+        }
+    }
+}
+
+fn camera_value(camera: &HttpCamera, rtsp: &RtspServer) -> serde_json::Value {
+    let status = camera.health.status();
+    let mounts = camera
+        .paths
+        .iter()
+        .map(|path| (path.as_str(), rtsp.client_count(path)))
+        .collect();
+    let ping_latency = camera.health.ping_latency();
+    let json = camera_json(&camera.name, &status, ping_latency, mounts, Instant::now());
+    serde_json::to_value(json).expect("Camera JSON should serialize")
+}
+
+fn camera_json<'a>(
+    name: &'a str,
+    status: &'a CameraStatus,
+    ping_latency: Option<std::time::Duration>,
+    mounts: Vec<(&'a str, usize)>,
+    now: Instant,
+) -> CameraJson<'a> {
+    CameraJson {
+        name,
+        state: status.state.name(),
+        backoff_secs: status.backoff.map(|(backoff, _)| backoff.as_secs()),
+        retry_in_secs: status.backoff.map(|(backoff, since)| {
+            backoff
+                .checked_sub(now.saturating_duration_since(since))
+                .unwrap_or_default()
+                .as_secs()
+        }),
+        ping_ms: ping_latency.map(|latency| latency.as_millis() as u64),
+        firmware: status.version.as_ref().map(|version| FirmwareJson {
+            model: &version.name,
+            version: &version.firmwareVersion,
+            hardware: &version.hardwareVersion,
+            build: &version.buildDay,
+            serial: &version.serialNumber,
+        }),
+        device: status.device_info.as_ref().map(|info| DeviceJson {
+            resolution: &info.resolution.name,
+            width: info.resolution.width,
+            height: info.resolution.height,
+            sd_card: info.sd_card.map(|sd_card| sd_card != 0),
+            disks: info.disk_num,
+        }),
+        mounts: mounts
+            .into_iter()
+            .map(|(path, clients)| MountJson {
+                camera: None,
+                path,
+                clients,
+            })
+            .collect(),
+    }
+}
+
+fn json_response<T: Serialize>(status: u16, value: &T) -> Response<Cursor<Vec<u8>>> {
+    let content_type =
+        Header::from_bytes("Content-Type", "application/json").expect("Header is valid");
+    let body = serde_json::to_vec(value).expect("API responses should serialize");
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+fn error_response(status: u16, message: &str) -> Response<Cursor<Vec<u8>>> {
+    json_response(status, &json!({ "error": message }))
+}
+
+#[test]
+fn test_camera_json() {
+    use crate::health::ConnectionState;
+    use neolink::bc::xml::{DeviceInfo, Resolution, VersionInfo};
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let status = CameraStatus {
+        state: ConnectionState::Retrying,
+        backoff: Some((Duration::from_secs(4), start)),
+        version: Some(VersionInfo {
+            name: "RLC-410".to_string(),
+            firmwareVersion: "v2.0.0.1".to_string(),
+            ..Default::default()
+        }),
+        device_info: Some(DeviceInfo {
+            resolution: Resolution {
+                name: "2560*1440".to_string(),
+                width: 2560,
+                height: 1440,
+            },
+            sd_card: Some(1),
+            disk_num: None,
+        }),
+        ..Default::default()
+    };
+    let json = camera_json(
+        "driveway",
+        &status,
+        None,
+        vec![("/driveway", 2)],
+        start + Duration::from_secs(1),
+    );
+    assert_eq!(
+        serde_json::to_value(json).unwrap(),
+        json!({
+            "name": "driveway",
+            "state": "retrying",
+            "backoff_secs": 4,
+            "retry_in_secs": 3,
+            "ping_ms": null,
+            "firmware": {
+                "model": "RLC-410",
+                "version": "v2.0.0.1",
+                "hardware": "",
+                "build": "",
+                "serial": "",
+            },
+            "device": {
+                "resolution": "2560*1440",
+                "width": 2560,
+                "height": 1440,
+                "sd_card": true,
+                "disks": null,
+            },
+            "mounts": [{ "path": "/driveway", "clients": 2 }],
+        })
+    );
+}
+
+#[test]
+fn test_check_login() {
+    let refused = check_login(&Method::Post, None).unwrap_err();
+    assert_eq!(refused.status_code(), tiny_http::StatusCode(403));
+    assert!(check_login(&Method::Post, Some("me")).is_ok());
+    assert!(check_login(&Method::Get, None).is_ok());
+}
@@ -5,35 +5,56 @@ use std::sync::{Arc, Mutex};
 pub(super) const MAGIC_HEADER: u32 = 0xabcdef0;
 
 pub const MSG_ID_LOGIN: u32 = 1;
+pub const MSG_ID_LOGOUT: u32 = 2;
 pub const MSG_ID_VIDEO: u32 = 3;
+pub const MSG_ID_VIDEO_STOP: u32 = 4;
+pub const MSG_ID_REPLAY: u32 = 5;
+pub const MSG_ID_REPLAY_STOP: u32 = 7;
+pub const MSG_ID_TALKABILITY: u32 = 10;
+pub const MSG_ID_TALKRESET: u32 = 11;
+pub const MSG_ID_FILE_INFO_LIST_OPEN: u32 = 14;
+pub const MSG_ID_FILE_INFO_LIST: u32 = 15;
+pub const MSG_ID_FILE_INFO_LIST_CLOSE: u32 = 16;
+pub const MSG_ID_PTZ_CONTROL: u32 = 18;
+pub const MSG_ID_PTZ_CONTROL_PRESET: u32 = 19;
+pub const MSG_ID_REBOOT: u32 = 23;
+pub const MSG_ID_MOTION_REQUEST: u32 = 31;
+pub const MSG_ID_MOTION: u32 = 33;
+pub const MSG_ID_UPGRADE: u32 = 67;
 pub const MSG_ID_VERSION: u32 = 80;
 pub const MSG_ID_PING: u32 = 93;
 pub const MSG_ID_GET_GENERAL: u32 = 104;
 pub const MSG_ID_SET_GENERAL: u32 = 105;
+pub const MSG_ID_SNAP: u32 = 109;
+pub const MSG_ID_GET_PTZ_PRESET: u32 = 190;
+pub const MSG_ID_GET_LED_STATUS: u32 = 208;
+pub const MSG_ID_SET_LED_STATUS: u32 = 209;
+pub const MSG_ID_TALKCONFIG: u32 = 201;
+pub const MSG_ID_TALK: u32 = 202;
 
 pub const EMPTY_LEGACY_PASSWORD: &str =
     "\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0";
 
-***REMOVED***[derive(Debug, PartialEq, Eq)]
+***REMOVED***[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Bc {
     pub meta: BcMeta,
     pub body: BcBody,
 }
 
-***REMOVED***[derive(Debug, PartialEq, Eq)]
+***REMOVED***[derive(Debug, Clone, PartialEq, Eq)]
 ***REMOVED***[allow(clippy::large_enum_variant)]
 pub enum BcBody {
     LegacyMsg(LegacyMsg),
     ModernMsg(ModernMsg),
 }
 
-***REMOVED***[derive(Debug, Default, PartialEq, Eq)]
+***REMOVED***[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct ModernMsg {
     pub extension: Option<Extension>,
     pub payload: Option<BcPayloads>,
 }
 
-***REMOVED***[derive(Debug, PartialEq, Eq)]
+***REMOVED***[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LegacyMsg {
     LoginMsg { username: String, password: String },
     UnknownMsg,
@@ -53,7 +74,7 @@ pub(super) struct BcHeader {
 
 /// The components of the Baichuan TLV header that are not
 /// descriptions of the Body (the application dictates these)
-***REMOVED***[derive(Debug, PartialEq, Eq)]
+***REMOVED***[derive(Debug, Clone, PartialEq, Eq)]
 pub struct BcMeta {
     pub msg_id: u32,
     pub channel_id: u8,
@@ -128,6 +149,16 @@ impl Bc {
             }),
         }
     }
+
+    pub fn new_from_ext_binary(meta: BcMeta, ext: Extension, binary: Vec<u8>) -> Bc {
+        Bc {
+            meta,
+            body: BcBody::ModernMsg(ModernMsg {
+                extension: Some(ext),
+                payload: Some(BcPayloads::Binary(binary)),
+            }),
+        }
+    }
 }
 
 impl BcContext {
@@ -9,7 +9,7 @@ use yaserde_derive::{YaDeserialize, YaSerialize};
 ***REMOVED***[cfg(test)]
 use indoc::indoc;
 
-***REMOVED***[derive(PartialEq, Eq, Debug, YaDeserialize)]
+***REMOVED***[derive(PartialEq, Eq, Debug, Clone, YaDeserialize)]
 ***REMOVED***[yaserde(flatten)]
 pub enum BcPayloads {
     ***REMOVED***[yaserde(rename = "body")]
@@ -25,7 +25,7 @@ impl Default for BcPayloads {
     }
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 ***REMOVED***[yaserde(rename = "body")]
 pub struct BcXml {
     ***REMOVED***[yaserde(rename = "Encryption")]
@@ -44,6 +44,24 @@ pub struct BcXml {
     pub system_general: Option<SystemGeneral>,
     ***REMOVED***[yaserde(rename = "Norm")]
     pub norm: Option<Norm>,
+    #[yaserde(rename = "PtzControl")]
+    pub ptz_control: Option<PtzControl>,
+    #[yaserde(rename = "PtzPreset")]
+    pub ptz_preset: Option<PtzPreset>,
+    #[yaserde(rename = "AlarmEventList")]
+    pub alarm_event_list: Option<AlarmEventList>,
+    #[yaserde(rename = "LedState")]
+    pub led_state: Option<LedState>,
+    #[yaserde(rename = "Snap")]
+    pub snap: Option<Snap>,
+    #[yaserde(rename = "TalkAbility")]
+    pub talk_ability: Option<TalkAbility>,
+    #[yaserde(rename = "TalkConfig")]
+    pub talk_config: Option<TalkConfig>,
+    #[yaserde(rename = "FileInfoList")]
+    pub file_info_list: Option<FileInfoList>,
+    #[yaserde(rename = "ConfigFileInfo")]
+    pub config_file_info: Option<ConfigFileInfo>,
 }
 
 impl BcXml {
@@ -64,7 +82,7 @@ impl Extension {
     }
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct Encryption {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
@@ -73,7 +91,7 @@ pub struct Encryption {
     pub nonce: String,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct LoginUser {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
@@ -84,7 +102,7 @@ pub struct LoginUser {
     pub user_ver: u32,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct LoginNet {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
@@ -104,12 +122,17 @@ impl Default for LoginNet {
     }
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct DeviceInfo {
     pub resolution: Resolution,
+    /// Nonzero if the camera has an SD card
+    #[yaserde(rename = "sdCard")]
+    pub sd_card: Option<u32>,
+    #[yaserde(rename = "diskNum")]
+    pub disk_num: Option<u32>,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct VersionInfo {
     pub name: String,
     pub serialNumber: String,
@@ -120,7 +143,7 @@ pub struct VersionInfo {
     pub detail: String,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct Resolution {
     ***REMOVED***[yaserde(rename = "resolutionName")]
     pub name: String,
@@ -128,19 +151,20 @@ pub struct Resolution {
     pub height: u32,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct Preview {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
 
     ***REMOVED***[yaserde(rename = "channelId")]
     pub channel_id: u8,
+    /// Chosen by us when starting a stream, to identify it when stopping it
     pub handle: u32,
     ***REMOVED***[yaserde(rename = "streamType")]
-    pub stream_type: String,
+    pub stream_type: Option<String>,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct Extension {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
@@ -153,7 +177,7 @@ pub struct Extension {
     pub channel_id: Option<u8>,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct SystemGeneral {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
@@ -177,13 +201,222 @@ pub struct SystemGeneral {
     pub device_name: Option<String>,
 }
 
-***REMOVED***[derive(PartialEq, Eq, Default, Debug, YaDeserialize, YaSerialize)]
+***REMOVED***[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
 pub struct Norm {
     ***REMOVED***[yaserde(attribute)]
     pub version: String,
     norm: String,
 }
 
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct PtzControl {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    pub speed: Option<u32>,
+    pub command: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct PtzPreset {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    #[yaserde(rename = "presetList")]
+    pub preset_list: Option<PresetList>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct PresetList {
+    pub preset: Vec<Preset>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct Preset {
+    pub id: u8,
+    pub name: Option<String>,
+    pub command: Option<String>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct AlarmEventList {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "AlarmEvent")]
+    pub alarm_events: Vec<AlarmEvent>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct AlarmEvent {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    /// "MD" while motion is detected, "none" otherwise
+    pub status: String,
+    /// Only sent by cameras with AI detection, e.g. "people", "vehicle" or "people,vehicle"
+    #[yaserde(rename = "AItype")]
+    pub ai_type: Option<String>,
+    pub recording: i32,
+    #[yaserde(rename = "timeStamp")]
+    pub timestamp: i32,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct LedState {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    /// Sent by the camera but must not be included when setting the state
+    #[yaserde(rename = "ledVersion")]
+    pub led_version: Option<u32>,
+    /// The IR lights: "auto", "open" or "close"
+    pub state: String,
+    /// The status LED: "open" or "close"
+    #[yaserde(rename = "lightState")]
+    pub light_state: String,
+}
+
+/// Requests a still image.  The reply carries the size of the image, which then follows as
+/// binary data in messages with the same msg_num.
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct Snap {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    #[yaserde(rename = "logicChannel")]
+    pub logic_channel: Option<u8>,
+    pub time: Option<u32>,
+    #[yaserde(rename = "fullFrame")]
+    pub full_frame: Option<u32>,
+    #[yaserde(rename = "streamType")]
+    pub stream_type: Option<String>,
+    #[yaserde(rename = "fileName")]
+    pub file_name: Option<String>,
+    #[yaserde(rename = "pictureSize")]
+    pub picture_size: Option<u32>,
+}
+
+/// The audio formats the camera's speaker accepts
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct TalkAbility {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "duplexList")]
+    pub duplex_list: Vec<DuplexList>,
+    #[yaserde(rename = "audioStreamModeList")]
+    pub audio_stream_mode_list: Vec<AudioStreamModeList>,
+    #[yaserde(rename = "audioConfigList")]
+    pub audio_config_list: Vec<AudioConfigList>,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct DuplexList {
+    pub duplex: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct AudioStreamModeList {
+    #[yaserde(rename = "audioStreamMode")]
+    pub audio_stream_mode: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct AudioConfigList {
+    #[yaserde(rename = "audioConfig")]
+    pub audio_config: AudioConfig,
+}
+
+/// Starts a talk session with one of the modes and formats from TalkAbility
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct TalkConfig {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    pub duplex: String,
+    #[yaserde(rename = "audioStreamMode")]
+    pub audio_stream_mode: String,
+    #[yaserde(rename = "audioConfig")]
+    pub audio_config: AudioConfig,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct AudioConfig {
+    pub priority: Option<u32>,
+    #[yaserde(rename = "audioType")]
+    pub audio_type: String,
+    #[yaserde(rename = "sampleRate")]
+    pub sample_rate: u32,
+    #[yaserde(rename = "samplePrecision")]
+    pub sample_precision: u16,
+    /// Samples per ADPCM block
+    #[yaserde(rename = "lengthPerEncoder")]
+    pub length_per_encoder: u32,
+    #[yaserde(rename = "soundTrack")]
+    pub sound_track: String,
+}
+
+/// Used both to search for recordings and to list the results.  A search returns a handle, which
+/// is then used to fetch the files found a batch at a time.
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct FileInfoList {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "FileInfo")]
+    pub file_info: Vec<FileInfo>,
+}
+
+/// A recording on the camera, or a search for them, or a request to play one back
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct FileInfo {
+    #[yaserde(rename = "channelId")]
+    pub channel_id: u8,
+    pub handle: Option<u32>,
+    #[yaserde(rename = "Name")]
+    pub name: Option<String>,
+    #[yaserde(rename = "streamType")]
+    pub stream_type: Option<String>,
+    #[yaserde(rename = "recordType")]
+    pub record_type: Option<String>,
+    #[yaserde(rename = "playSpeed")]
+    pub play_speed: Option<u32>,
+    pub size: Option<u64>,
+    #[yaserde(rename = "StartTime")]
+    pub start_time: Option<RecordTime>,
+    #[yaserde(rename = "EndTime")]
+    pub end_time: Option<RecordTime>,
+}
+
+/// A time in the camera's local time zone
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct RecordTime {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// Announces a firmware file, which is then sent to the camera as binary data
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct ConfigFileInfo {
+    #[yaserde(attribute)]
+    pub version: String,
+    #[yaserde(rename = "fileName")]
+    pub file_name: String,
+    #[yaserde(rename = "fileSize")]
+    pub file_size: u64,
+    /// 1 to keep the camera's settings through the upgrade
+    #[yaserde(rename = "updateParameter")]
+    pub update_parameter: Option<u8>,
+}
+
 pub fn xml_ver() -> String {
     "1.1".to_string()
 }
@@ -353,3 +586,242 @@ fn test_binary_deser() {
         _ => assert!(false),
     }
 }
+
+#[test]
+fn test_ptz_control_ser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <PtzControl version="1.1">
+        <channelId>0</channelId>
+        <speed>32</speed>
+        <command>right</command>
+        </PtzControl>
+        </body>"#
+    );
+
+    let b = BcXml {
+        ptz_control: Some(PtzControl {
+            version: "1.1".to_string(),
+            channel_id: 0,
+            speed: Some(32),
+            command: "right".to_string(),
+        }),
+        ..BcXml::default()
+    };
+
+    let b2 = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let b3 = BcXml::try_parse(b.serialize(vec![]).unwrap().as_slice()).unwrap();
+
+    assert_eq!(b, b2);
+    assert_eq!(b, b3);
+}
+
+#[test]
+fn test_ptz_preset_deser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <PtzPreset version="1.1">
+        <channelId>0</channelId>
+        <presetList>
+        <preset>
+        <id>1</id>
+        <name>gate</name>
+        </preset>
+        <preset>
+        <id>2</id>
+        <name>porch</name>
+        </preset>
+        </presetList>
+        </PtzPreset>
+        </body>"#
+    );
+
+    let b = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let presets = b.ptz_preset.unwrap().preset_list.unwrap().preset;
+
+    assert_eq!(presets.len(), 2);
+    assert_eq!(presets[0].id, 1);
+    assert_eq!(presets[0].name.as_deref(), Some("gate"));
+    assert_eq!(presets[1].id, 2);
+    assert_eq!(presets[1].name.as_deref(), Some("porch"));
+}
+
+#[test]
+fn test_alarm_event_list_deser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <AlarmEventList version="1.1">
+        <AlarmEvent version="1.1">
+        <channelId>0</channelId>
+        <status>MD</status>
+        <AItype>people</AItype>
+        <recording>0</recording>
+        <timeStamp>0</timeStamp>
+        </AlarmEvent>
+        </AlarmEventList>
+        </body>"#
+    );
+
+    let b = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let events = b.alarm_event_list.unwrap().alarm_events;
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].channel_id, 0);
+    assert_eq!(events[0].status, "MD");
+    assert_eq!(events[0].ai_type.as_deref(), Some("people"));
+}
+
+#[test]
+fn test_snap_reply_deser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <Snap version="1.1">
+        <channelId>0</channelId>
+        <fileName>01_20201011213035.jpg</fileName>
+        <pictureSize>193226</pictureSize>
+        </Snap>
+        </body>"#
+    );
+
+    let b = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let snap = b.snap.unwrap();
+
+    assert_eq!(snap.file_name.as_deref(), Some("01_20201011213035.jpg"));
+    assert_eq!(snap.picture_size, Some(193226));
+}
+
+#[test]
+fn test_talk_ability_deser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <TalkAbility version="1.1">
+        <duplexList>
+        <duplex>FDX</duplex>
+        </duplexList>
+        <audioStreamModeList>
+        <audioStreamMode>followVideoStream</audioStreamMode>
+        </audioStreamModeList>
+        <audioConfigList>
+        <audioConfig>
+        <priority>0</priority>
+        <audioType>adpcm</audioType>
+        <sampleRate>16000</sampleRate>
+        <samplePrecision>16</samplePrecision>
+        <lengthPerEncoder>1024</lengthPerEncoder>
+        <soundTrack>mono</soundTrack>
+        </audioConfig>
+        </audioConfigList>
+        </TalkAbility>
+        </body>"#
+    );
+
+    let b = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let ability = b.talk_ability.unwrap();
+
+    assert_eq!(ability.duplex_list[0].duplex, "FDX");
+    assert_eq!(
+        ability.audio_stream_mode_list[0].audio_stream_mode,
+        "followVideoStream"
+    );
+    let audio_config = &ability.audio_config_list[0].audio_config;
+    assert_eq!(audio_config.audio_type, "adpcm");
+    assert_eq!(audio_config.sample_rate, 16000);
+    assert_eq!(audio_config.length_per_encoder, 1024);
+}
+
+#[test]
+fn test_talk_config_ser() {
+    let b = BcXml {
+        talk_config: Some(TalkConfig {
+            version: xml_ver(),
+            channel_id: 0,
+            duplex: "FDX".to_string(),
+            audio_stream_mode: "followVideoStream".to_string(),
+            audio_config: AudioConfig {
+                priority: Some(0),
+                audio_type: "adpcm".to_string(),
+                sample_rate: 16000,
+                sample_precision: 16,
+                length_per_encoder: 1024,
+                sound_track: "mono".to_string(),
+            },
+        }),
+        ..BcXml::default()
+    };
+
+    let b2 = BcXml::try_parse(b.serialize(vec![]).unwrap().as_slice()).unwrap();
+    assert_eq!(b, b2);
+}
+
+#[test]
+fn test_file_info_list_deser() {
+    let sample = indoc!(
+        r#"
+        <?xml version="1.0" encoding="UTF-8" ?>
+        <body>
+        <FileInfoList version="1.1">
+        <FileInfo>
+        <channelId>0</channelId>
+        <Name>RecM01_20201011_213035_213542_6D28808_2A9E5C.mp4</Name>
+        <recordType>md</recordType>
+        <streamType>mainStream</streamType>
+        <size>44687708</size>
+        <StartTime>
+        <year>2020</year>
+        <month>10</month>
+        <day>11</day>
+        <hour>21</hour>
+        <minute>30</minute>
+        <second>35</second>
+        </StartTime>
+        <EndTime>
+        <year>2020</year>
+        <month>10</month>
+        <day>11</day>
+        <hour>21</hour>
+        <minute>35</minute>
+        <second>42</second>
+        </EndTime>
+        </FileInfo>
+        </FileInfoList>
+        </body>"#
+    );
+
+    let b = BcXml::try_parse(sample.as_bytes()).unwrap();
+    let files = b.file_info_list.unwrap().file_info;
+
+    assert_eq!(files.len(), 1);
+    assert_eq!(
+        files[0].name.as_deref(),
+        Some("RecM01_20201011_213035_213542_6D28808_2A9E5C.mp4")
+    );
+    assert_eq!(files[0].size, Some(44687708));
+    assert_eq!(files[0].start_time.as_ref().unwrap().minute, 30);
+    assert_eq!(files[0].end_time.as_ref().unwrap().second, 42);
+}
+
+#[test]
+fn test_config_file_info_ser() {
+    let b = BcXml {
+        config_file_info: Some(ConfigFileInfo {
+            version: xml_ver(),
+            file_name: "IPC_51516M5M.2356_21031904.RLC-410-5MP.OV05A10.5MP.REOLINK.pak".to_string(),
+            file_size: 19353542,
+            update_parameter: Some(1),
+        }),
+        ..BcXml::default()
+    };
+
+    let b2 = BcXml::try_parse(b.serialize(vec![]).unwrap().as_slice()).unwrap();
+    assert_eq!(b, b2);
+}
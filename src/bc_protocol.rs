@@ -1,29 +1,47 @@
+pub use self::adpcm::adpcm_to_pcm;
 use self::connection::BcConnection;
-use self::media_packet::{MediaDataKind, MediaDataSubscriber};
+pub use self::discover::{discover, DiscoveredCamera};
+pub use self::general::{DateFormat, GeneralSettings, TimeFormat};
+use self::media_packet::MediaDataSubscriber;
+pub use self::media_packet::{MediaData, MediaDataKind, StreamInfo};
+pub use self::motion::{MotionDataSubscriber, MotionEvent, MotionKind, MotionStatus};
+pub use self::ptz::{Direction, Zoom};
+pub use self::replay::Recording;
+pub use self::talk::Talk;
 use crate::bc;
 use crate::bc::{model::*, xml::*};
 use crate::gst::GstOutputs;
-use adpcm::adpcm_to_pcm;
 use err_derive::Error;
 use log::*;
 use std::convert::TryInto;
-use std::io::Write;
 use std::net::{SocketAddr, ToSocketAddrs};
 use std::sync::atomic::{AtomicU16, Ordering};
-use std::time::Duration;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use Md5Trunc::*;
 
 mod adpcm;
 mod connection;
+mod discover;
+mod firmware;
+mod general;
+mod ledstate;
 mod media_packet;
+mod motion;
+mod ptz;
+mod replay;
+mod snapshot;
+mod talk;
 mod time;
+mod udp;
 
 pub struct BcCamera {
     address: SocketAddr,
     channel_id: u8,
     connection: Option<BcConnection>,
-    logged_in: bool,
+    /// The credentials that the camera accepted, which it expects back when we log out
+    logged_in: Mutex<Option<LoginUser>>,
     message_num: AtomicU16,
 }
 
@@ -82,6 +100,82 @@ impl<'a> From<std::sync::mpsc::RecvTimeoutError> for Error {
     }
 }
 
+impl Error {
+    /// Whether the connection to the camera was lost, as opposed to the camera refusing or not
+    /// understanding one request.  Only a lost connection should end a camera session.
+    pub fn is_connection_lost(&self) -> bool {
+        matches!(
+            self,
+            Error::DroppedConnection(_)
+                | Error::TimeoutDisconnected
+                | Error::CommunicationError(_)
+                | Error::ConnectionError(connection::Error::CommunicationError(_))
+        )
+    }
+}
+
+/// Something that consumes the media packets of a stream, such as the RTSP server or a recording
+pub trait MediaSink {
+    fn write_media(&mut self, media: &MediaData) -> Result<()>;
+}
+
+impl MediaSink for GstOutputs {
+    fn write_media(&mut self, media: &MediaData) -> Result<()> {
+        match media.kind() {
+            MediaDataKind::VideoDataIframe | MediaDataKind::VideoDataPframe => {
+                self.set_format(media.media_format());
+                let timestamp = media.timestamp().map(|timestamp| timestamp as u32);
+                self.write_video(media.body(), timestamp)?;
+            }
+            MediaDataKind::AudioDataAac => {
+                self.set_format(media.media_format());
+                self.write_audio(media.body(), aac_duration(media.body()))?;
+            }
+            MediaDataKind::AudioDataAdpcm => {
+                self.set_format(media.media_format());
+                let pcm = adpcm_to_pcm(media.body())?;
+                // The PCM is 16-bit mono at 8kHz
+                let duration = Duration::from_micros(pcm.len() as u64 / 2 * 1_000_000 / 8000);
+                self.write_audio(&pcm, Some(duration))?;
+            }
+            MediaDataKind::InfoData => {
+                if let Some(info) = media.stream_info() {
+                    self.set_stream_info(info);
+                }
+            }
+            _ => {}
+        };
+        Ok(())
+    }
+}
+
+/// Works out how long the ADTS frames of AAC audio in `data` last, from their sample rates
+fn aac_duration(data: &[u8]) -> Option<Duration> {
+    const SAMPLE_RATES: [u64; 13] = [
+        96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+    ];
+    let mut duration = Duration::from_secs(0);
+    let mut frame = data;
+    while !frame.is_empty() {
+        if frame.len() < 7 || frame[0] != 0xff || frame[1] & 0xf0 != 0xf0 {
+            return None;
+        }
+        let sample_rate = *SAMPLE_RATES.get((frame[2] as usize >> 2) & 0x0f)?;
+        // Each raw data block in the frame is 1024 samples
+        let samples = 1024 * ((frame[6] & 0x03) as u64 + 1);
+        duration += Duration::from_micros(samples * 1_000_000 / sample_rate);
+
+        let length = ((frame[3] as usize & 0x03) << 11)
+            | ((frame[4] as usize) << 3)
+            | (frame[5] as usize >> 5);
+        if length < 7 || length > frame.len() {
+            return None;
+        }
+        frame = &frame[length..];
+    }
+    Some(duration)
+}
+
 impl Drop for BcCamera {
     fn drop(&mut self) {
         self.disconnect();
@@ -114,13 +208,33 @@ impl BcCamera {
                 connection: Some(conn),
                 message_num: AtomicU16::new(0),
                 channel_id,
-                logged_in: false,
+                logged_in: Mutex::new(None),
             });
         }
 
         Err(Error::Timeout)
     }
 
+    /// Connects over the Baichuan UDP transport to the camera with the given UID.  The camera
+    /// must be on the local network, as it is found by broadcasting for it.
+    pub fn new_with_uid(uid: &str, channel_id: u8) -> Result<Self> {
+        let conn = BcConnection::new_udp(uid, RX_TIMEOUT)?;
+        let address = conn.peer_addr()?;
+        debug!("Success: {} at {}", uid, address);
+
+        Ok(Self {
+            address,
+            connection: Some(conn),
+            message_num: AtomicU16::new(0),
+            channel_id,
+            logged_in: Mutex::new(None),
+        })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
     pub fn new_message_num(&self) -> u16 {
         self.message_num.fetch_add(1, Ordering::Relaxed)
     }
@@ -132,6 +246,24 @@ impl BcCamera {
         self.connection = None;
     }
 
+    /// Logs out and tears down the connection, for use while other threads are still using the
+    /// camera.  Anything waiting on the camera fails with a dropped connection.
+    pub fn shutdown(&self) {
+        if let Err(err) = self.logout() {
+            debug!("Could not log out before shutting down, ignoring: {}", err);
+        }
+        if let Some(connection) = &self.connection {
+            connection.shutdown();
+        }
+    }
+
+    /// Whether the connection was lost because the camera sent something that could not be parsed
+    pub fn deserialization_failed(&self) -> bool {
+        self.connection
+            .as_ref()
+            .map_or(false, BcConnection::parse_failed)
+    }
+
     pub fn login(&mut self, username: &str, password: Option<&str>) -> Result<DeviceInfo> {
         let connection = self
             .connection
@@ -205,6 +337,13 @@ impl BcCamera {
         let md5_username = md5_string(&concat_username, Truncate);
         let md5_password = md5_string(&concat_password, Truncate);
 
+        let login_user = LoginUser {
+            version: xml_ver(),
+            user_name: md5_username,
+            password: md5_password,
+            user_ver: 1,
+        };
+
         let modern_login = Bc::new_from_xml(
             BcMeta {
                 msg_id: MSG_ID_LOGIN,
@@ -215,12 +354,7 @@ impl BcCamera {
                 class: 0x6414,
             },
             BcXml {
-                login_user: Some(LoginUser {
-                    version: xml_ver(),
-                    user_name: md5_username,
-                    password: md5_password,
-                    user_ver: 1,
-                }),
+                login_user: Some(login_user.clone()),
                 login_net: Some(LoginNet::default()),
                 ..Default::default()
             },
@@ -240,7 +374,7 @@ impl BcCamera {
                 ..
             }) => {
                 // Login succeeded!
-                self.logged_in = true;
+                *self.logged_in.lock().unwrap() = Some(login_user);
                 device_info = info;
             }
             BcBody::ModernMsg(ModernMsg {
@@ -266,11 +400,45 @@ impl BcCamera {
         Ok(device_info)
     }
 
-    pub fn logout(&mut self) -> Result<()> {
-        if self.logged_in {
-            // TODO: Send message ID 2
+    /// Ends the session so that the camera does not hold it open until it times out.  Does
+    /// nothing if we are not logged in.
+    pub fn logout(&self) -> Result<()> {
+        let login_user = match self.logged_in.lock().unwrap().take() {
+            Some(login_user) => login_user,
+            None => return Ok(()),
+        };
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to log out");
+        let msg_num = self.new_message_num();
+        let sub_logout = connection.subscribe_to_reply(MSG_ID_LOGOUT, msg_num)?;
+
+        let logout = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_LOGOUT,
+                channel_id: self.channel_id,
+                msg_num,
+                stream_type: 0,
+                response_code: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                login_user: Some(login_user),
+                ..Default::default()
+            },
+        );
+
+        sub_logout.send(logout)?;
+        let msg = sub_logout.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the logout",
+            });
         }
-        self.logged_in = false;
+
         Ok(())
     }
 
@@ -279,13 +447,14 @@ impl BcCamera {
             .connection
             .as_ref()
             .expect("Must be connected to get version info");
-        let sub_version = connection.subscribe(MSG_ID_VERSION)?;
+        let msg_num = self.new_message_num();
+        let sub_version = connection.subscribe_to_reply(MSG_ID_VERSION, msg_num)?;
 
         let version = Bc {
             meta: BcMeta {
                 msg_id: MSG_ID_VERSION,
                 channel_id: self.channel_id,
-                msg_num: self.new_message_num(),
+                msg_num,
                 stream_type: 0,
                 response_code: 0,
                 class: 0x6414, // IDK why
@@ -321,15 +490,17 @@ impl BcCamera {
         Ok(version_info)
     }
 
-    pub fn ping(&self) -> Result<()> {
+    /// Checks that the camera is still responding, and returns how long it took to reply
+    pub fn ping(&self) -> Result<Duration> {
         let connection = self.connection.as_ref().expect("Must be connected to ping");
-        let sub_ping = connection.subscribe(MSG_ID_PING)?;
+        let msg_num = self.new_message_num();
+        let sub_ping = connection.subscribe_to_reply(MSG_ID_PING, msg_num)?;
 
         let ping = Bc {
             meta: BcMeta {
                 msg_id: MSG_ID_PING,
                 channel_id: self.channel_id,
-                msg_num: self.new_message_num(),
+                msg_num,
                 stream_type: 0,
                 response_code: 0,
                 class: 0x6414,
@@ -339,25 +510,56 @@ impl BcCamera {
             }),
         };
 
+        let start = Instant::now();
         sub_ping.send(ping)?;
 
         sub_ping.rx.recv_timeout(RX_TIMEOUT)?;
 
+        Ok(start.elapsed())
+    }
+
+    pub fn reboot(&self) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to reboot");
+        let msg_num = self.new_message_num();
+        let sub_reboot = connection.subscribe_to_reply(MSG_ID_REBOOT, msg_num)?;
+
+        let reboot = Bc::new_from_meta(BcMeta {
+            msg_id: MSG_ID_REBOOT,
+            channel_id: self.channel_id,
+            msg_num,
+            stream_type: 0,
+            response_code: 0,
+            class: 0x6414,
+        });
+
+        sub_reboot.send(reboot)?;
+        let msg = sub_reboot.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the reboot command (is the user an admin?)",
+            });
+        }
+
         Ok(())
     }
 
-    pub fn start_video(&self, data_outs: &mut GstOutputs, stream_name: &str) -> Result<Never> {
+    pub fn start_video(&self, data_outs: &mut dyn MediaSink, stream_name: &str) -> Result<Never> {
         let connection = self
             .connection
             .as_ref()
             .expect("Must be connected to start video");
-        let sub_video = connection.subscribe(MSG_ID_VIDEO)?;
 
         let stream_num = match stream_name {
             "mainStream" => 0,
             "subStream" => 1,
             _ => 0,
         };
+        let sub_video = connection.subscribe_to_stream(MSG_ID_VIDEO, stream_num)?;
 
         let start_video = Bc::new_from_xml(
             BcMeta {
@@ -372,8 +574,10 @@ impl BcCamera {
                 preview: Some(Preview {
                     version: xml_ver(),
                     channel_id: self.channel_id,
-                    handle: 0,
-                    stream_type: stream_name.to_string(),
+                    // Both streams can run on one connection, so each has its own handle, which
+                    // stop_video() uses to stop only that one
+                    handle: stream_num as u32,
+                    stream_type: Some(stream_name.to_string()),
                 }),
                 ..Default::default()
             },
@@ -383,31 +587,69 @@ impl BcCamera {
 
         let mut media_sub = MediaDataSubscriber::from_bc_sub(&sub_video);
 
-        loop {
-            let binary_data = media_sub.next_media_packet()?;
-            // We now have a complete interesting packet. Send it to gst.
-            // Process the packet
-            match binary_data.kind() {
-                MediaDataKind::VideoDataIframe | MediaDataKind::VideoDataPframe => {
-                    let media_format = binary_data.media_format();
-                    data_outs.set_format(media_format);
-                    data_outs.vidsrc.write_all(binary_data.body())?;
-                }
-                MediaDataKind::AudioDataAac => {
-                    let media_format = binary_data.media_format();
-                    data_outs.set_format(media_format);
-                    data_outs.audsrc.write_all(binary_data.body())?;
-                }
-                MediaDataKind::AudioDataAdpcm => {
-                    let media_format = binary_data.media_format();
-                    data_outs.set_format(media_format);
-                    let adpcm = binary_data.body();
-                    let pcm = adpcm_to_pcm(adpcm)?;
-                    data_outs.audsrc.write_all(&pcm)?;
+        let result = (|| -> Result<Never> {
+            loop {
+                let binary_data = media_sub.next_media_packet()?;
+                // We now have a complete interesting packet. Send it on.
+                data_outs.write_media(&binary_data)?;
+            }
+        })();
+
+        // If we stopped for our own reasons, such as a failure to write to GStreamer, ask the
+        // camera to stop sending.  There is nobody to ask if the connection itself failed.
+        match result {
+            Err(Error::DroppedConnection(_))
+            | Err(Error::TimeoutDisconnected)
+            | Err(Error::Timeout)
+            | Err(Error::CommunicationError(_)) => {}
+            _ => {
+                if let Err(err) = self.stop_video(stream_num, stream_name) {
+                    debug!("Could not stop {}, ignoring: {}", stream_name, err);
                 }
-                _ => {}
-            };
+            }
+        }
+        result
+    }
+
+    fn stop_video(&self, stream_num: u8, stream_name: &str) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to stop video");
+        let msg_num = self.new_message_num();
+        let sub_stop = connection.subscribe_to_reply(MSG_ID_VIDEO_STOP, msg_num)?;
+
+        let stop_video = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_VIDEO_STOP,
+                channel_id: self.channel_id,
+                msg_num,
+                stream_type: stream_num,
+                response_code: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                preview: Some(Preview {
+                    version: xml_ver(),
+                    channel_id: self.channel_id,
+                    handle: stream_num as u32,
+                    stream_type: Some(stream_name.to_string()),
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_stop.send(stop_video)?;
+        let msg = sub_stop.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the request to stop video",
+            });
         }
+
+        Ok(())
     }
 }
 
@@ -451,3 +693,24 @@ fn test_md5_string() {
         "21232F297A57A5A743894A0E4A801FC\0"
     );
 }
+
+#[test]
+fn test_aac_duration() {
+    // Two 16kHz frames of 9 bytes each
+    let frame = [0xff, 0xf1, 0x60, 0x40, 0x01, 0x3f, 0xfc, 0x00, 0x00];
+    let data = [&frame[..], &frame[..]].concat();
+    assert_eq!(aac_duration(&data), Some(Duration::from_millis(128)));
+    assert_eq!(aac_duration(&data[..12]), None);
+    assert_eq!(aac_duration(&[0x00; 9]), None);
+}
+
+#[test]
+fn test_is_connection_lost() {
+    let reset = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    assert!(Error::CommunicationError(reset).is_connection_lost());
+    assert!(Error::TimeoutDisconnected.is_connection_lost());
+    assert!(!Error::Timeout.is_connection_lost());
+    assert!(!Error::Other("Camera did not accept the request").is_connection_lost());
+    let simultaneous = connection::Error::SimultaneousSubscription { msg_id: 0 };
+    assert!(!Error::ConnectionError(simultaneous).is_connection_lost());
+}
@@ -242,3 +242,124 @@ pub fn adpcm_to_pcm(bytes: &[u8]) -> Result<Vec<u8>, Error> {
     }
     Ok(result)
 }
+
+/// Encodes PCM into DVI4 ADPCM blocks in the format the camera sends, so that the camera can
+/// decode them the same way adpcm_to_pcm does.  The predictor state carries over from one block
+/// to the next, as it would for a continuous stream.
+pub struct AdpcmEncoder {
+    last_output: i32,
+    step_index: i32,
+}
+
+impl Default for AdpcmEncoder {
+    fn default() -> Self {
+        Self {
+            last_output: 0,
+            step_index: 0,
+        }
+    }
+}
+
+impl AdpcmEncoder {
+    /// Encodes signed 16-bit mono samples into one block.  Two samples fit in each byte, so an
+    /// odd sample at the end is dropped.
+    pub fn encode_block(&mut self, samples: &[i16]) -> Vec<u8> {
+        let context = AdpcmSetup::new_ima();
+        let samples = &samples[..samples.len() - samples.len() % 2];
+
+        // Block header as described in adpcm_to_pcm: frame type, half the block size (which
+        // counts the predictor state but not the frame type or itself), then the predictor state
+        let block_size = 4 + samples.len() / 2;
+        let mut result = Vec::with_capacity(4 + block_size);
+        result.extend_from_slice(&[0x00, 0x01]);
+        result.extend_from_slice(&((block_size / 2) as u16).to_le_bytes());
+        result.extend_from_slice(&(self.last_output as i16).to_le_bytes());
+        result.extend_from_slice(&(self.step_index as u16).to_le_bytes());
+
+        for pair in samples.chunks(2) {
+            let high = self.encode_sample(&context, pair[0]);
+            let low = self.encode_sample(&context, pair[1]);
+            result.push((high << 4) | low);
+        }
+        result
+    }
+
+    /// Picks the nibble that takes the decoder closest to `sample`, and updates the predictor
+    /// exactly as the decoder will, so that the two stay in step.
+    fn encode_sample(&mut self, context: &AdpcmSetup, sample: i16) -> u8 {
+        let step = context.steps[self.step_index as usize];
+        let mut delta = sample as i32 - self.last_output;
+
+        let mut nibble = 0u8;
+        if delta < 0 {
+            nibble = 0b1000;
+            delta = -delta;
+        }
+        let mut diff = step >> 3;
+        if delta >= step as i32 {
+            nibble |= 0b0100;
+            delta -= step as i32;
+            diff += step;
+        }
+        if delta >= (step >> 1) as i32 {
+            nibble |= 0b0010;
+            delta -= (step >> 1) as i32;
+            diff += step >> 1;
+        }
+        if delta >= (step >> 2) as i32 {
+            nibble |= 0b0001;
+            diff += step >> 2;
+        }
+
+        let raw_sample = if nibble & 0b1000 == 0b1000 {
+            self.last_output - diff as i32
+        } else {
+            self.last_output + diff as i32
+        };
+        self.last_output = match raw_sample {
+            value if value > context.max_sample_size - 1 => context.max_sample_size - 1,
+            value if value < -context.max_sample_size => -context.max_sample_size,
+            value => value,
+        };
+        self.step_index = match self.step_index + context.changes[nibble as usize] {
+            n if n < 0 => 0,
+            n if n > context.max_step_index as i32 => context.max_step_index as i32,
+            n => n,
+        };
+
+        nibble
+    }
+}
+
+#[test]
+fn test_adpcm_roundtrip() {
+    // A 440Hz tone at 16kHz, in two blocks so that the predictor state has to carry over
+    let samples: Vec<i16> = (0..2048)
+        .map(|i| {
+            let t = i as f64 / 16000.0;
+            (8000.0 * (2.0 * std::f64::consts::PI * 440.0 * t).sin()) as i16
+        })
+        .collect();
+
+    let mut encoder = AdpcmEncoder::default();
+    let mut adpcm = encoder.encode_block(&samples[..1024]);
+    adpcm.extend(encoder.encode_block(&samples[1024..]));
+    assert_eq!(adpcm.len(), 2 * (8 + 512));
+
+    let pcm = adpcm_to_pcm(&adpcm).unwrap();
+    let decoded: Vec<i16> = pcm
+        .chunks(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    assert_eq!(decoded.len(), samples.len());
+
+    // ADPCM is lossy, but once the step size has adapted it should follow the tone closely
+    for (original, decoded) in samples.iter().zip(&decoded).skip(64) {
+        assert!(
+            (*original as i32 - *decoded as i32).abs() < 1000,
+            "{} decoded as {}",
+            original,
+            decoded
+        );
+    }
+}
@@ -1,26 +1,33 @@
+use super::udp::UdpStream;
 use crate::bc;
 use crate::bc::model::*;
 use err_derive::Error;
 use log::*;
 use socket2::{Domain, Socket, Type};
-use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::error::Error as StdErr; // Just need the traits
+use std::io::{Read, Write};
 use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::time::Duration;
 
 /// A shareable connection to a camera.  Handles serialization of messages.  To send/receive, call
-/// .subscribe() with a message ID.  You can use the BcSubscription to send or receive only
-/// messages with that ID; each incoming message is routed to its appropriate subscriber.
+/// one of the .subscribe*() functions with a message ID.  You can use the BcSubscription to send
+/// or receive only messages with that ID; each incoming message is routed to every subscriber
+/// whose filter matches it.
 ///
-/// There can be only one subscriber per kind of message at a time.
+/// Replies are routed by msg_num, so any number of requests with the same ID can be in flight
+/// at once.  Video is routed by stream_type, so each stream can have its own subscriber.
 pub struct BcConnection {
-    connection: Arc<Mutex<TcpStream>>,
-    subscribers: Arc<Mutex<BTreeMap<u32, Sender<Bc>>>>,
+    connection: Arc<Mutex<Transport>>,
+    subscribers: Arc<Mutex<Subscribers>>,
+    next_subscriber_id: AtomicU64,
     rx_thread: Option<JoinHandle<()>>,
+    /// Set when the camera sent something that could not be parsed, which ends the connection
+    parse_failed: Arc<AtomicBool>,
     // Arc<Mutex<EncryptionProtocol>> because it is shared between context
     // and connection for deserialisation and serialistion respectivly
     encryption_protocol: Arc<Mutex<EncryptionProtocol>>,
@@ -29,9 +36,133 @@ pub struct BcConnection {
 pub struct BcSubscription<'a> {
     pub rx: Receiver<Bc>,
     msg_id: u32,
+    filter: BcFilter,
+    subscriber_id: u64,
     conn: &'a BcConnection,
 }
 
+/// Which of the messages with a subscription's msg_id are delivered to it
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+enum BcFilter {
+    All,
+    MsgNum(u16),
+    StreamType(u8),
+}
+
+struct Subscriber {
+    id: u64,
+    filter: BcFilter,
+    tx: Sender<Bc>,
+}
+
+type Subscribers = BTreeMap<u32, Vec<Subscriber>>;
+
+/// The socket underneath a connection.  Either way it carries the same stream of Baichuan
+/// messages; over UDP, UdpStream provides the ordering and retransmission that TCP would.
+enum Transport {
+    Tcp(TcpStream),
+    Udp(UdpStream),
+}
+
+impl Transport {
+    fn try_clone(&self) -> std::io::Result<Transport> {
+        Ok(match self {
+            Transport::Tcp(tcp) => Transport::Tcp(tcp.try_clone()?),
+            Transport::Udp(udp) => Transport::Udp(udp.clone()),
+        })
+    }
+
+    fn shutdown(&self) {
+        match self {
+            Transport::Tcp(tcp) => {
+                let _ = tcp.shutdown(Shutdown::Both);
+            }
+            Transport::Udp(udp) => udp.shutdown(),
+        }
+    }
+
+    fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Transport::Tcp(tcp) => tcp.peer_addr(),
+            Transport::Udp(udp) => Ok(udp.peer_addr()),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(tcp) => tcp.read(buf),
+            Transport::Udp(udp) => udp.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Transport::Tcp(tcp) => tcp.write(buf),
+            Transport::Udp(udp) => udp.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Transport::Tcp(tcp) => tcp.flush(),
+            Transport::Udp(udp) => udp.flush(),
+        }
+    }
+}
+
+impl BcFilter {
+    fn matches(&self, meta: &BcMeta) -> bool {
+        match *self {
+            BcFilter::All => true,
+            BcFilter::MsgNum(msg_num) => meta.msg_num == msg_num,
+            BcFilter::StreamType(stream_type) => meta.stream_type == stream_type,
+        }
+    }
+}
+
+/// Sends a message to every subscriber to its msg_id whose filter matches it
+fn route(subscribers: &mut Subscribers, response: Bc) {
+    let msg_id = response.meta.msg_id;
+    let subs = match subscribers.get_mut(&msg_id) {
+        Some(subs) if subs.iter().any(|sub| sub.filter.matches(&response.meta)) => subs,
+        _ => {
+            debug!("Ignoring uninteresting message ID {}", msg_id);
+            trace!("Contents: {:?}", response);
+            return;
+        }
+    };
+
+    let mut matching: Vec<&Subscriber> = subs
+        .iter()
+        .filter(|sub| sub.filter.matches(&response.meta))
+        .collect();
+    let last = matching
+        .pop()
+        .expect("There is at least one matching subscriber");
+
+    // Only clone the message when it is fanned out to more than one subscriber; video data is
+    // large and almost always has exactly one.
+    let mut dropped = vec![];
+    for sub in matching {
+        if sub.tx.send(response.clone()).is_err() {
+            dropped.push(sub.id);
+        }
+    }
+    if last.tx.send(response).is_err() {
+        dropped.push(last.id);
+    }
+
+    if !dropped.is_empty() {
+        // Exceedingly unlikely, unless you mishandle the subscription object
+        warn!("Subscriber to ID {} dropped their channel", msg_id);
+        subs.retain(|sub| !dropped.contains(&sub.id));
+    }
+}
+
 type Result<T> = std::result::Result<T, Error>;
 
 ***REMOVED***[derive(Debug, Error)]
@@ -52,18 +183,31 @@ pub enum Error {
 impl BcConnection {
     pub fn new(addr: SocketAddr, timeout: Duration) -> Result<BcConnection> {
         let tcp_conn = connect_to(addr, timeout)?;
-        let subscribers: Arc<Mutex<BTreeMap<u32, Sender<Bc>>>> = Default::default();
+        BcConnection::from_transport(Transport::Tcp(tcp_conn))
+    }
+
+    /// Finds the camera with the given UID on the local network and connects to it over the
+    /// Baichuan UDP transport
+    pub fn new_udp(uid: &str, timeout: Duration) -> Result<BcConnection> {
+        let udp_conn = UdpStream::connect_uid(uid, timeout)?;
+        BcConnection::from_transport(Transport::Udp(udp_conn))
+    }
+
+    fn from_transport(transport: Transport) -> Result<BcConnection> {
+        let subscribers: Arc<Mutex<Subscribers>> = Default::default();
 
         let mut subs = subscribers.clone();
-        let conn = tcp_conn.try_clone()?;
+        let mut conn = transport.try_clone()?;
 
         let encryption_protocol = Arc::new(Mutex::new(EncryptionProtocol::Unencrypted));
         let connections_encryption_protocol = encryption_protocol.clone();
+        let parse_failed = Arc::new(AtomicBool::new(false));
+        let rx_parse_failed = parse_failed.clone();
         let rx_thread = std::thread::spawn(move || {
             let mut context = BcContext::new(connections_encryption_protocol);
             let mut result;
             while {
-                result = BcConnection::poll(&mut context, &conn, &mut subs);
+                result = BcConnection::poll(&mut context, &mut conn, &mut subs, &rx_parse_failed);
                 result.is_ok()
             } {}
             let e = result.unwrap_err();
@@ -73,62 +217,103 @@ impl BcConnection {
                 error!("caused by: {}", e);
                 cause = e.source();
             }
+            // Hang up on every subscriber so that anyone blocked on a reply or on the video
+            // stream learns that the connection is gone
+            subs.lock().unwrap().clear();
         });
 
         Ok(BcConnection {
-            connection: Arc::new(Mutex::new(tcp_conn)),
+            connection: Arc::new(Mutex::new(transport)),
             subscribers,
+            next_subscriber_id: AtomicU64::new(0),
             rx_thread: Some(rx_thread),
+            parse_failed,
             encryption_protocol,
         })
     }
 
+    /// Subscribes to every message with the given ID, such as alarm messages that the camera
+    /// sends on its own.  Any number of these subscriptions may exist at once.
     pub fn subscribe(&self, msg_id: u32) -> Result<BcSubscription> {
+        self.add_subscriber(msg_id, BcFilter::All)
+    }
+
+    /// Subscribes to the replies to the request with the given msg_num.  Only one subscriber may
+    /// wait on each request.
+    pub fn subscribe_to_reply(&self, msg_id: u32, msg_num: u16) -> Result<BcSubscription> {
+        self.add_subscriber(msg_id, BcFilter::MsgNum(msg_num))
+    }
+
+    /// Subscribes to messages for one stream, such as the video data of mainStream.  Only one
+    /// subscriber may receive each stream.
+    pub fn subscribe_to_stream(&self, msg_id: u32, stream_type: u8) -> Result<BcSubscription> {
+        self.add_subscriber(msg_id, BcFilter::StreamType(stream_type))
+    }
+
+    fn add_subscriber(&self, msg_id: u32, filter: BcFilter) -> Result<BcSubscription> {
         let (tx, rx) = channel();
-        match self.subscribers.lock().unwrap().entry(msg_id) {
-            Entry::Vacant(vac_entry) => vac_entry.insert(tx),
-            Entry::Occupied(_) => return Err(Error::SimultaneousSubscription { msg_id }),
-        };
+        let subscriber_id = self.next_subscriber_id.fetch_add(1, Ordering::Relaxed);
+
+        let mut locked_subs = self.subscribers.lock().unwrap();
+        let subs = locked_subs.entry(msg_id).or_default();
+        if filter != BcFilter::All && subs.iter().any(|sub| sub.filter == filter) {
+            return Err(Error::SimultaneousSubscription { msg_id });
+        }
+        subs.push(Subscriber {
+            id: subscriber_id,
+            filter,
+            tx,
+        });
+
         Ok(BcSubscription {
             rx,
             conn: self,
             msg_id,
+            filter,
+            subscriber_id,
         })
     }
 
     fn poll(
         context: &mut BcContext,
-        connection: &TcpStream,
-        subscribers: &mut Arc<Mutex<BTreeMap<u32, Sender<Bc>>>>,
+        connection: &mut Transport,
+        subscribers: &mut Arc<Mutex<Subscribers>>,
+        parse_failed: &AtomicBool,
     ) -> Result<()> {
         // Don't hold the lock during deserialization so we don't poison the subscribers mutex if
         // something goes wrong
 
         let response = Bc::deserialize(context, connection).map_err(|err| {
+            // Set before the subscribers find out, so that they can tell why
+            if let bc::de::Error::NomError(_) = err {
+                parse_failed.store(true, Ordering::Relaxed);
+            }
             // If the connection hangs up, hang up on all subscribers
             subscribers.lock().unwrap().clear();
             err
         })?;
-        let msg_id = response.meta.msg_id;
-
-        let mut locked_subs = subscribers.lock().unwrap();
-        match locked_subs.entry(msg_id) {
-            Entry::Occupied(mut occ) => {
-                if occ.get_mut().send(response).is_err() {
-                    // Exceedingly unlikely, unless you mishandle the subscription object
-                    warn!("Subscriber to ID {} dropped their channel", msg_id);
-                    occ.remove();
-                }
-            }
-            Entry::Vacant(_) => {
-                debug!("Ignoring uninteresting message ID {}", msg_id);
-                trace!("Contents: {:?}", response);
-            }
-        }
 
+        route(&mut subscribers.lock().unwrap(), response);
         Ok(())
     }
 
+    /// Closes the socket.  The receive thread stops and every subscriber sees a dropped
+    /// connection, which wakes up any other threads that are using this connection.
+    pub fn shutdown(&self) {
+        self.connection.lock().unwrap().shutdown();
+    }
+
+    /// The address of the camera, which for UDP connections is only known once it answers
+    pub fn peer_addr(&self) -> Result<SocketAddr> {
+        Ok(self.connection.lock().unwrap().peer_addr()?)
+    }
+
+    /// Whether the connection ended because the camera sent a message that could not be parsed,
+    /// rather than because it hung up
+    pub fn parse_failed(&self) -> bool {
+        self.parse_failed.load(Ordering::Relaxed)
+    }
+
     pub fn set_encrypted(&self, value: EncryptionProtocol) {
         *(self.encryption_protocol.lock().unwrap()) = value;
     }
@@ -141,7 +326,7 @@ impl BcConnection {
 impl Drop for BcConnection {
     fn drop(&mut self) {
         debug!("Shutting down BcConnection...");
-        let _ = self.connection.lock().unwrap().shutdown(Shutdown::Both);
+        self.shutdown();
         match self
             .rx_thread
             .take()
@@ -161,9 +346,10 @@ impl Drop for BcConnection {
 impl<'a> BcSubscription<'a> {
     pub fn send(&self, bc: Bc) -> Result<()> {
         assert!(bc.meta.msg_id == self.msg_id);
+        assert!(self.filter == BcFilter::All || self.filter.matches(&bc.meta));
 
         bc.serialize(
-            &*self.conn.connection.lock().unwrap(),
+            &mut *self.conn.connection.lock().unwrap(),
             &self.conn.get_encrypted(),
         )?;
         Ok(())
@@ -173,7 +359,13 @@ impl<'a> BcSubscription<'a> {
 /// Makes it difficult to avoid unsubscribing when you're finished
 impl<'a> Drop for BcSubscription<'a> {
     fn drop(&mut self) {
-        self.conn.subscribers.lock().unwrap().remove(&self.msg_id);
+        let mut locked_subs = self.conn.subscribers.lock().unwrap();
+        if let Some(subs) = locked_subs.get_mut(&self.msg_id) {
+            subs.retain(|sub| sub.id != self.subscriber_id);
+            if subs.is_empty() {
+                locked_subs.remove(&self.msg_id);
+            }
+        }
     }
 }
 
@@ -195,3 +387,83 @@ fn connect_to(addr: SocketAddr, timeout: Duration) -> Result<TcpStream> {
 
     Ok(socket.into_tcp_stream())
 }
+
+#[cfg(test)]
+fn test_message(msg_id: u32, msg_num: u16, stream_type: u8) -> Bc {
+    Bc::new_from_meta(BcMeta {
+        msg_id,
+        channel_id: 0,
+        msg_num,
+        stream_type,
+        response_code: 200,
+        class: 0x0000,
+    })
+}
+
+#[test]
+fn test_filter_matches() {
+    let meta = test_message(MSG_ID_VIDEO, 7, 1).meta;
+    assert!(BcFilter::All.matches(&meta));
+    assert!(BcFilter::MsgNum(7).matches(&meta));
+    assert!(!BcFilter::MsgNum(8).matches(&meta));
+    assert!(BcFilter::StreamType(1).matches(&meta));
+    assert!(!BcFilter::StreamType(0).matches(&meta));
+}
+
+#[test]
+fn test_route_replies() {
+    let mut subscribers = Subscribers::new();
+    let mut subscribe = |id, msg_id, filter| {
+        let (tx, rx) = channel();
+        subscribers
+            .entry(msg_id)
+            .or_default()
+            .push(Subscriber { id, filter, tx });
+        rx
+    };
+    // Two requests with the same ID in flight, and someone listening to every message
+    let first = subscribe(0, MSG_ID_PING, BcFilter::MsgNum(1));
+    let second = subscribe(1, MSG_ID_PING, BcFilter::MsgNum(2));
+    let all = subscribe(2, MSG_ID_PING, BcFilter::All);
+
+    route(&mut subscribers, test_message(MSG_ID_PING, 2, 0));
+    assert_eq!(second.try_recv().unwrap().meta.msg_num, 2);
+    assert!(first.try_recv().is_err());
+    assert_eq!(all.try_recv().unwrap().meta.msg_num, 2);
+
+    // Nobody is subscribed to this ID
+    route(&mut subscribers, test_message(MSG_ID_VERSION, 1, 0));
+    assert!(first.try_recv().is_err());
+    assert!(all.try_recv().is_err());
+
+    // A subscriber that has gone away is forgotten
+    drop(first);
+    route(&mut subscribers, test_message(MSG_ID_PING, 1, 0));
+    assert_eq!(subscribers[&MSG_ID_PING].len(), 2);
+    assert!(all.try_recv().is_ok());
+}
+
+#[test]
+fn test_route_streams() {
+    let mut subscribers = Subscribers::new();
+    let mut subscribe = |id, filter| {
+        let (tx, rx) = channel();
+        subscribers
+            .entry(MSG_ID_VIDEO)
+            .or_default()
+            .push(Subscriber { id, filter, tx });
+        rx
+    };
+    // The main and sub streams share one connection
+    let main_stream = subscribe(0, BcFilter::StreamType(0));
+    let sub_stream = subscribe(1, BcFilter::StreamType(1));
+
+    route(&mut subscribers, test_message(MSG_ID_VIDEO, 5, 1));
+    route(&mut subscribers, test_message(MSG_ID_VIDEO, 4, 0));
+    route(&mut subscribers, test_message(MSG_ID_VIDEO, 5, 1));
+
+    let received =
+        |rx: &Receiver<Bc>| -> Vec<u8> { rx.try_iter().map(|msg| msg.meta.stream_type).collect() };
+    assert_eq!(received(&main_stream), vec![0]);
+    assert_eq!(received(&sub_stream), vec![1, 1]);
+}
@@ -0,0 +1,85 @@
+use super::udp::{is_timeout, random_i32};
+use crate::bcudp::model::*;
+use log::*;
+use std::io::{Error, ErrorKind, Result};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// How many times to broadcast the probe, spread evenly over the timeout, in case some of the
+/// broadcasts are lost
+const PROBE_COUNT: u32 = 3;
+
+/// A camera that answered a discovery broadcast
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DiscoveredCamera {
+    /// The address to use for Baichuan TCP connections
+    pub addr: SocketAddr,
+    pub uid: String,
+    pub model: String,
+    pub firmware: String,
+}
+
+/// Broadcasts a discovery probe on the local network and collects the cameras that answer within
+/// `timeout`.  The probe is repeated a few times within `timeout`.  Cameras that answer more than
+/// once are only listed once.
+pub fn discover(timeout: Duration) -> Result<Vec<DiscoveredCamera>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+    socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+
+    let probe = BcUdp::Discovery(UdpDiscovery {
+        tid: random_i32() as u32,
+        payload: UdpXml {
+            c2d_s: Some(C2dS {
+                cli: Cli {
+                    port: socket.local_addr()?.port(),
+                },
+            }),
+            ..Default::default()
+        },
+    })
+    .serialize(vec![])
+    .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+    let start = Instant::now();
+    let mut probes_sent = 0;
+    let mut buf = vec![0; MTU as usize];
+    let mut cameras: Vec<DiscoveredCamera> = vec![];
+    while start.elapsed() < timeout {
+        if probes_sent < PROBE_COUNT && start.elapsed() >= timeout * probes_sent / PROBE_COUNT {
+            for &port in DISCOVERY_PORTS.iter() {
+                socket.send_to(&probe, (Ipv4Addr::BROADCAST, port))?;
+            }
+            probes_sent += 1;
+        }
+
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e),
+        };
+        match BcUdp::deserialize(&buf[..len]) {
+            Ok(BcUdp::Discovery(UdpDiscovery {
+                payload: UdpXml {
+                    d2c_s: Some(reply), ..
+                },
+                ..
+            })) => {
+                let camera = DiscoveredCamera {
+                    addr: SocketAddr::new(addr.ip(), reply.port),
+                    uid: reply.uid,
+                    model: reply.type_,
+                    firmware: reply.ver,
+                };
+                if !cameras.contains(&camera) {
+                    debug!("Found {:?}", camera);
+                    cameras.push(camera);
+                }
+            }
+            Ok(packet) => trace!("Ignoring UDP packet {:?}", packet),
+            Err(e) => debug!("Ignoring unparseable UDP packet from {}: {}", addr, e),
+        }
+    }
+
+    Ok(cameras)
+}
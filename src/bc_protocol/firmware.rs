@@ -0,0 +1,89 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+use std::time::Duration;
+
+/// How much of the firmware to send in each message
+const CHUNK_SIZE: usize = 40 * 1024;
+
+/// The camera writes each chunk to flash before acknowledging it, which can take far longer than
+/// answering a request
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(60);
+
+impl BcCamera {
+    /// Sends a firmware file to the camera, which installs it and then reboots.  `file_name` should
+    /// be the name of the file as Reolink publishes it, which the camera checks against its model.
+    /// `progress` is called with the number of bytes sent so far and the total after each chunk.
+    pub fn upgrade_firmware(
+        &self,
+        file_name: &str,
+        firmware: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to upgrade the firmware");
+        // The file info and all of the data share one msg_num, which the camera uses to put the
+        // messages that follow the file info into binary mode
+        let msg_num = self.new_message_num();
+        let sub_upgrade = connection.subscribe_to_reply(MSG_ID_UPGRADE, msg_num)?;
+        let meta = BcMeta {
+            msg_id: MSG_ID_UPGRADE,
+            channel_id: self.channel_id,
+            msg_num,
+            response_code: 0,
+            stream_type: 0,
+            class: 0x6414,
+        };
+
+        let info = Bc::new_from_xml(
+            meta.clone(),
+            BcXml {
+                config_file_info: Some(ConfigFileInfo {
+                    version: xml_ver(),
+                    file_name: file_name.to_string(),
+                    file_size: firmware.len() as u64,
+                    update_parameter: Some(1),
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_upgrade.send(info)?;
+        let msg = sub_upgrade.rx.recv_timeout(RX_TIMEOUT)?;
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the firmware (is the user an admin?)",
+            });
+        }
+
+        let mut sent = 0;
+        for chunk in firmware.chunks(CHUNK_SIZE) {
+            let data = Bc::new_from_ext_binary(
+                meta.clone(),
+                Extension {
+                    version: xml_ver(),
+                    binary_data: Some(1),
+                    channel_id: Some(self.channel_id),
+                    ..Default::default()
+                },
+                chunk.to_vec(),
+            );
+
+            sub_upgrade.send(data)?;
+            let msg = sub_upgrade.rx.recv_timeout(CHUNK_TIMEOUT)?;
+            if msg.meta.response_code != 200 {
+                return Err(Error::UnintelligibleReply {
+                    reply: msg,
+                    why: "Camera stopped accepting the firmware",
+                });
+            }
+
+            sent += chunk.len();
+            progress(sent, firmware.len());
+        }
+
+        Ok(())
+    }
+}
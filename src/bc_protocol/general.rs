@@ -0,0 +1,209 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+use time::UtcOffset;
+
+/// The camera's general settings.  Fields that are None are left as they are by set_general, and
+/// are None from get_general if the camera does not have them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GeneralSettings {
+    pub device_name: Option<String>,
+    /// The order of the date shown on the picture
+    pub date_format: Option<DateFormat>,
+    pub time_format: Option<TimeFormat>,
+    /// The language of the camera's voice prompts, such as "English"
+    pub language: Option<String>,
+    pub time_zone: Option<UtcOffset>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DateFormat {
+    DayMonthYear,
+    MonthDayYear,
+    YearMonthDay,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    TwentyFourHour,
+    TwelveHour,
+}
+
+impl DateFormat {
+    /// The camera's name for the format, such as "DMY"
+    pub fn code(self) -> &'static str {
+        match self {
+            DateFormat::DayMonthYear => "DMY",
+            DateFormat::MonthDayYear => "MDY",
+            DateFormat::YearMonthDay => "YMD",
+        }
+    }
+
+    pub fn from_code(code: &str) -> Option<DateFormat> {
+        match code {
+            "DMY" => Some(DateFormat::DayMonthYear),
+            "MDY" => Some(DateFormat::MonthDayYear),
+            "YMD" => Some(DateFormat::YearMonthDay),
+            _ => None,
+        }
+    }
+}
+
+impl TimeFormat {
+    fn code(self) -> u8 {
+        match self {
+            TimeFormat::TwentyFourHour => 0,
+            TimeFormat::TwelveHour => 1,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<TimeFormat> {
+        match code {
+            0 => Some(TimeFormat::TwentyFourHour),
+            1 => Some(TimeFormat::TwelveHour),
+            _ => None,
+        }
+    }
+}
+
+impl BcCamera {
+    /// Gets the camera's name, date and time formats, language and time zone
+    pub fn get_general(&self) -> Result<GeneralSettings> {
+        let general = self.get_system_general()?;
+        Ok(GeneralSettings {
+            device_name: general.device_name,
+            date_format: general
+                .osd_format
+                .as_deref()
+                .and_then(DateFormat::from_code),
+            time_format: general.time_format.and_then(TimeFormat::from_code),
+            language: general.language,
+            time_zone: general.time_zone.map(time_zone_offset),
+        })
+    }
+
+    /// Changes the settings that are Some in `settings`, and leaves the others as they are
+    pub fn set_general(&self, settings: &GeneralSettings) -> Result<()> {
+        // Send the camera back everything it told us, apart from the time, which would otherwise
+        // be set back to when we asked for it
+        let mut general = SystemGeneral {
+            year: None,
+            month: None,
+            day: None,
+            hour: None,
+            minute: None,
+            second: None,
+            ..self.get_system_general()?
+        };
+        general.version = xml_ver();
+        if let Some(device_name) = &settings.device_name {
+            general.device_name = Some(device_name.clone());
+        }
+        if let Some(date_format) = settings.date_format {
+            general.osd_format = Some(date_format.code().to_string());
+        }
+        if let Some(time_format) = settings.time_format {
+            general.time_format = Some(time_format.code());
+        }
+        if let Some(language) = &settings.language {
+            general.language = Some(language.clone());
+        }
+        if let Some(time_zone) = settings.time_zone {
+            general.time_zone = Some(reolink_time_zone(time_zone));
+        }
+
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to set the general settings");
+        let msg_num = self.new_message_num();
+        let sub_set = connection.subscribe_to_reply(MSG_ID_SET_GENERAL, msg_num)?;
+        let set = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_SET_GENERAL,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                system_general: Some(general),
+                ..Default::default()
+            },
+        );
+
+        sub_set.send(set)?;
+        let msg = sub_set.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the new general settings",
+            });
+        }
+
+        Ok(())
+    }
+
+    fn get_system_general(&self) -> Result<SystemGeneral> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to get the general settings");
+        let msg_num = self.new_message_num();
+        let sub_get = connection.subscribe_to_reply(MSG_ID_GET_GENERAL, msg_num)?;
+        let get = Bc {
+            meta: BcMeta {
+                msg_id: MSG_ID_GET_GENERAL,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            body: BcBody::ModernMsg(ModernMsg::default()),
+        };
+
+        sub_get.send(get)?;
+        let msg = sub_get.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if let BcBody::ModernMsg(ModernMsg {
+            payload:
+                Some(BcPayloads::BcXml(BcXml {
+                    system_general: Some(general),
+                    ..
+                })),
+            ..
+        }) = msg.body
+        {
+            Ok(general)
+        } else {
+            Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Expected a SystemGeneral message",
+            })
+        }
+    }
+}
+
+/// Reolink gives time zones as seconds west of UTC, the opposite way around to the usual
+pub(super) fn time_zone_offset(time_zone: i32) -> UtcOffset {
+    if time_zone > 0 {
+        UtcOffset::west_seconds(time_zone as u32)
+    } else {
+        UtcOffset::east_seconds(-time_zone as u32)
+    }
+}
+
+pub(super) fn reolink_time_zone(offset: UtcOffset) -> i32 {
+    -offset.as_seconds()
+}
+
+#[test]
+fn test_time_zone() {
+    assert_eq!(time_zone_offset(3600), UtcOffset::hours(-1));
+    assert_eq!(time_zone_offset(-19800), UtcOffset::minutes(330));
+    assert_eq!(time_zone_offset(0), UtcOffset::UTC);
+    assert_eq!(reolink_time_zone(UtcOffset::hours(-5)), 18000);
+    assert_eq!(reolink_time_zone(UtcOffset::minutes(330)), -19800);
+}
@@ -0,0 +1,97 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+
+impl BcCamera {
+    /// Gets the state of the IR lights and the status LED
+    pub fn get_ledstate(&self) -> Result<LedState> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to get the LED state");
+        let msg_num = self.new_message_num();
+        let sub_get = connection.subscribe_to_reply(MSG_ID_GET_LED_STATUS, msg_num)?;
+        let get = Bc::new_from_ext(
+            BcMeta {
+                msg_id: MSG_ID_GET_LED_STATUS,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(self.channel_id),
+                ..Default::default()
+            },
+        );
+
+        sub_get.send(get)?;
+        let msg = sub_get.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if let BcBody::ModernMsg(ModernMsg {
+            payload:
+                Some(BcPayloads::BcXml(BcXml {
+                    led_state: Some(led_state),
+                    ..
+                })),
+            ..
+        }) = msg.body
+        {
+            Ok(led_state)
+        } else {
+            Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Expected a LedState message",
+            })
+        }
+    }
+
+    /// Sets the state of the IR lights and the status LED.  Usually you will want to modify the
+    /// result of get_ledstate() and pass it back in.
+    pub fn set_ledstate(&self, mut led_state: LedState) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to set the LED state");
+        let msg_num = self.new_message_num();
+        let sub_set = connection.subscribe_to_reply(MSG_ID_SET_LED_STATUS, msg_num)?;
+
+        // The camera rejects the message if the read-only ledVersion is present
+        led_state.version = xml_ver();
+        led_state.channel_id = self.channel_id;
+        led_state.led_version = None;
+
+        let set = Bc::new_from_ext_xml(
+            BcMeta {
+                msg_id: MSG_ID_SET_LED_STATUS,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(self.channel_id),
+                ..Default::default()
+            },
+            BcXml {
+                led_state: Some(led_state),
+                ..Default::default()
+            },
+        );
+
+        sub_set.send(set)?;
+        let msg = sub_set.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the new LED state",
+            });
+        }
+
+        Ok(())
+    }
+}
@@ -25,7 +25,15 @@ pub enum MediaDataKind {
     Unknown,
 }
 
-***REMOVED***[derive(Debug, PartialEq, Eq)]
+/// The resolution and frame rate of a stream, from its InfoData packets
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct StreamInfo {
+    pub width: u32,
+    pub height: u32,
+    pub fps: u8,
+}
+
+***REMOVED***[derive(Debug, PartialEq, Eq, Clone)]
 pub struct MediaData {
     data: Vec<u8>,
 }
@@ -196,6 +204,18 @@ impl MediaData {
             _ => None,
         }
     }
+
+    /// Decodes an InfoData header.  See dissector/mediapacket.md for the layout.
+    pub fn stream_info(&self) -> Option<StreamInfo> {
+        if self.kind() != MediaDataKind::InfoData || self.data.len() < 18 {
+            return None;
+        }
+        Some(StreamInfo {
+            width: Self::bytes_to_size(&self.data[8..12]) as u32,
+            height: Self::bytes_to_size(&self.data[12..16]) as u32,
+            fps: self.data[17],
+        })
+    }
 }
 
 pub struct MediaDataSubscriber<'a> {
@@ -301,3 +321,32 @@ impl<'a> MediaDataSubscriber<'a> {
         })
     }
 }
+
+#[test]
+fn test_stream_info() {
+    let info = MediaData {
+        data: vec![
+            0x31, 0x30, 0x30, 0x31, // Info V1
+            32, 0, 0, 0, // Header size
+            0x00, 0x0f, 0, 0, // 3840
+            0x70, 0x08, 0, 0, // 2160
+            1, 20, // Unknown, then FPS
+            120, 10, 11, 21, 30, 0, // Start time
+            120, 10, 11, 21, 35, 0, // End time
+            0, 0,
+        ],
+    };
+    assert_eq!(
+        info.stream_info(),
+        Some(StreamInfo {
+            width: 3840,
+            height: 2160,
+            fps: 20,
+        })
+    );
+
+    let audio = MediaData {
+        data: vec![0x30, 0x35, 0x77, 0x62, 0, 0, 0, 0],
+    };
+    assert_eq!(audio.stream_info(), None);
+}
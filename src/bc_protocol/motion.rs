@@ -0,0 +1,209 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+use crate::bc_protocol::connection::BcSubscription;
+use log::*;
+use std::collections::HashSet;
+
+/// What the camera has detected.  Person and vehicle detection are only reported by cameras
+/// with AI detection.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum MotionKind {
+    Motion,
+    Person,
+    Vehicle,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum MotionStatus {
+    Start,
+    Stop,
+}
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct MotionEvent {
+    pub channel_id: u8,
+    pub kind: MotionKind,
+    pub status: MotionStatus,
+}
+
+/// Receives alarm messages from the camera.  The camera reports the full set of active alarms
+/// each time something changes, so we remember what was last active and turn each report into
+/// start/stop events.
+pub struct MotionDataSubscriber<'a> {
+    bc_sub: BcSubscription<'a>,
+    tracker: MotionTracker,
+}
+
+impl BcCamera {
+    /// Asks the camera to push alarm messages to us, and returns a subscriber that yields them.
+    pub fn listen_on_motion(&self) -> Result<MotionDataSubscriber> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to listen to motion");
+
+        // Subscribe to the alarm messages first so that none are missed between the camera
+        // accepting the request and us listening for them
+        let sub_motion = connection.subscribe(MSG_ID_MOTION)?;
+        let msg_num = self.new_message_num();
+        let sub_motion_request = connection.subscribe_to_reply(MSG_ID_MOTION_REQUEST, msg_num)?;
+
+        let request = Bc::new_from_meta(BcMeta {
+            msg_id: MSG_ID_MOTION_REQUEST,
+            channel_id: self.channel_id,
+            msg_num,
+            response_code: 0,
+            stream_type: 0,
+            class: 0x6414,
+        });
+
+        sub_motion_request.send(request)?;
+        let msg = sub_motion_request.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the request to send alarm messages",
+            });
+        }
+
+        Ok(MotionDataSubscriber {
+            bc_sub: sub_motion,
+            tracker: MotionTracker::default(),
+        })
+    }
+}
+
+impl<'a> MotionDataSubscriber<'a> {
+    /// Blocks until the camera reports a change in its alarm state, and returns the events that
+    /// started or stopped.
+    pub fn next_motion(&mut self) -> Result<Vec<MotionEvent>> {
+        loop {
+            let msg = self.bc_sub.rx.recv()?;
+            if let BcBody::ModernMsg(ModernMsg {
+                payload:
+                    Some(BcPayloads::BcXml(BcXml {
+                        alarm_event_list: Some(alarm_event_list),
+                        ..
+                    })),
+                ..
+            }) = msg.body
+            {
+                let events = self.tracker.update(&alarm_event_list.alarm_events);
+                if !events.is_empty() {
+                    return Ok(events);
+                }
+            } else {
+                debug!("Ignoring alarm message without an AlarmEventList");
+                trace!("Contents: {:?}", msg);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct MotionTracker {
+    active: HashSet<(u8, MotionKind)>,
+}
+
+impl MotionTracker {
+    fn update(&mut self, alarm_events: &[AlarmEvent]) -> Vec<MotionEvent> {
+        let mut events = vec![];
+        for alarm_event in alarm_events {
+            let channel_id = alarm_event.channel_id;
+            let now_active = active_kinds(alarm_event);
+
+            for &kind in &[MotionKind::Motion, MotionKind::Person, MotionKind::Vehicle] {
+                let was_active = self.active.contains(&(channel_id, kind));
+                let is_active = now_active.contains(&kind);
+                let status = match (was_active, is_active) {
+                    (false, true) => {
+                        self.active.insert((channel_id, kind));
+                        MotionStatus::Start
+                    }
+                    (true, false) => {
+                        self.active.remove(&(channel_id, kind));
+                        MotionStatus::Stop
+                    }
+                    _ => continue,
+                };
+                events.push(MotionEvent {
+                    channel_id,
+                    kind,
+                    status,
+                });
+            }
+        }
+        events
+    }
+}
+
+fn active_kinds(alarm_event: &AlarmEvent) -> HashSet<MotionKind> {
+    let mut kinds = HashSet::new();
+    if alarm_event.status.split(',').any(|s| s == "MD") {
+        kinds.insert(MotionKind::Motion);
+    }
+    if let Some(ai_type) = &alarm_event.ai_type {
+        for ai in ai_type.split(',') {
+            match ai {
+                "people" => {
+                    kinds.insert(MotionKind::Person);
+                }
+                "vehicle" => {
+                    kinds.insert(MotionKind::Vehicle);
+                }
+                _ => {}
+            }
+        }
+    }
+    kinds
+}
+
+#[test]
+fn test_motion_tracker() {
+    let alarm = |status: &str, ai_type: Option<&str>| AlarmEvent {
+        version: xml_ver(),
+        channel_id: 0,
+        status: status.to_string(),
+        ai_type: ai_type.map(str::to_string),
+        recording: 0,
+        timestamp: 0,
+    };
+    let mut tracker = MotionTracker::default();
+
+    let events = tracker.update(&[alarm("MD", Some("people"))]);
+    assert_eq!(events.len(), 2);
+    assert!(events.contains(&MotionEvent {
+        channel_id: 0,
+        kind: MotionKind::Motion,
+        status: MotionStatus::Start,
+    }));
+    assert!(events.contains(&MotionEvent {
+        channel_id: 0,
+        kind: MotionKind::Person,
+        status: MotionStatus::Start,
+    }));
+
+    // Repeated reports of the same state are not new events
+    assert!(tracker.update(&[alarm("MD", Some("people"))]).is_empty());
+
+    let events = tracker.update(&[alarm("MD", Some("none"))]);
+    assert_eq!(
+        events,
+        vec![MotionEvent {
+            channel_id: 0,
+            kind: MotionKind::Person,
+            status: MotionStatus::Stop,
+        }]
+    );
+
+    let events = tracker.update(&[alarm("none", None)]);
+    assert_eq!(
+        events,
+        vec![MotionEvent {
+            channel_id: 0,
+            kind: MotionKind::Motion,
+            status: MotionStatus::Stop,
+        }]
+    );
+}
@@ -0,0 +1,209 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+
+/// Directions understood by the camera's `<PtzControl>` command
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+    LeftUp,
+    LeftDown,
+    RightUp,
+    RightDown,
+}
+
+/// Zoom directions understood by the camera's `<PtzControl>` command
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Zoom {
+    In,
+    Out,
+}
+
+impl Direction {
+    fn command(self) -> &'static str {
+        match self {
+            Direction::Up => "up",
+            Direction::Down => "down",
+            Direction::Left => "left",
+            Direction::Right => "right",
+            Direction::LeftUp => "leftUp",
+            Direction::LeftDown => "leftDown",
+            Direction::RightUp => "rightUp",
+            Direction::RightDown => "rightDown",
+        }
+    }
+}
+
+impl Zoom {
+    fn command(self) -> &'static str {
+        match self {
+            Zoom::In => "zoomin",
+            Zoom::Out => "zoomout",
+        }
+    }
+}
+
+impl BcCamera {
+    /// Starts moving the camera in the given direction.  The camera keeps moving until it
+    /// reaches its limit or ptz_stop() is called.  The official clients use a speed of 32.
+    pub fn ptz_move(&self, direction: Direction, speed: u32) -> Result<()> {
+        self.send_ptz_control(direction.command(), Some(speed))
+    }
+
+    /// Starts zooming the camera.  The camera keeps zooming until it reaches its limit or
+    /// ptz_stop() is called.
+    pub fn ptz_zoom(&self, zoom: Zoom, speed: u32) -> Result<()> {
+        self.send_ptz_control(zoom.command(), Some(speed))
+    }
+
+    /// Stops any ongoing movement or zoom
+    pub fn ptz_stop(&self) -> Result<()> {
+        self.send_ptz_control("stop", None)
+    }
+
+    /// Moves the camera to a previously saved preset
+    pub fn ptz_goto_preset(&self, preset_id: u8) -> Result<()> {
+        self.send_ptz_preset(Preset {
+            id: preset_id,
+            name: None,
+            command: Some("toPos".to_string()),
+        })
+    }
+
+    /// Saves the current camera position as a preset, overwriting any preset with the same ID
+    pub fn ptz_save_preset(&self, preset_id: u8, name: &str) -> Result<()> {
+        self.send_ptz_preset(Preset {
+            id: preset_id,
+            name: Some(name.to_string()),
+            command: Some("setPos".to_string()),
+        })
+    }
+
+    /// Lists the presets that have been saved on the camera
+    pub fn ptz_presets(&self) -> Result<Vec<Preset>> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to get PTZ presets");
+        let msg_num = self.new_message_num();
+        let sub_get_preset = connection.subscribe_to_reply(MSG_ID_GET_PTZ_PRESET, msg_num)?;
+        let get = Bc::new_from_ext(
+            BcMeta {
+                msg_id: MSG_ID_GET_PTZ_PRESET,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(self.channel_id),
+                ..Default::default()
+            },
+        );
+
+        sub_get_preset.send(get)?;
+        let msg = sub_get_preset.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if let BcBody::ModernMsg(ModernMsg {
+            payload:
+                Some(BcPayloads::BcXml(BcXml {
+                    ptz_preset: Some(PtzPreset { preset_list, .. }),
+                    ..
+                })),
+            ..
+        }) = msg.body
+        {
+            Ok(preset_list.map(|list| list.preset).unwrap_or_default())
+        } else {
+            Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Expected a PtzPreset message",
+            })
+        }
+    }
+
+    fn send_ptz_control(&self, command: &str, speed: Option<u32>) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to control PTZ");
+        let msg_num = self.new_message_num();
+        let sub_ptz = connection.subscribe_to_reply(MSG_ID_PTZ_CONTROL, msg_num)?;
+        let ptz = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_PTZ_CONTROL,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                ptz_control: Some(PtzControl {
+                    version: xml_ver(),
+                    channel_id: self.channel_id,
+                    speed,
+                    command: command.to_string(),
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_ptz.send(ptz)?;
+        let msg = sub_ptz.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the PTZ command (does it support PTZ?)",
+            });
+        }
+
+        Ok(())
+    }
+
+    fn send_ptz_preset(&self, preset: Preset) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to control PTZ presets");
+        let msg_num = self.new_message_num();
+        let sub_preset = connection.subscribe_to_reply(MSG_ID_PTZ_CONTROL_PRESET, msg_num)?;
+        let set = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_PTZ_CONTROL_PRESET,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                ptz_preset: Some(PtzPreset {
+                    version: xml_ver(),
+                    channel_id: self.channel_id,
+                    preset_list: Some(PresetList {
+                        preset: vec![preset],
+                    }),
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_preset.send(set)?;
+        let msg = sub_preset.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the PTZ preset command",
+            });
+        }
+
+        Ok(())
+    }
+}
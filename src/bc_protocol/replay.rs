@@ -0,0 +1,325 @@
+use super::media_packet::MediaDataSubscriber;
+use super::{BcCamera, Error, MediaSink, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+use log::*;
+use time::{Date, PrimitiveDateTime, Time};
+
+/// A file on the camera's SD card.  Times are in the camera's own time zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recording {
+    pub name: String,
+    pub stream_type: String,
+    pub record_type: String,
+    pub start: PrimitiveDateTime,
+    pub end: PrimitiveDateTime,
+    pub size: Option<u64>,
+}
+
+impl BcCamera {
+    /// Lists the recordings of `stream_type` ("mainStream" or "subStream") that overlap the given
+    /// time range, oldest first.  Times are in the camera's own time zone.
+    pub fn search_recordings(
+        &self,
+        stream_type: &str,
+        start: PrimitiveDateTime,
+        end: PrimitiveDateTime,
+    ) -> Result<Vec<Recording>> {
+        let handle = self.open_file_info_list(stream_type, start, end)?;
+        let result = (|| -> Result<Vec<Recording>> {
+            let mut recordings = vec![];
+            loop {
+                let files = self.get_file_info_list(handle)?;
+                if files.is_empty() {
+                    break;
+                }
+                recordings.extend(files.into_iter().filter_map(recording_from_file_info));
+            }
+            Ok(recordings)
+        })();
+
+        if let Err(err) = self.close_file_info_list(handle) {
+            debug!("Could not close recording search, ignoring: {}", err);
+        }
+
+        let mut recordings = result?;
+        recordings.sort_by_key(|recording| recording.start);
+        Ok(recordings)
+    }
+
+    /// Streams the recording called `name` from the SD card into `data_outs`, at the speed it
+    /// was recorded.  Returns once the camera has sent the whole file.
+    pub fn replay(&self, name: &str, data_outs: &mut dyn MediaSink) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to replay recordings");
+        let msg_num = self.new_message_num();
+        let sub_replay = connection.subscribe_to_reply(MSG_ID_REPLAY, msg_num)?;
+
+        let replay = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_REPLAY,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                file_info_list: Some(file_info_list(FileInfo {
+                    channel_id: self.channel_id,
+                    name: Some(name.to_string()),
+                    play_speed: Some(1),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        );
+        sub_replay.send(replay)?;
+
+        let mut media_sub = MediaDataSubscriber::from_bc_sub(&sub_replay);
+        let mut received = false;
+        let result = loop {
+            match media_sub.next_media_packet() {
+                Ok(media) => {
+                    received = true;
+                    if let Err(err) = data_outs.write_media(&media) {
+                        break Err(err);
+                    }
+                }
+                // The camera simply stops sending at the end of the file
+                Err(Error::Timeout) if received => break Ok(()),
+                Err(err) => break Err(err),
+            }
+        };
+
+        match result {
+            Err(Error::DroppedConnection(_))
+            | Err(Error::TimeoutDisconnected)
+            | Err(Error::CommunicationError(_)) => {}
+            _ => {
+                if let Err(err) = self.stop_replay(name) {
+                    debug!("Could not stop replaying {}, ignoring: {}", name, err);
+                }
+            }
+        }
+        result
+    }
+
+    fn stop_replay(&self, name: &str) -> Result<()> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to stop replaying");
+        let msg_num = self.new_message_num();
+        let sub_stop = connection.subscribe_to_reply(MSG_ID_REPLAY_STOP, msg_num)?;
+
+        let stop = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_REPLAY_STOP,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                file_info_list: Some(file_info_list(FileInfo {
+                    channel_id: self.channel_id,
+                    name: Some(name.to_string()),
+                    ..Default::default()
+                })),
+                ..Default::default()
+            },
+        );
+
+        sub_stop.send(stop)?;
+        let msg = sub_stop.rx.recv_timeout(RX_TIMEOUT)?;
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the request to stop replaying",
+            });
+        }
+        Ok(())
+    }
+
+    /// Starts a search, returning the handle to fetch its results with
+    fn open_file_info_list(
+        &self,
+        stream_type: &str,
+        start: PrimitiveDateTime,
+        end: PrimitiveDateTime,
+    ) -> Result<u32> {
+        let msg = self.file_info_request(
+            MSG_ID_FILE_INFO_LIST_OPEN,
+            FileInfo {
+                channel_id: self.channel_id,
+                stream_type: Some(stream_type.to_string()),
+                record_type: Some("manual, sched, io, md".to_string()),
+                start_time: Some(record_time(start)),
+                end_time: Some(record_time(end)),
+                ..Default::default()
+            },
+        )?;
+
+        if let BcBody::ModernMsg(ModernMsg {
+            payload:
+                Some(BcPayloads::BcXml(BcXml {
+                    file_info_list: Some(list),
+                    ..
+                })),
+            ..
+        }) = &msg.body
+        {
+            if let Some(handle) = list.file_info.first().and_then(|info| info.handle) {
+                return Ok(handle);
+            }
+        }
+        Err(Error::UnintelligibleReply {
+            reply: msg,
+            why: "Expected a FileInfoList with a search handle",
+        })
+    }
+
+    /// Fetches the next batch of results, which is empty once there are no more
+    fn get_file_info_list(&self, handle: u32) -> Result<Vec<FileInfo>> {
+        let msg = self.file_info_request(
+            MSG_ID_FILE_INFO_LIST,
+            FileInfo {
+                channel_id: self.channel_id,
+                handle: Some(handle),
+                ..Default::default()
+            },
+        )?;
+
+        match msg.body {
+            BcBody::ModernMsg(ModernMsg {
+                payload:
+                    Some(BcPayloads::BcXml(BcXml {
+                        file_info_list: Some(list),
+                        ..
+                    })),
+                ..
+            }) => Ok(list
+                .file_info
+                .into_iter()
+                .filter(|info| info.name.is_some())
+                .collect()),
+            BcBody::ModernMsg(ModernMsg { payload: None, .. }) => Ok(vec![]),
+            _ => Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Expected a FileInfoList",
+            }),
+        }
+    }
+
+    fn close_file_info_list(&self, handle: u32) -> Result<()> {
+        self.file_info_request(
+            MSG_ID_FILE_INFO_LIST_CLOSE,
+            FileInfo {
+                channel_id: self.channel_id,
+                handle: Some(handle),
+                ..Default::default()
+            },
+        )?;
+        Ok(())
+    }
+
+    fn file_info_request(&self, msg_id: u32, file_info: FileInfo) -> Result<Bc> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to search recordings");
+        let msg_num = self.new_message_num();
+        let sub = connection.subscribe_to_reply(msg_id, msg_num)?;
+
+        let request = Bc::new_from_xml(
+            BcMeta {
+                msg_id,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                file_info_list: Some(file_info_list(file_info)),
+                ..Default::default()
+            },
+        );
+
+        sub.send(request)?;
+        let msg = sub.rx.recv_timeout(RX_TIMEOUT)?;
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Camera did not accept the recording search (is there an SD card?)",
+            });
+        }
+        Ok(msg)
+    }
+}
+
+fn file_info_list(file_info: FileInfo) -> FileInfoList {
+    FileInfoList {
+        version: xml_ver(),
+        file_info: vec![file_info],
+    }
+}
+
+fn record_time(datetime: PrimitiveDateTime) -> RecordTime {
+    RecordTime {
+        year: datetime.year(),
+        month: datetime.month(),
+        day: datetime.day(),
+        hour: datetime.hour(),
+        minute: datetime.minute(),
+        second: datetime.second(),
+    }
+}
+
+fn datetime_from_record_time(time: &RecordTime) -> Option<PrimitiveDateTime> {
+    let date = Date::try_from_ymd(time.year, time.month, time.day).ok()?;
+    let time = Time::try_from_hms(time.hour, time.minute, time.second).ok()?;
+    Some(PrimitiveDateTime::new(date, time))
+}
+
+/// Skips anything the camera lists without a name or a valid time range
+fn recording_from_file_info(info: FileInfo) -> Option<Recording> {
+    Some(Recording {
+        start: datetime_from_record_time(info.start_time.as_ref()?)?,
+        end: datetime_from_record_time(info.end_time.as_ref()?)?,
+        name: info.name?,
+        stream_type: info.stream_type.unwrap_or_default(),
+        record_type: info.record_type.unwrap_or_default(),
+        size: info.size,
+    })
+}
+
+#[test]
+fn test_recording_from_file_info() {
+    let time = |minute| RecordTime {
+        year: 2020,
+        month: 10,
+        day: 11,
+        hour: 21,
+        minute,
+        second: 0,
+    };
+    let info = FileInfo {
+        name: Some("RecM01_20201011_213000_213500.mp4".to_string()),
+        stream_type: Some("mainStream".to_string()),
+        start_time: Some(time(30)),
+        end_time: Some(time(35)),
+        ..Default::default()
+    };
+
+    let recording = recording_from_file_info(info.clone()).unwrap();
+    assert_eq!(recording.name, "RecM01_20201011_213000_213500.mp4");
+    assert_eq!(recording.start.minute(), 30);
+    assert_eq!(record_time(recording.end), time(35));
+
+    let unnamed = FileInfo { name: None, ..info };
+    assert_eq!(recording_from_file_info(unnamed), None);
+}
@@ -0,0 +1,81 @@
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+
+impl BcCamera {
+    /// Asks the camera for a still image from the main stream and returns it as JPEG bytes
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to take a snapshot");
+        let msg_num = self.new_message_num();
+        let sub_snap = connection.subscribe_to_reply(MSG_ID_SNAP, msg_num)?;
+
+        let snap = Bc::new_from_xml(
+            BcMeta {
+                msg_id: MSG_ID_SNAP,
+                channel_id: self.channel_id,
+                msg_num,
+                stream_type: 0,
+                response_code: 0,
+                class: 0x6414,
+            },
+            BcXml {
+                snap: Some(Snap {
+                    version: xml_ver(),
+                    channel_id: self.channel_id,
+                    logic_channel: Some(self.channel_id),
+                    time: Some(0),
+                    full_frame: Some(0),
+                    stream_type: Some("main".to_string()),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_snap.send(snap)?;
+        let msg = sub_snap.rx.recv_timeout(RX_TIMEOUT)?;
+
+        let picture_size = match msg.body {
+            BcBody::ModernMsg(ModernMsg {
+                payload:
+                    Some(BcPayloads::BcXml(BcXml {
+                        snap:
+                            Some(Snap {
+                                picture_size: Some(picture_size),
+                                ..
+                            }),
+                        ..
+                    })),
+                ..
+            }) if msg.meta.response_code == 200 => picture_size as usize,
+            _ => {
+                return Err(Error::UnintelligibleReply {
+                    reply: msg,
+                    why: "Expected a Snap message with the picture size",
+                })
+            }
+        };
+
+        // The image follows in as many binary messages as it takes
+        let mut jpeg = Vec::with_capacity(picture_size);
+        while jpeg.len() < picture_size {
+            let msg = sub_snap.rx.recv_timeout(RX_TIMEOUT)?;
+            if let BcBody::ModernMsg(ModernMsg {
+                payload: Some(BcPayloads::Binary(data)),
+                ..
+            }) = msg.body
+            {
+                jpeg.extend_from_slice(&data);
+            } else {
+                return Err(Error::UnintelligibleReply {
+                    reply: msg,
+                    why: "Expected binary image data",
+                });
+            }
+        }
+
+        Ok(jpeg)
+    }
+}
@@ -0,0 +1,224 @@
+use super::adpcm::AdpcmEncoder;
+use super::connection::BcSubscription;
+use super::{BcCamera, Error, Result, RX_TIMEOUT};
+use crate::bc::{model::*, xml::*};
+
+/// Talk data is wrapped in the same media packets that the camera uses for its own ADPCM audio
+const MAGIC_ADPCM: &[u8] = &[0x30, 0x31, 0x77, 0x62];
+const PAD_SIZE: usize = 8;
+
+/// An open talk session.  Audio passed to send_pcm() is played on the camera's speaker.
+pub struct Talk<'a> {
+    camera: &'a BcCamera,
+    sub_talk: BcSubscription<'a>,
+    msg_num: u16,
+    samples_per_block: usize,
+    pending: Vec<i16>,
+    encoder: AdpcmEncoder,
+}
+
+impl BcCamera {
+    /// Gets the duplex modes and audio formats that the camera's speaker supports
+    pub fn talk_ability(&self) -> Result<TalkAbility> {
+        let connection = self
+            .connection
+            .as_ref()
+            .expect("Must be connected to get the talk ability");
+        let msg_num = self.new_message_num();
+        let sub_ability = connection.subscribe_to_reply(MSG_ID_TALKABILITY, msg_num)?;
+        let ability = Bc::new_from_ext(
+            BcMeta {
+                msg_id: MSG_ID_TALKABILITY,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(self.channel_id),
+                ..Default::default()
+            },
+        );
+
+        sub_ability.send(ability)?;
+        let msg = sub_ability.rx.recv_timeout(RX_TIMEOUT)?;
+
+        if let BcBody::ModernMsg(ModernMsg {
+            payload:
+                Some(BcPayloads::BcXml(BcXml {
+                    talk_ability: Some(talk_ability),
+                    ..
+                })),
+            ..
+        }) = msg.body
+        {
+            Ok(talk_ability)
+        } else {
+            Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "Expected a TalkAbility message",
+            })
+        }
+    }
+
+    /// Starts a talk session.  `audio_config` should be one of the configurations from
+    /// talk_ability(); only ADPCM is supported.
+    pub fn talk_start(&self, audio_config: AudioConfig) -> Result<Talk> {
+        let connection = self.connection.as_ref().expect("Must be connected to talk");
+        let msg_num = self.new_message_num();
+        let sub_config = connection.subscribe_to_reply(MSG_ID_TALKCONFIG, msg_num)?;
+
+        let samples_per_block = audio_config.length_per_encoder as usize;
+        let config = Bc::new_from_ext_xml(
+            BcMeta {
+                msg_id: MSG_ID_TALKCONFIG,
+                channel_id: self.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(self.channel_id),
+                ..Default::default()
+            },
+            BcXml {
+                talk_config: Some(TalkConfig {
+                    version: xml_ver(),
+                    channel_id: self.channel_id,
+                    duplex: "FDX".to_string(),
+                    audio_stream_mode: "followVideoStream".to_string(),
+                    audio_config,
+                }),
+                ..Default::default()
+            },
+        );
+
+        sub_config.send(config)?;
+        let msg = sub_config.rx.recv_timeout(RX_TIMEOUT)?;
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "The camera did not accept the talk configuration",
+            });
+        }
+
+        // All of the talk data shares one msg_num, which the camera uses to tell it apart from
+        // any other binary data
+        let msg_num = self.new_message_num();
+        let sub_talk = connection.subscribe_to_reply(MSG_ID_TALK, msg_num)?;
+        Ok(Talk {
+            camera: self,
+            sub_talk,
+            msg_num,
+            samples_per_block,
+            pending: Vec::with_capacity(samples_per_block),
+            encoder: AdpcmEncoder::default(),
+        })
+    }
+}
+
+impl<'a> Talk<'a> {
+    /// Queues signed 16-bit mono PCM at the sample rate given to talk_start(), and sends it to the
+    /// camera a block at a time.  Audio should be sent at roughly the rate it is played.
+    pub fn send_pcm(&mut self, pcm: &[i16]) -> Result<()> {
+        self.pending.extend_from_slice(pcm);
+        while self.pending.len() >= self.samples_per_block {
+            let block: Vec<i16> = self.pending.drain(..self.samples_per_block).collect();
+            let adpcm = self.encoder.encode_block(&block);
+            self.send_media_packet(&adpcm)?;
+        }
+
+        // The camera may acknowledge the talk data, but there is nothing for us to do with it
+        while self.sub_talk.rx.try_recv().is_ok() {}
+        Ok(())
+    }
+
+    /// Stops the talk session, dropping any audio too short to fill a block
+    pub fn stop(self) -> Result<()> {
+        let camera = self.camera;
+        let connection = camera
+            .connection
+            .as_ref()
+            .expect("Must be connected to stop talking");
+        let msg_num = camera.new_message_num();
+        let sub_reset = connection.subscribe_to_reply(MSG_ID_TALKRESET, msg_num)?;
+        let reset = Bc::new_from_ext(
+            BcMeta {
+                msg_id: MSG_ID_TALKRESET,
+                channel_id: camera.channel_id,
+                msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                channel_id: Some(camera.channel_id),
+                ..Default::default()
+            },
+        );
+
+        sub_reset.send(reset)?;
+        let msg = sub_reset.rx.recv_timeout(RX_TIMEOUT)?;
+        if msg.meta.response_code != 200 {
+            return Err(Error::UnintelligibleReply {
+                reply: msg,
+                why: "The camera did not accept the request to stop talking",
+            });
+        }
+        Ok(())
+    }
+
+    fn send_media_packet(&self, adpcm: &[u8]) -> Result<()> {
+        let data = media_packet(adpcm);
+        let talk = Bc::new_from_ext_binary(
+            BcMeta {
+                msg_id: MSG_ID_TALK,
+                channel_id: self.camera.channel_id,
+                msg_num: self.msg_num,
+                response_code: 0,
+                stream_type: 0,
+                class: 0x6414,
+            },
+            Extension {
+                version: xml_ver(),
+                binary_data: Some(1),
+                channel_id: Some(self.camera.channel_id),
+                ..Default::default()
+            },
+            data,
+        );
+        self.sub_talk.send(talk)
+    }
+}
+
+/// Wraps ADPCM blocks in a media packet: the magic, the data size (twice), the data, then padding
+/// to a multiple of eight bytes
+fn media_packet(adpcm: &[u8]) -> Vec<u8> {
+    let size = (adpcm.len() as u16).to_le_bytes();
+    let mut packet = Vec::with_capacity(8 + adpcm.len() + PAD_SIZE);
+    packet.extend_from_slice(MAGIC_ADPCM);
+    packet.extend_from_slice(&size);
+    packet.extend_from_slice(&size);
+    packet.extend_from_slice(adpcm);
+    while packet.len() % PAD_SIZE != 0 {
+        packet.push(0);
+    }
+    packet
+}
+
+#[test]
+fn test_talk_media_packet() {
+    let packet = media_packet(&[0x00, 0x01, 0x03, 0x00, 0, 0, 0, 0, 0x12, 0x34]);
+    assert_eq!(&packet[0..4], MAGIC_ADPCM);
+    assert_eq!(&packet[4..8], &[10, 0, 10, 0]);
+    assert_eq!(
+        &packet[8..18],
+        &[0x00, 0x01, 0x03, 0x00, 0, 0, 0, 0, 0x12, 0x34]
+    );
+    assert_eq!(packet.len(), 24);
+}
@@ -1,6 +1,7 @@
+use super::general::{reolink_time_zone, time_zone_offset};
 use super::{BcCamera, Error, Result, RX_TIMEOUT};
 use crate::bc::{model::*, xml::*};
-use time::{date, Date, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+use time::{date, Date, OffsetDateTime, PrimitiveDateTime, Time};
 
 impl BcCamera {
     pub fn get_time(&self) -> Result<Option<OffsetDateTime>> {
@@ -8,12 +9,13 @@ impl BcCamera {
             .connection
             .as_ref()
             .expect("Must be connected to get time");
-        let sub_get_general = connection.subscribe(MSG_ID_GET_GENERAL)?;
+        let msg_num = self.new_message_num();
+        let sub_get_general = connection.subscribe_to_reply(MSG_ID_GET_GENERAL, msg_num)?;
         let get = Bc {
             meta: BcMeta {
                 msg_id: MSG_ID_GET_GENERAL,
                 channel_id: self.channel_id,
-                msg_num: self.new_message_num(),
+                msg_num,
                 response_code: 0,
                 stream_type: 0,
                 class: 0x6414,
@@ -80,12 +82,13 @@ impl BcCamera {
             .connection
             .as_ref()
             .expect("Must be connected to set time");
-        let sub_set_general = connection.subscribe(MSG_ID_SET_GENERAL)?;
+        let msg_num = self.new_message_num();
+        let sub_set_general = connection.subscribe_to_reply(MSG_ID_SET_GENERAL, msg_num)?;
         let set = Bc::new_from_xml(
             BcMeta {
                 msg_id: MSG_ID_SET_GENERAL,
                 channel_id: self.channel_id,
-                msg_num: self.new_message_num(),
+                msg_num,
                 response_code: 0,
                 stream_type: 0,
                 class: 0x6414,
@@ -95,8 +98,7 @@ impl BcCamera {
                     version: xml_ver(),
                     //osd_format: Some("MDY".to_string()),
                     time_format: Some(0),
-                    // Reolink uses positive seconds to indicate a negative UTC offset:
-                    time_zone: Some(-timestamp.offset().as_seconds()),
+                    time_zone: Some(reolink_time_zone(timestamp.offset())),
                     year: Some(timestamp.year()),
                     month: Some(timestamp.month()),
                     day: Some(timestamp.day()),
@@ -127,11 +129,5 @@ fn try_build_timestamp(
 ) -> std::result::Result<OffsetDateTime, time::ComponentRangeError> {
     let date = Date::try_from_ymd(year, month, day)?;
     let time = Time::try_from_hms(hour, minute, second)?;
-    let offset = if timezone > 0 {
-        UtcOffset::west_seconds(timezone as u32)
-    } else {
-        UtcOffset::east_seconds(-timezone as u32)
-    };
-
-    Ok(PrimitiveDateTime::new(date, time).assume_offset(offset))
+    Ok(PrimitiveDateTime::new(date, time).assume_offset(time_zone_offset(timezone)))
 }
@@ -0,0 +1,367 @@
+use crate::bcudp::model::*;
+use log::*;
+use std::collections::BTreeMap;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How often the UDP thread wakes up to check for packets that need resending
+const TICK: Duration = Duration::from_millis(20);
+/// How long to wait for an ack before sending a data packet again
+const RESEND_TIMEOUT: Duration = Duration::from_millis(500);
+/// How often to repeat the discovery broadcast while looking for a camera
+const DISCOVERY_INTERVAL: Duration = Duration::from_millis(500);
+/// How often to ack the camera even when there is nothing new to ack, so that it knows we are
+/// still here while the connection is idle
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A reliable, ordered byte stream to a camera over the Baichuan UDP transport.  Baichuan
+/// messages are written to and read from it exactly as they would be on a TcpStream.
+///
+/// Outgoing bytes are cut into numbered data packets, which are kept until the camera acks them
+/// and sent again if it does not.  Incoming data packets are reordered by number, and we ack
+/// them as they arrive.  All of this happens on a background thread, which stops when the
+/// stream is shut down or when the camera stops acking what we send it.  An idle connection is
+/// kept open with heartbeat acks, and reads wait for as long as it stays open.
+#[derive(Clone)]
+pub struct UdpStream {
+    shared: Arc<Shared>,
+    thread: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+struct Shared {
+    socket: UdpSocket,
+    camera_addr: SocketAddr,
+    /// The ID that the camera puts on packets for us
+    client_id: i32,
+    /// The ID that we put on packets for the camera
+    camera_id: i32,
+    sending: Mutex<Sending>,
+    receiving: Mutex<Receiving>,
+    closed: AtomicBool,
+}
+
+#[derive(Default)]
+struct Sending {
+    next_packet_id: u32,
+    /// Serialized packets that the camera has not acked yet, and when we last sent them
+    unacked: BTreeMap<u32, (Vec<u8>, Instant)>,
+    /// When we started waiting for the camera to ack something, if we are
+    waiting_since: Option<Instant>,
+}
+
+struct Receiving {
+    rx: Receiver<Vec<u8>>,
+    /// Bytes handed to us by the UDP thread that have not been read yet
+    buf: Vec<u8>,
+    pos: usize,
+}
+
+impl UdpStream {
+    /// Broadcasts on the local network for the camera with the given UID and connects to it.
+    pub fn connect_uid(uid: &str, timeout: Duration) -> Result<UdpStream> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_read_timeout(Some(DISCOVERY_INTERVAL))?;
+
+        let client_id = random_i32();
+        let tid = random_i32() as u32;
+        let request = BcUdp::Discovery(UdpDiscovery {
+            tid,
+            payload: UdpXml {
+                c2d_c: Some(C2dC {
+                    uid: uid.to_string(),
+                    cli: Cli {
+                        port: socket.local_addr()?.port(),
+                    },
+                    cid: client_id,
+                    mtu: MTU,
+                    debug: 0,
+                    p: "MAC".to_string(),
+                }),
+                ..Default::default()
+            },
+        })
+        .serialize(vec![])
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+
+        let start = Instant::now();
+        let mut buf = vec![0; MTU as usize];
+        let (camera_addr, camera_id) = 'discovery: loop {
+            if start.elapsed() > timeout {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("No camera with UID {} answered", uid),
+                ));
+            }
+            for &port in DISCOVERY_PORTS.iter() {
+                socket.send_to(&request, (Ipv4Addr::BROADCAST, port))?;
+            }
+
+            let wait_until = Instant::now() + DISCOVERY_INTERVAL;
+            while Instant::now() < wait_until {
+                let (len, addr) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) if is_timeout(&e) => break,
+                    Err(e) => return Err(e),
+                };
+                if let Ok(BcUdp::Discovery(UdpDiscovery {
+                    payload:
+                        UdpXml {
+                            d2c_c_r: Some(reply),
+                            ..
+                        },
+                    ..
+                })) = BcUdp::deserialize(&buf[..len])
+                {
+                    if reply.cid == client_id && reply.rsp == 0 {
+                        break 'discovery (addr, reply.did);
+                    }
+                }
+            }
+        };
+        debug!("Camera {} answered from {}", uid, camera_addr);
+
+        UdpStream::new(socket, camera_addr, client_id, camera_id, timeout)
+    }
+
+    fn new(
+        socket: UdpSocket,
+        camera_addr: SocketAddr,
+        client_id: i32,
+        camera_id: i32,
+        timeout: Duration,
+    ) -> Result<UdpStream> {
+        socket.set_read_timeout(Some(TICK))?;
+        let (tx, rx) = channel();
+        let shared = Arc::new(Shared {
+            socket,
+            camera_addr,
+            client_id,
+            camera_id,
+            sending: Default::default(),
+            receiving: Mutex::new(Receiving {
+                rx,
+                buf: vec![],
+                pos: 0,
+            }),
+            closed: AtomicBool::new(false),
+        });
+
+        let thread_shared = shared.clone();
+        let thread = std::thread::spawn(move || {
+            if let Err(e) = thread_shared.run(tx, timeout) {
+                error!("UDP connection failed: {}", e);
+            }
+            thread_shared.closed.store(true, Ordering::Relaxed);
+        });
+
+        Ok(UdpStream {
+            shared,
+            thread: Arc::new(Mutex::new(Some(thread))),
+        })
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.shared.camera_addr
+    }
+
+    /// Tells the camera we are going away and stops the background thread.  Blocked reads
+    /// return end of file.
+    pub fn shutdown(&self) {
+        if !self.shared.closed.swap(true, Ordering::Relaxed) {
+            let disconnect = BcUdp::Discovery(UdpDiscovery {
+                tid: random_i32() as u32,
+                payload: UdpXml {
+                    c2d_disc: Some(Disc {
+                        cid: self.shared.client_id,
+                        did: self.shared.camera_id,
+                    }),
+                    ..Default::default()
+                },
+            });
+            if let Err(e) = self.shared.send(&disconnect) {
+                debug!("Could not send UDP disconnect, ignoring: {}", e);
+            }
+        }
+        if let Some(thread) = self.thread.lock().unwrap().take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Shared {
+    fn send(&self, packet: &BcUdp) -> Result<Vec<u8>> {
+        let buf = packet
+            .serialize(vec![])
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+        self.socket.send_to(&buf, self.camera_addr)?;
+        Ok(buf)
+    }
+
+    /// Body of the background thread
+    fn run(&self, tx: Sender<Vec<u8>>, timeout: Duration) -> Result<()> {
+        let mut buf = vec![0; MTU as usize];
+        // Received data packets that are ahead of the next one we can hand to the reader
+        let mut out_of_order: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+        let mut next_incoming = 0;
+        let mut last_heard = Instant::now();
+        let mut last_ack = Instant::now();
+
+        while !self.closed.load(Ordering::Relaxed) {
+            let mut ack_needed = false;
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, addr)) if addr == self.camera_addr => {
+                    last_heard = Instant::now();
+                    match BcUdp::deserialize(&buf[..len]) {
+                        Ok(BcUdp::Data(data)) if data.connection_id == self.client_id => {
+                            ack_needed = true;
+                            if data.packet_id >= next_incoming {
+                                out_of_order.insert(data.packet_id, data.payload);
+                            }
+                            while let Some(payload) = out_of_order.remove(&next_incoming) {
+                                if tx.send(payload).is_err() {
+                                    // Nobody is reading any more
+                                    return Ok(());
+                                }
+                                next_incoming += 1;
+                            }
+                        }
+                        Ok(BcUdp::Ack(ack)) if ack.connection_id == self.client_id => {
+                            self.handle_ack(&ack);
+                        }
+                        Ok(BcUdp::Discovery(discovery)) if discovery.payload.d2c_disc.is_some() => {
+                            debug!("Camera closed the UDP connection");
+                            return Ok(());
+                        }
+                        Ok(packet) => trace!("Ignoring UDP packet {:?}", packet),
+                        Err(e) => debug!("Ignoring unparseable UDP packet: {}", e),
+                    }
+                }
+                Ok(_) => {}
+                Err(e) if is_timeout(&e) => {}
+                Err(e) => return Err(e),
+            }
+
+            if ack_needed || last_ack.elapsed() > HEARTBEAT_INTERVAL {
+                last_ack = Instant::now();
+                let received = match out_of_order.keys().next_back() {
+                    Some(&last) => (next_incoming..=last)
+                        .map(|id| out_of_order.contains_key(&id) as u8)
+                        .collect(),
+                    None => vec![],
+                };
+                self.send(&BcUdp::Ack(UdpAck {
+                    connection_id: self.camera_id,
+                    packet_id: next_incoming.wrapping_sub(1),
+                    received,
+                }))?;
+            }
+
+            let mut sending = self.sending.lock().unwrap();
+            // The camera need not send anything while the connection is idle, but it must ack
+            // what we send it
+            if let Some(waiting_since) = sending.waiting_since {
+                if std::cmp::max(last_heard, waiting_since).elapsed() > timeout {
+                    return Err(Error::new(
+                        ErrorKind::TimedOut,
+                        "Camera stopped acking UDP packets",
+                    ));
+                }
+            }
+            for (packet, sent_at) in sending.unacked.values_mut() {
+                if sent_at.elapsed() > RESEND_TIMEOUT {
+                    self.socket.send_to(packet, self.camera_addr)?;
+                    *sent_at = Instant::now();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_ack(&self, ack: &UdpAck) {
+        let mut sending = self.sending.lock().unwrap();
+        // The camera acks with the ID before its first packet, which wraps around to u32::MAX
+        // until it has received anything
+        if ack.packet_id != u32::MAX {
+            sending.unacked = sending.unacked.split_off(&(ack.packet_id + 1));
+        }
+        for (offset, &received) in ack.received.iter().enumerate() {
+            if received != 0 {
+                let packet_id = ack.packet_id.wrapping_add(1 + offset as u32);
+                sending.unacked.remove(&packet_id);
+            }
+        }
+        if sending.unacked.is_empty() {
+            sending.waiting_since = None;
+        }
+    }
+}
+
+impl Read for UdpStream {
+    fn read(&mut self, out: &mut [u8]) -> Result<usize> {
+        let mut receiving = self.shared.receiving.lock().unwrap();
+        if receiving.pos == receiving.buf.len() {
+            // The UDP thread decides when the camera has gone away, and then closes the channel
+            receiving.buf = match receiving.rx.recv() {
+                Ok(payload) => payload,
+                // The connection is closed
+                Err(_) => return Ok(0),
+            };
+            receiving.pos = 0;
+        }
+        let len = std::cmp::min(out.len(), receiving.buf.len() - receiving.pos);
+        out[..len].copy_from_slice(&receiving.buf[receiving.pos..receiving.pos + len]);
+        receiving.pos += len;
+        Ok(len)
+    }
+}
+
+impl Write for UdpStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(Error::new(
+                ErrorKind::NotConnected,
+                "UDP connection is closed",
+            ));
+        }
+        let max_payload = MTU as usize - UdpData::HEADER_LEN;
+        let mut sending = self.shared.sending.lock().unwrap();
+        if sending.unacked.is_empty() && !buf.is_empty() {
+            sending.waiting_since = Some(Instant::now());
+        }
+        for chunk in buf.chunks(max_payload) {
+            let packet_id = sending.next_packet_id;
+            sending.next_packet_id += 1;
+            let packet = self.shared.send(&BcUdp::Data(UdpData {
+                connection_id: self.shared.camera_id,
+                packet_id,
+                payload: chunk.to_vec(),
+            }))?;
+            sending.unacked.insert(packet_id, (packet, Instant::now()));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(super) fn is_timeout(e: &Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Connection and transaction IDs only need to differ between connections, so the clock is a
+/// good enough source of randomness
+pub(super) fn random_i32() -> i32 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    (nanos ^ std::process::id().rotate_left(16)) as i32 & 0x7fff_ffff
+}
@@ -0,0 +1,30 @@
+const XML_KEY: [u32; 8] = [
+    0x1f2d3c4b, 0x5a6c7f8d, 0x38172e4b, 0x8271635a, 0x863f1a2b, 0xa5c6f7d8, 0x8371e1b4, 0x17f2d3a5,
+];
+
+/// Decrypts the XML of a discovery packet.  The key is offset by the packet's transaction ID.
+pub fn decrypt(offset: u32, buf: &[u8]) -> Vec<u8> {
+    let key: Vec<u8> = XML_KEY
+        .iter()
+        .flat_map(|word| word.wrapping_add(offset).to_le_bytes().to_vec())
+        .collect();
+    buf.iter()
+        .zip(key.iter().cycle())
+        .map(|(byte, key)| byte ^ key)
+        .collect()
+}
+
+pub fn encrypt(offset: u32, buf: &[u8]) -> Vec<u8> {
+    // Encrypt is the same as decrypt
+    decrypt(offset, buf)
+}
+
+#[test]
+fn test_udp_crypto_roundtrip() {
+    let sample = b"<P2P><C2D_DISC><cid>1</cid><did>2</did></C2D_DISC></P2P>";
+    let encrypted = encrypt(0x1234, &sample[..]);
+    assert_ne!(&encrypted[..], &sample[..]);
+    assert_eq!(decrypt(0x1234, &encrypted), &sample[..]);
+    // A different transaction ID gives a different key
+    assert_ne!(decrypt(0x1235, &encrypted), &sample[..]);
+}
@@ -0,0 +1,93 @@
+use super::crypto;
+use super::model::*;
+use err_derive::Error;
+use nom::IResult;
+use nom::{bytes::complete::take, number::complete::*};
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(display = "Parsing error")]
+    NomError(&'static str),
+    #[error(display = "Discovery packet checksum mismatch")]
+    ChecksumError,
+    #[error(display = "Discovery packet XML error: {}", _0)]
+    XmlError(String),
+}
+
+type NomErrorType<'a> = nom::error::Error<&'a [u8]>;
+
+impl<'a> From<nom::Err<NomErrorType<'a>>> for Error {
+    fn from(k: nom::Err<NomErrorType<'a>>) -> Self {
+        let reason = match k {
+            nom::Err::Error(_) => "Nom Error",
+            nom::Err::Failure(_) => "Nom Failure",
+            _ => "Unknown Nom error",
+        };
+        Error::NomError(reason)
+    }
+}
+
+impl BcUdp {
+    /// Parses one UDP packet.  Unlike the TCP transport, each packet is self-contained.
+    pub fn deserialize(buf: &[u8]) -> Result<BcUdp, Error> {
+        let (buf, magic) = le_u32(buf)?;
+        match magic {
+            MAGIC_HEADER_UDP_DISCOVERY => Ok(BcUdp::Discovery(udp_discovery(buf)?)),
+            MAGIC_HEADER_UDP_DATA => Ok(BcUdp::Data(udp_data(buf)?.1)),
+            MAGIC_HEADER_UDP_ACK => Ok(BcUdp::Ack(udp_ack(buf)?.1)),
+            _ => Err(Error::NomError("Unknown magic number")),
+        }
+    }
+}
+
+fn udp_discovery(buf: &[u8]) -> Result<UdpDiscovery, Error> {
+    let (buf, payload_len) = le_u32(buf)?;
+    let (buf, _unknown) = le_u32(buf)?;
+    let (buf, tid) = le_u32(buf)?;
+    let (buf, checksum) = le_u32(buf)?;
+    let (_, enc_payload) = take(payload_len)(buf)?;
+
+    if crc32fast::hash(enc_payload) != checksum {
+        return Err(Error::ChecksumError);
+    }
+    let payload = crypto::decrypt(tid, enc_payload);
+    let payload = UdpXml::try_parse(payload.as_slice()).map_err(Error::XmlError)?;
+
+    Ok(UdpDiscovery { tid, payload })
+}
+
+fn udp_data(buf: &[u8]) -> IResult<&[u8], UdpData> {
+    let (buf, connection_id) = le_i32(buf)?;
+    let (buf, _unknown) = le_u32(buf)?;
+    let (buf, packet_id) = le_u32(buf)?;
+    let (buf, payload_len) = le_u32(buf)?;
+    let (buf, payload) = take(payload_len)(buf)?;
+
+    Ok((
+        buf,
+        UdpData {
+            connection_id,
+            packet_id,
+            payload: payload.to_vec(),
+        },
+    ))
+}
+
+fn udp_ack(buf: &[u8]) -> IResult<&[u8], UdpAck> {
+    let (buf, connection_id) = le_i32(buf)?;
+    let (buf, _unknown) = le_u32(buf)?;
+    let (buf, _group_id) = le_u32(buf)?;
+    let (buf, packet_id) = le_u32(buf)?;
+    let (buf, _unknown) = le_u32(buf)?;
+    let (buf, payload_len) = le_u32(buf)?;
+    let (buf, received) = take(payload_len)(buf)?;
+
+    Ok((
+        buf,
+        UdpAck {
+            connection_id,
+            packet_id,
+            received: received.to_vec(),
+        },
+    ))
+}
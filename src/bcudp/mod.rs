@@ -0,0 +1,20 @@
+//! The Baichuan UDP transport carries the same Baichuan messages as the TCP transport, for
+//! cameras that can only be reached by their UID.  Every UDP packet starts with a 32-bit magic
+//! number that says what kind of packet it is:
+//!
+//! - Discovery packets carry XML, "encrypted" with a simple XOR routine.  They are used to find a
+//!   camera by UID and to set up and tear down a connection.
+//! - Data packets carry a numbered slice of the Baichuan TCP byte stream.
+//! - Ack packets tell the other side which data packets have arrived, so that lost packets can be
+//!   sent again.
+//!
+//! There is no documentation for this protocol; everything here comes from packet captures of the
+//! official clients.
+
+pub mod model;
+
+pub mod de;
+pub mod ser;
+pub mod xml;
+
+mod crypto;
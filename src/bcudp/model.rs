@@ -0,0 +1,50 @@
+pub use super::xml::*;
+
+pub const MAGIC_HEADER_UDP_DISCOVERY: u32 = 0x2a87cf3a;
+pub const MAGIC_HEADER_UDP_DATA: u32 = 0x2a87cf10;
+pub const MAGIC_HEADER_UDP_ACK: u32 = 0x2a87cf20;
+
+/// The ports that cameras listen on for discovery broadcasts
+pub const DISCOVERY_PORTS: [u16; 2] = [2015, 2018];
+
+/// The largest UDP packet the official clients send, which we also advertise as our MTU
+pub const MTU: u32 = 1350;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum BcUdp {
+    Discovery(UdpDiscovery),
+    Data(UdpData),
+    Ack(UdpAck),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UdpDiscovery {
+    /// Transaction ID, chosen by whoever starts the exchange and echoed in the reply.  It is also
+    /// the offset of the XML encryption.
+    pub tid: u32,
+    pub payload: UdpXml,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UdpData {
+    /// The connection ID chosen by the receiver of this packet during the discovery handshake
+    pub connection_id: i32,
+    /// Sequence number of this packet, counting up from 0 in each direction
+    pub packet_id: u32,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UdpAck {
+    pub connection_id: i32,
+    /// The highest packet_id up to which every packet has arrived
+    pub packet_id: u32,
+    /// One byte per packet after `packet_id`: 1 if it has arrived out of order, 0 if it is
+    /// still missing.  Empty if nothing has arrived out of order.
+    pub received: Vec<u8>,
+}
+
+impl UdpData {
+    /// Size of the data packet header; a data packet's payload is at most MTU minus this
+    pub const HEADER_LEN: usize = 20;
+}
@@ -0,0 +1,111 @@
+use super::crypto;
+use super::model::*;
+use cookie_factory::bytes::*;
+use cookie_factory::sequence::tuple;
+use cookie_factory::{combinator::*, gen};
+use cookie_factory::{GenError, SerializeFn};
+use std::io::Write;
+
+pub type Error = GenError;
+
+impl BcUdp {
+    pub fn serialize<W: Write>(&self, buf: W) -> Result<W, GenError> {
+        let (buf, _n) = match self {
+            BcUdp::Discovery(discovery) => {
+                let xml_bytes = discovery
+                    .payload
+                    .serialize(vec![])
+                    .map_err(|_| GenError::CustomError(0))?;
+                let enc_bytes = crypto::encrypt(discovery.tid, &xml_bytes);
+                gen(udp_discovery(discovery.tid, enc_bytes), buf)?
+            }
+            BcUdp::Data(data) => gen(udp_data(data), buf)?,
+            BcUdp::Ack(ack) => gen(udp_ack(ack), buf)?,
+        };
+        Ok(buf)
+    }
+}
+
+fn udp_discovery<W: Write>(tid: u32, enc_payload: Vec<u8>) -> impl SerializeFn<W> {
+    let checksum = crc32fast::hash(&enc_payload);
+    tuple((
+        le_u32(MAGIC_HEADER_UDP_DISCOVERY),
+        le_u32(enc_payload.len() as u32),
+        le_u32(1),
+        le_u32(tid),
+        le_u32(checksum),
+        slice(enc_payload),
+    ))
+}
+
+fn udp_data<W: Write>(data: &'_ UdpData) -> impl SerializeFn<W> + '_ {
+    tuple((
+        le_u32(MAGIC_HEADER_UDP_DATA),
+        le_i32(data.connection_id),
+        le_u32(0),
+        le_u32(data.packet_id),
+        le_u32(data.payload.len() as u32),
+        slice(&data.payload),
+    ))
+}
+
+fn udp_ack<W: Write>(ack: &'_ UdpAck) -> impl SerializeFn<W> + '_ {
+    tuple((
+        le_u32(MAGIC_HEADER_UDP_ACK),
+        le_i32(ack.connection_id),
+        le_u32(0),
+        le_u32(0),
+        le_u32(ack.packet_id),
+        le_u32(0),
+        le_u32(ack.received.len() as u32),
+        slice(&ack.received),
+    ))
+}
+
+#[test]
+fn test_udp_data_roundtrip() {
+    let msg = BcUdp::Data(UdpData {
+        connection_id: 80,
+        packet_id: 7,
+        payload: vec![0xf0, 0xde, 0xbc, 0x0a, 0x01],
+    });
+    let ser_buf = msg.serialize(vec![]).unwrap();
+    assert_eq!(ser_buf.len(), UdpData::HEADER_LEN + 5);
+    assert_eq!(BcUdp::deserialize(&ser_buf).unwrap(), msg);
+}
+
+#[test]
+fn test_udp_ack_roundtrip() {
+    let msg = BcUdp::Ack(UdpAck {
+        connection_id: 82000,
+        packet_id: 41,
+        received: vec![0, 1, 1],
+    });
+    let ser_buf = msg.serialize(vec![]).unwrap();
+    assert_eq!(BcUdp::deserialize(&ser_buf).unwrap(), msg);
+}
+
+#[test]
+fn test_udp_discovery_roundtrip() {
+    let msg = BcUdp::Discovery(UdpDiscovery {
+        tid: 0x5eed,
+        payload: UdpXml {
+            c2d_c: Some(C2dC {
+                uid: "95270000ABCDEFGH".to_string(),
+                cli: Cli { port: 51234 },
+                cid: 82000,
+                mtu: MTU,
+                debug: 0,
+                p: "MAC".to_string(),
+            }),
+            ..Default::default()
+        },
+    });
+    let mut ser_buf = msg.serialize(vec![]).unwrap();
+    assert_eq!(BcUdp::deserialize(&ser_buf).unwrap(), msg);
+
+    // Corrupting the payload is caught by the checksum
+    let last = ser_buf.len() - 1;
+    ser_buf[last] ^= 0xff;
+    assert!(BcUdp::deserialize(&ser_buf).is_err());
+}
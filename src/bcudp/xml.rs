@@ -0,0 +1,146 @@
+// YaSerde currently macro-expands names like __type__value from type_
+#![allow(non_snake_case)]
+
+use std::io::{Read, Write};
+// YaSerde is currently naming the traits and the derive macros identically
+use yaserde::{ser::Config, YaDeserialize, YaSerialize};
+use yaserde_derive::{YaDeserialize, YaSerialize};
+
+#[cfg(test)]
+use indoc::indoc;
+
+/// The XML carried by discovery packets.  Each packet carries exactly one of these messages.
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+#[yaserde(rename = "P2P")]
+pub struct UdpXml {
+    /// Client to device: scan, broadcast to find every camera on the network
+    #[yaserde(rename = "C2D_S")]
+    pub c2d_s: Option<C2dS>,
+    /// Device to client: scan reply
+    #[yaserde(rename = "D2C_S")]
+    pub d2c_s: Option<D2cS>,
+    /// Client to device: connection request, broadcast to find a camera by UID
+    #[yaserde(rename = "C2D_C")]
+    pub c2d_c: Option<C2dC>,
+    /// Device to client: connection reply
+    #[yaserde(rename = "D2C_C_R")]
+    pub d2c_c_r: Option<D2cCR>,
+    /// Client to device: disconnect
+    #[yaserde(rename = "C2D_DISC")]
+    pub c2d_disc: Option<Disc>,
+    /// Device to client: disconnect
+    #[yaserde(rename = "D2C_DISC")]
+    pub d2c_disc: Option<Disc>,
+}
+
+impl UdpXml {
+    pub fn try_parse(s: impl Read) -> Result<Self, String> {
+        yaserde::de::from_reader(s)
+    }
+    pub fn serialize<W: Write>(&self, w: W) -> Result<W, String> {
+        yaserde::ser::serialize_with_writer(self, w, &Config::default())
+    }
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct C2dS {
+    pub cli: Cli,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct D2cS {
+    pub uid: String,
+    /// The TCP port for Baichuan connections, usually 9000
+    pub port: u16,
+    #[yaserde(rename = "type")]
+    pub type_: String,
+    /// Firmware version
+    pub ver: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct C2dC {
+    pub uid: String,
+    pub cli: Cli,
+    /// Our connection ID, which the camera puts on the packets it sends us
+    pub cid: i32,
+    pub mtu: u32,
+    pub debug: u32,
+    /// The client platform; the official clients send "MAC" or "WIN"
+    pub p: String,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct Cli {
+    /// The local UDP port the camera should reply to
+    pub port: u16,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct D2cCR {
+    /// 0 if the camera accepted the connection
+    pub rsp: i32,
+    pub cid: i32,
+    /// The camera's connection ID, which we put on the packets we send it
+    pub did: i32,
+}
+
+#[derive(PartialEq, Eq, Default, Debug, Clone, YaDeserialize, YaSerialize)]
+pub struct Disc {
+    pub cid: i32,
+    pub did: i32,
+}
+
+#[test]
+fn test_d2c_s_deser() {
+    let sample = indoc!(
+        r#"
+        <P2P>
+        <D2C_S>
+        <uid>95270000ABCDEFGH</uid>
+        <port>9000</port>
+        <type>RLC-410W</type>
+        <ver>v3.0.0.136_20121102</ver>
+        </D2C_S>
+        </P2P>"#
+    );
+    let b: UdpXml = yaserde::de::from_str(sample).unwrap();
+    assert_eq!(
+        b.d2c_s,
+        Some(D2cS {
+            uid: "95270000ABCDEFGH".to_string(),
+            port: 9000,
+            type_: "RLC-410W".to_string(),
+            ver: "v3.0.0.136_20121102".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_d2c_c_r_deser() {
+    let sample = indoc!(
+        r#"
+        <P2P>
+        <D2C_C_R>
+        <timer>
+        <def>3000</def>
+        <hb>0</hb>
+        <hbt>0</hbt>
+        </timer>
+        <rsp>0</rsp>
+        <cid>82000</cid>
+        <did>80</did>
+        </D2C_C_R>
+        </P2P>"#
+    );
+    let b: UdpXml = yaserde::de::from_str(sample).unwrap();
+    assert_eq!(
+        b.d2c_c_r,
+        Some(D2cCR {
+            rsp: 0,
+            cid: 82000,
+            did: 80,
+        })
+    );
+    assert_eq!(b.c2d_c, None);
+}
@@ -0,0 +1,349 @@
+//! Records a clip whenever the camera detects motion.  The last few seconds of the stream are
+//! kept in memory, so that each clip starts from before the motion did.  Clips start and end on
+//! keyframes, so every clip can be played by itself.
+//!
+//! Clips go in a `clips` directory next to the camera's recordings, and are deleted by the same
+//! limits.  Finishing a clip waits for its muxer, so it is done on a thread of its own rather than
+//! holding up the camera's stream.
+use crate::config::{CameraConfig, ClipConfig, RecordingConfig};
+use crate::record::{audio_format, container, prune, recording_path, write_to_muxer};
+use crossbeam::channel::{self, Receiver, Sender};
+use log::*;
+use neolink::bc_protocol::{BcCamera, MediaData, MediaDataKind, MotionStatus};
+use neolink::gst::Muxer;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+pub struct ClipRecorder {
+    name: String,
+    config: RecordingConfig,
+    dir: PathBuf,
+    pre_roll: PreRoll<MediaData>,
+    motion: Receiver<()>,
+    timer: ClipTimer,
+    clip: Option<Clip>,
+    /// Sends the muxers of recorded clips to the thread that finishes them
+    finisher: Option<Sender<Muxer>>,
+    finisher_thread: Option<JoinHandle<()>>,
+}
+
+struct Clip {
+    muxer: Muxer,
+    start: Instant,
+}
+
+impl ClipRecorder {
+    /// Creates a recorder that starts a clip each time something is sent to `motion`
+    pub fn new(
+        camera_config: &CameraConfig,
+        recording_config: &RecordingConfig,
+        clip_config: &ClipConfig,
+        motion: Receiver<()>,
+    ) -> ClipRecorder {
+        let name = camera_config.name.clone();
+        let (finisher, recorded) = channel::unbounded::<Muxer>();
+        let finisher_thread = {
+            let name = name.clone();
+            std::thread::spawn(move || {
+                for muxer in recorded {
+                    match muxer.finish() {
+                        Ok(()) => debug!("{}: Finished recording clip", name),
+                        Err(e) => error!("{}: Could not finish clip: {}", name, e),
+                    }
+                }
+            })
+        };
+        ClipRecorder {
+            name,
+            config: recording_config.clone(),
+            dir: recording_config
+                .path
+                .join(&camera_config.name)
+                .join("clips"),
+            pre_roll: PreRoll::new(Duration::from_secs(clip_config.pre_roll)),
+            motion,
+            timer: ClipTimer::new(Duration::from_secs(clip_config.post_roll)),
+            clip: None,
+            finisher: Some(finisher),
+            finisher_thread: Some(finisher_thread),
+        }
+    }
+
+    /// Buffers a media packet, and records it if a clip is in progress.  If recording fails, the
+    /// error is logged and the clip is abandoned, but the stream itself carries on.
+    pub fn write_media(&mut self, media: &MediaData) {
+        let now = Instant::now();
+        if self.motion.try_iter().count() > 0 {
+            self.timer.motion(now);
+        }
+
+        let kind = media.kind();
+        let keyframe = kind == MediaDataKind::VideoDataIframe;
+        if self.timer.should_finish(now, keyframe) {
+            self.finish_clip();
+        }
+
+        match kind {
+            MediaDataKind::VideoDataIframe
+            | MediaDataKind::VideoDataPframe
+            | MediaDataKind::AudioDataAac
+            | MediaDataKind::AudioDataAdpcm => self.pre_roll.push(now, media.clone(), keyframe),
+            _ => return,
+        }
+
+        let result = if let Some(clip) = &self.clip {
+            write_to_muxer(&clip.muxer, media, now - clip.start)
+        } else if self.timer.should_start(now, !self.pre_roll.is_empty()) {
+            // The pre-roll is only empty until the first keyframe arrives
+            self.start_clip()
+        } else {
+            Ok(())
+        };
+        if let Err(e) = result {
+            error!("{}: Could not record clip: {}", self.name, e);
+            self.finish_clip();
+            self.timer.abandon();
+        }
+    }
+
+    /// Starts a clip with everything in the pre-roll buffer, which always begins with a keyframe
+    fn start_clip(&mut self) -> io::Result<()> {
+        let (start, first) = self.pre_roll.front().unwrap();
+        let video_format = first
+            .media_format()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown video format"))?;
+        let audio_kind = self
+            .pre_roll
+            .iter()
+            .map(|(_, media)| media.kind())
+            .find(|kind| {
+                *kind == MediaDataKind::AudioDataAac || *kind == MediaDataKind::AudioDataAdpcm
+            });
+
+        fs::create_dir_all(&self.dir)?;
+        if let Err(e) = prune(&self.dir, &self.config) {
+            warn!("{}: Could not delete old clips: {}", self.name, e);
+        }
+        let path = recording_path(&self.dir, &self.config);
+        info!(
+            "{}: Motion detected, recording {}",
+            self.name,
+            path.display()
+        );
+
+        let muxer = Muxer::new_file(
+            &path,
+            container(&self.config),
+            video_format,
+            audio_format(audio_kind),
+        )?;
+        for (time, media) in self.pre_roll.iter() {
+            write_to_muxer(&muxer, media, time - start)?;
+        }
+        self.clip = Some(Clip { muxer, start });
+        Ok(())
+    }
+
+    fn finish_clip(&mut self) {
+        if let Some(clip) = self.clip.take() {
+            if let Some(finisher) = &self.finisher {
+                let _ = finisher.send(clip.muxer);
+            }
+        }
+    }
+}
+
+impl Drop for ClipRecorder {
+    fn drop(&mut self) {
+        // The clip in progress is finished with what it has so far, and waited for along with
+        // the others
+        self.finish_clip();
+        self.finisher = None;
+        if let Some(thread) = self.finisher_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Tells the ClipRecorder about each new motion alarm, until the camera connection fails.  If the
+/// camera will not send motion alarms, no clips are recorded, but the session carries on.
+pub fn watch_motion(
+    camera: &BcCamera,
+    camera_config: &CameraConfig,
+    motion: Sender<()>,
+) -> Result<(), neolink::Error> {
+    let mut motion_sub = match camera.listen_on_motion() {
+        Ok(motion_sub) => motion_sub,
+        Err(e) if e.is_connection_lost() => return Err(e),
+        Err(e) => {
+            warn!(
+                "{}: Not recording clips, as the camera will not send motion alarms: {}",
+                camera_config.name, e
+            );
+            return Ok(());
+        }
+    };
+    loop {
+        let started = motion_sub.next_motion()?.iter().any(|event| {
+            event.channel_id == camera_config.channel_id && event.status == MotionStatus::Start
+        });
+        if started {
+            let _ = motion.send(());
+        }
+    }
+}
+
+/// Decides when clips start and end.  Motion starts a clip, or keeps the one in progress going for
+/// another `post_roll`.
+struct ClipTimer {
+    post_roll: Duration,
+    /// Motion was seen while no clip was being recorded
+    triggered: bool,
+    /// When the clip in progress will end, if there is one
+    end: Option<Instant>,
+}
+
+impl ClipTimer {
+    fn new(post_roll: Duration) -> ClipTimer {
+        ClipTimer {
+            post_roll,
+            triggered: false,
+            end: None,
+        }
+    }
+
+    fn motion(&mut self, now: Instant) {
+        match &mut self.end {
+            Some(end) => *end = now + self.post_roll,
+            None => self.triggered = true,
+        }
+    }
+
+    /// Whether to start a clip now.  `ready` is false until there is something to record.
+    fn should_start(&mut self, now: Instant, ready: bool) -> bool {
+        if self.end.is_none() && self.triggered && ready {
+            self.triggered = false;
+            self.end = Some(now + self.post_roll);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether to finish the clip in progress.  Clips only finish on keyframes.
+    fn should_finish(&mut self, now: Instant, keyframe: bool) -> bool {
+        match self.end {
+            Some(end) if keyframe && now >= end => {
+                self.end = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn abandon(&mut self) {
+        self.end = None;
+    }
+}
+
+/// Keeps the packets from at least the last `length` of the stream, starting from a keyframe
+struct PreRoll<T> {
+    length: Duration,
+    packets: VecDeque<(Instant, T, bool)>,
+}
+
+impl<T> PreRoll<T> {
+    fn new(length: Duration) -> PreRoll<T> {
+        PreRoll {
+            length,
+            packets: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, now: Instant, packet: T, keyframe: bool) {
+        // Nothing before the first keyframe can be decoded
+        if self.packets.is_empty() && !keyframe {
+            return;
+        }
+        self.packets.push_back((now, packet, keyframe));
+
+        // Drop everything before the last keyframe that still leaves us `length` of the stream
+        let cutoff = now.checked_sub(self.length);
+        let last_keyframe = self
+            .packets
+            .iter()
+            .rposition(|(time, _, keyframe)| *keyframe && Some(*time) <= cutoff);
+        if let Some(last_keyframe) = last_keyframe {
+            self.packets.drain(..last_keyframe);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    fn front(&self) -> Option<(Instant, &T)> {
+        self.packets
+            .front()
+            .map(|(time, packet, _)| (*time, packet))
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (Instant, &T)> {
+        self.packets.iter().map(|(time, packet, _)| (*time, packet))
+    }
+}
+
+#[test]
+fn test_pre_roll() {
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let mut pre_roll = PreRoll::new(Duration::from_secs(5));
+
+    // Packets before the first keyframe are useless
+    pre_roll.push(at(0), 0, false);
+    assert!(pre_roll.is_empty());
+
+    // Keyframes every 4 seconds
+    for secs in 1..=10 {
+        pre_roll.push(at(secs), secs, secs % 4 == 0);
+    }
+
+    // At 10s, the keyframe at 4s is the last one at least 5s ago
+    let packets: Vec<_> = pre_roll.iter().map(|(_, packet)| *packet).collect();
+    assert_eq!(packets, vec![4, 5, 6, 7, 8, 9, 10]);
+    assert_eq!(pre_roll.front(), Some((at(4), &4)));
+}
+
+#[test]
+fn test_clip_timer() {
+    let start = Instant::now();
+    let at = |secs| start + Duration::from_secs(secs);
+    let mut timer = ClipTimer::new(Duration::from_secs(5));
+
+    // Motion at 1s, and again at 3s while the clip is recording, with keyframes every 2 seconds
+    let mut starts = vec![];
+    let mut finishes = vec![];
+    for secs in 0..30 {
+        if secs == 1 || secs == 3 {
+            timer.motion(at(secs));
+        }
+        if timer.should_finish(at(secs), secs % 2 == 0) {
+            finishes.push(secs);
+        }
+        if timer.should_start(at(secs), true) {
+            starts.push(secs);
+        }
+    }
+    // The second motion only makes the one clip longer
+    assert_eq!(starts, vec![1]);
+    assert_eq!(finishes, vec![8]);
+
+    // Motion before there is anything to record waits for it
+    timer.motion(at(30));
+    assert!(!timer.should_start(at(30), false));
+    assert!(timer.should_start(at(31), true));
+}
@@ -1,11 +1,113 @@
+use crate::settings::{parse_date_format, parse_time_format, parse_time_zone};
+use neolink::bc_protocol::{DateFormat, TimeFormat};
 use std::path::PathBuf;
 use structopt::StructOpt;
+use time::UtcOffset;
 
 /// A standards-compliant bridge to Reolink IP cameras
 ***REMOVED***[derive(StructOpt, Debug)]
 ***REMOVED***[structopt(name = "neolink")]
 pub struct Opt {
-    /// main configuration file
+    /// main configuration file; required unless a subcommand is given
     ***REMOVED***[structopt(short, long, parse(from_os_str))]
-    pub config: PathBuf,
+    pub config: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub cmd: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Finds cameras on the local network and prints their addresses and UIDs
+    Discover {
+        /// how long to wait for cameras to answer, in seconds
+        #[structopt(short, long, default_value = "3")]
+        timeout: u64,
+
+        /// also print a config section for each camera, ready to paste into the config file
+        #[structopt(long)]
+        toml: bool,
+    },
+
+    /// Lists the recordings on a camera's SD card
+    Recordings {
+        /// the name of the camera in the config file
+        camera: String,
+
+        /// list recordings from this time on, in the camera's time zone, as 2020-10-11 or
+        /// 2020-10-11T21:30:00; defaults to a day ago
+        #[structopt(long)]
+        start: Option<String>,
+
+        /// list recordings up to this time; defaults to now
+        #[structopt(long)]
+        end: Option<String>,
+
+        /// mainStream or subStream
+        #[structopt(long, default_value = "mainStream")]
+        stream: String,
+    },
+
+    /// Downloads a recording from a camera's SD card as an MP4 file
+    Download {
+        /// the name of the camera in the config file
+        camera: String,
+
+        /// the name of the recording, as given by the recordings subcommand
+        name: String,
+
+        /// where to save the file
+        #[structopt(parse(from_os_str))]
+        output: PathBuf,
+    },
+
+    /// Shows a camera's general settings, or changes the ones that are given
+    ConfigCamera {
+        /// the name of the camera in the config file
+        camera: String,
+
+        /// the camera's own name, which it shows on the picture and in the Reolink app
+        #[structopt(long)]
+        name: Option<String>,
+
+        /// the order of the date shown on the picture: DMY, MDY or YMD
+        #[structopt(long, parse(try_from_str = parse_date_format))]
+        date_format: Option<DateFormat>,
+
+        /// 12 or 24, for the clock shown on the picture
+        #[structopt(long, parse(try_from_str = parse_time_format))]
+        time_format: Option<TimeFormat>,
+
+        /// the language of the camera's voice prompts, such as English
+        #[structopt(long)]
+        language: Option<String>,
+
+        /// the camera's time zone, as an offset from UTC such as +01:00 or -05:30
+        #[structopt(long, allow_hyphen_values = true, parse(try_from_str = parse_time_zone))]
+        time_zone: Option<UtcOffset>,
+    },
+
+    /// Reboots a camera
+    Reboot {
+        /// the name of the camera in the config file
+        camera: String,
+
+        /// reboot the camera; without this, nothing is done
+        #[structopt(long)]
+        confirm: bool,
+    },
+
+    /// Installs a firmware file from Reolink on a camera, which then reboots
+    Upgrade {
+        /// the name of the camera in the config file
+        camera: String,
+
+        /// the firmware file, with the name Reolink gave it
+        #[structopt(parse(from_os_str))]
+        firmware: PathBuf,
+
+        /// upgrade the camera; without this, nothing is done
+        #[structopt(long)]
+        confirm: bool,
+    },
 }
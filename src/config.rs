@@ -2,6 +2,7 @@ use lazy_static::lazy_static;
 use regex::Regex;
 use serde::Deserialize;
 use std::clone::Clone;
+use std::path::PathBuf;
 use std::time::Duration;
 use validator::{Validate, ValidationError};
 use validator_derive::Validate;
@@ -9,6 +10,8 @@ use validator_derive::Validate;
 lazy_static! {
     static ref RE_STREAM_SRC: Regex = Regex::new(r"^(mainStream|subStream|both)$").unwrap();
     static ref RE_TLS_CLIENT_AUTH: Regex = Regex::new(r"^(none|request|require)$").unwrap();
+    static ref RE_RECORDING_FORMAT: Regex = Regex::new(r"^(mp4|mkv)$").unwrap();
+    static ref RE_TIME_SYNC_MODE: Regex = Regex::new(r"^(drift|unset|off)$").unwrap();
 }
 
 ***REMOVED***[derive(Debug, Deserialize, Validate, Clone)]
@@ -26,6 +29,9 @@ pub struct Config {
     ***REMOVED***[serde(default = "default_certificate")]
     pub certificate: Option<String>,
 
+    /// Port for the HTTP server, which is off unless this is set
+    pub http_bind_port: Option<u16>,
+
     ***REMOVED***[validate(regex(
         path = "RE_TLS_CLIENT_AUTH",
         message = "Incorrect tls auth",
@@ -37,14 +43,23 @@ pub struct Config {
     ***REMOVED***[validate]
     ***REMOVED***[serde(default)]
     pub users: Vec<UserConfig>,
+
+    /// Where and how the cameras with `record = true` are recorded
+    #[validate]
+    #[serde(default)]
+    pub recording: RecordingConfig,
 }
 
-***REMOVED***[derive(Debug, Deserialize, Validate, Clone)]
+***REMOVED***[derive(Debug, Deserialize, Validate, Clone, PartialEq)]
+#[validate(schema(function = "validate_camera_config"))]
 pub struct CameraConfig {
     pub name: String,
 
     ***REMOVED***[serde(rename = "address")]
-    pub camera_addr: String,
+    pub camera_addr: Option<String>,
+
+    /// Connect over UDP to the camera with this UID instead of to an address
+    pub uid: Option<String>,
 
     pub username: String,
     pub password: Option<String>,
@@ -68,9 +83,145 @@ pub struct CameraConfig {
     ***REMOVED***[validate(range(min = 0, max = 31, message = "Invalid channel", code = "channel_id"))]
     ***REMOVED***[serde(default = "default_channel_id")]
     pub channel_id: u8,
+
+    pub mqtt: Option<MqttConfig>,
+
+    /// Seconds between keepalive pings to the camera, or 0 to only notice a dead connection when
+    /// the video stops
+    #[serde(default = "default_keepalive_interval")]
+    pub keepalive_interval: u64,
+
+    /// Play audio from RTSP clients' ONVIF backchannels on the camera's speaker
+    #[serde(default)]
+    pub talkback: bool,
+
+    /// Record the camera's stream to files, as set up in the recording config
+    #[serde(default)]
+    pub record: bool,
+
+    /// Record a clip whenever the camera detects motion
+    pub clips: Option<ClipConfig>,
+
+    /// Serve the camera as HLS from the HTTP server, for browsers
+    #[serde(default)]
+    pub hls: bool,
+
+    /// Only connect to the camera while RTSP clients are playing it
+    #[serde(default)]
+    pub idle_disconnect: bool,
+
+    /// Seconds to stay connected after the last RTSP client has gone, with idle_disconnect
+    #[serde(default = "default_idle_timeout")]
+    pub idle_timeout: u64,
+
+    /// How the camera's clock is kept right
+    #[validate]
+    #[serde(default)]
+    pub time_sync: TimeSyncConfig,
 }
 
-***REMOVED***[derive(Debug, Deserialize, Validate, Clone)]
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct ClipConfig {
+    /// Seconds of video from before the motion to include
+    #[serde(default = "default_pre_roll")]
+    pub pre_roll: u64,
+
+    /// Seconds of video to record after the motion starts
+    #[serde(default = "default_post_roll")]
+    pub post_roll: u64,
+}
+
+#[derive(Debug, Deserialize, Validate, Clone, PartialEq)]
+pub struct TimeSyncConfig {
+    /// "unset" to only set the clock when the camera has lost the time, "drift" to also set it
+    /// whenever it is wrong, or "off"
+    #[validate(regex(
+        path = "RE_TIME_SYNC_MODE",
+        message = "Incorrect time sync mode",
+        code = "mode"
+    ))]
+    #[serde(default = "default_time_sync_mode")]
+    pub mode: String,
+
+    /// Seconds that the camera's clock may be out by before it is set
+    #[serde(default = "default_max_drift")]
+    pub max_drift: u64,
+
+    /// Seconds between checks of the clock while connected, or 0 to only check on connecting
+    #[serde(default = "default_time_sync_interval")]
+    pub interval: u64,
+
+    /// Whether to change the camera's time zone to this computer's whenever they differ, rather
+    /// than leave the camera in its own
+    #[serde(default)]
+    pub set_time_zone: bool,
+}
+
+impl Default for TimeSyncConfig {
+    fn default() -> Self {
+        TimeSyncConfig {
+            mode: default_time_sync_mode(),
+            max_drift: default_max_drift(),
+            interval: default_time_sync_interval(),
+            set_time_zone: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct MqttConfig {
+    #[serde(alias = "server")]
+    pub broker_addr: String,
+
+    #[serde(default = "default_mqtt_port")]
+    pub port: u16,
+
+    pub credentials: Option<(String, String)>,
+
+    #[serde(default = "default_mqtt_topic_prefix")]
+    pub topic_prefix: String,
+}
+
+#[derive(Debug, Deserialize, Validate, Clone, PartialEq)]
+pub struct RecordingConfig {
+    /// Each camera records into a directory of its own under this one
+    #[serde(default = "default_recording_path")]
+    pub path: PathBuf,
+
+    #[validate(regex(
+        path = "RE_RECORDING_FORMAT",
+        message = "Incorrect recording format",
+        code = "format"
+    ))]
+    #[serde(default = "default_recording_format")]
+    pub format: String,
+
+    /// Seconds of video in each file
+    #[validate(range(min = 1, message = "Invalid segment length", code = "segment_length"))]
+    #[serde(default = "default_segment_length")]
+    pub segment_length: u64,
+
+    /// Delete recordings that are older than this many hours
+    pub max_age_hours: Option<u64>,
+
+    /// Delete the oldest recordings when a camera's recordings take up more than this many
+    /// megabytes
+    pub max_size_mb: Option<u64>,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        RecordingConfig {
+            path: default_recording_path(),
+            format: default_recording_format(),
+            segment_length: default_segment_length(),
+            max_age_hours: None,
+            max_size_mb: None,
+        }
+    }
+}
+
+***REMOVED***[derive(Debug, Deserialize, Validate, Clone, PartialEq)]
 pub struct UserConfig {
     ***REMOVED***[validate(custom = "validate_username")]
     ***REMOVED***[serde(alias = "username")]
@@ -104,6 +255,62 @@ fn default_channel_id() -> u8 {
     0
 }
 
+fn default_keepalive_interval() -> u64 {
+    10
+}
+
+fn default_idle_timeout() -> u64 {
+    30
+}
+
+fn default_time_sync_mode() -> String {
+    "unset".to_string()
+}
+
+fn default_max_drift() -> u64 {
+    5
+}
+
+fn default_time_sync_interval() -> u64 {
+    3600
+}
+
+fn default_recording_path() -> PathBuf {
+    PathBuf::from("recordings")
+}
+
+fn default_recording_format() -> String {
+    "mp4".to_string()
+}
+
+fn default_segment_length() -> u64 {
+    600
+}
+
+fn default_pre_roll() -> u64 {
+    5
+}
+
+fn default_post_roll() -> u64 {
+    30
+}
+
+fn default_mqtt_port() -> u16 {
+    1883
+}
+
+fn default_mqtt_topic_prefix() -> String {
+    "neolink".to_string()
+}
+
+fn validate_camera_config(camera_config: &CameraConfig) -> Result<(), ValidationError> {
+    match (&camera_config.camera_addr, &camera_config.uid) {
+        (None, None) => Err(ValidationError::new("Either address or UID must be given")),
+        (Some(_), Some(_)) => Err(ValidationError::new("Address and UID cannot both be given")),
+        _ => Ok(()),
+    }
+}
+
 pub static RESERVED_NAMES: &[&str] = &["anyone", "anonymous"];
 fn validate_username(name: &str) -> Result<(), ValidationError> {
     if name.trim().is_empty() {
@@ -0,0 +1,84 @@
+//! The `discover` subcommand, which lists the cameras on the local network so that the user does
+//! not have to hunt for their addresses.
+use crate::Error;
+use neolink::bc_protocol::{discover, DiscoveredCamera};
+use std::collections::HashSet;
+use std::time::Duration;
+
+pub fn main(timeout: Duration, print_toml: bool) -> Result<(), Error> {
+    let cameras = discover(timeout)?;
+    if cameras.is_empty() {
+        println!("No cameras answered.  Cameras on another subnet or VLAN cannot be discovered.");
+        return Ok(());
+    }
+
+    println!("{:<22} {:<20} {:<16} FIRMWARE", "ADDRESS", "UID", "MODEL");
+    for camera in &cameras {
+        println!(
+            "{:<22} {:<20} {:<16} {}",
+            camera.addr, camera.uid, camera.model, camera.firmware
+        );
+    }
+
+    if print_toml {
+        let mut names = HashSet::new();
+        for camera in &cameras {
+            println!();
+            print!("{}", camera_toml(camera, &mut names));
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a `[[cameras]]` section for the camera.  The name is made from the model, with a
+/// number added if several cameras are the same model.
+fn camera_toml(camera: &DiscoveredCamera, names: &mut HashSet<String>) -> String {
+    let base_name: String = camera
+        .model
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    let mut name = base_name.clone();
+    let mut n = 1;
+    while !names.insert(name.clone()) {
+        n += 1;
+        name = format!("{}-{}", base_name, n);
+    }
+
+    format!(
+        "[[cameras]]\n\
+         name = \"{}\"\n\
+         username = \"admin\"\n\
+         password = \"password\"\n\
+         address = \"{}\"\n\
+         # uid = \"{}\"\n",
+        name, camera.addr, camera.uid
+    )
+}
+
+#[test]
+fn test_camera_toml() {
+    use crate::config::Config;
+    use validator::Validate;
+
+    let camera = DiscoveredCamera {
+        addr: "192.168.1.187:9000".parse().unwrap(),
+        uid: "95270000ABCDEFGH".to_string(),
+        model: "RLC-410W".to_string(),
+        firmware: "v3.0.0.136_20121102".to_string(),
+    };
+    let mut names = HashSet::new();
+    let first = camera_toml(&camera, &mut names);
+    let second = camera_toml(&camera, &mut names);
+
+    let config: Config = toml::from_str(&format!("{}{}", first, second)).unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.cameras[0].name, "rlc-410w");
+    assert_eq!(config.cameras[1].name, "rlc-410w-2");
+    assert_eq!(
+        config.cameras[0].camera_addr.as_deref(),
+        Some("192.168.1.187:9000")
+    );
+}
@@ -1,28 +1,53 @@
 //! This module provides an "RtspServer" abstraction that allows consumers of its API to feed it
-//! data using an ordinary std::io::Write interface.
+//! data using an ordinary std::io::Write interface.  Clients that support the ONVIF audio
+//! backchannel can also send audio the other way, which comes out of a channel as PCM.
+//! Playback mounts give each client its own GstOutputs to replay recordings into.
 pub use self::maybe_app_src::MaybeAppSrc;
+pub use self::muxer::{Container, Muxer};
+pub use self::webrtc::WebRtcSession;
+use crate::bc_protocol::StreamInfo;
 use gstreamer::prelude::Cast;
-use gstreamer::{Bin, Structure};
-use gstreamer_app::AppSrc;
+use gstreamer::{Bin, Caps, FlowError, FlowSuccess, Fraction, Structure};
+use gstreamer_app::{AppSink, AppSinkCallbacks, AppSrc};
 //use gstreamer_rtsp::RTSPLowerTrans;
+use crossbeam::channel::Sender;
 use gio::{TlsAuthenticationMode, TlsCertificate};
 use gstreamer_rtsp::RTSPAuthMethod;
 use gstreamer_rtsp_server::prelude::*;
 use gstreamer_rtsp_server::{
-    RTSPAuth, RTSPMediaFactory, RTSPServer as GstRTSPServer, RTSPToken,
-    RTSP_PERM_MEDIA_FACTORY_ACCESS, RTSP_PERM_MEDIA_FACTORY_CONSTRUCT,
-    RTSP_TOKEN_MEDIA_FACTORY_ROLE,
+    RTSPAuth, RTSPMediaFactory, RTSPOnvifMediaFactory, RTSPOnvifServer,
+    RTSPServer as GstRTSPServer, RTSPToken, RTSP_PERM_MEDIA_FACTORY_ACCESS,
+    RTSP_PERM_MEDIA_FACTORY_CONSTRUCT, RTSP_TOKEN_MEDIA_FACTORY_ROLE,
 };
 use log::*;
 use std::collections::HashSet;
+use std::ffi::CStr;
 use std::fs;
 use std::io;
 use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+mod muxer;
+mod webrtc;
 
 type Result<T> = std::result::Result<T, ()>;
 
+/// A bigger step than this between two of the camera's timestamps is taken to be its clock
+/// jumping, rather than time passing
+const MAX_TIMESTAMP_STEP: Duration = Duration::from_secs(10);
+
+/// How far the timestamps may drift from the pipeline's clock before they are lined up again
+const MAX_DRIFT: Duration = Duration::from_secs(2);
+
+/// How far the audio may get from the video before it is timed from the video again, such as
+/// after some audio has been lost
+const MAX_AUDIO_SKEW: Duration = Duration::from_secs(1);
+
 pub struct RtspServer {
     server: GstRTSPServer,
+    /// The basic auth strings of the users set up by set_credentials, to remove when they change
+    basics: Mutex<Vec<String>>,
 }
 
 ***REMOVED***[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
@@ -38,7 +63,32 @@ pub struct GstOutputs {
     pub vidsrc: MaybeAppSrc,
     video_format: Option<StreamFormat>,
     audio_format: Option<StreamFormat>,
-    factory: RTSPMediaFactory,
+    stream_info: Option<StreamInfo>,
+    clock: CameraClock,
+    /// Camera time plus this is the running time of the pipeline.  It is worked out again for
+    /// each new pipeline, which is known by the generation of the video AppSrc.
+    offset: Option<(u64, i64)>,
+    /// When the next audio buffer starts, in camera time
+    audio_time: Option<Duration>,
+    factory: RTSPOnvifMediaFactory,
+    /// Playback mounts, which are assumed to have the same formats as the live stream
+    playback_factories: Vec<RTSPMediaFactory>,
+}
+
+/// A change in whether a stream is being played by RTSP clients
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// The stream was set up for a client, which is waiting for data
+    Arrived,
+    /// The clients that the stream was set up for have all gone
+    Left,
+}
+
+/// An RTSP client asking to play back recordings.  Write them into `outputs` until it is closed.
+pub struct PlaybackRequest {
+    /// The query string of the URL the client asked for, such as `start=2020-10-11T21:30:00`
+    pub query: Option<String>,
+    pub outputs: GstOutputs,
 }
 
 impl GstOutputs {
@@ -48,7 +98,12 @@ impl GstOutputs {
             audsrc,
             video_format: None,
             audio_format: None,
-            factory: RTSPMediaFactory::new(),
+            stream_info: None,
+            clock: CameraClock::default(),
+            offset: None,
+            audio_time: None,
+            factory: RTSPOnvifMediaFactory::new(),
+            playback_factories: vec![],
         };
         result.apply_format();
         result
@@ -60,6 +115,7 @@ impl GstOutputs {
                 if format != self.video_format {
                     self.video_format = format;
                     self.apply_format();
+                    self.apply_video_caps();
                 }
             }
             Some(StreamFormat::AAC) | Some(StreamFormat::ADPCM) => {
@@ -72,6 +128,97 @@ impl GstOutputs {
         }
     }
 
+    /// Whether the video format is known.  Until it is, RTSP clients are given a stream without
+    /// any video.
+    pub fn has_video_format(&self) -> bool {
+        self.video_format.is_some()
+    }
+
+    /// Tells downstream the resolution and frame rate, which the camera sends whenever the stream
+    /// starts or changes
+    pub fn set_stream_info(&mut self, info: StreamInfo) {
+        if Some(info) != self.stream_info {
+            debug!(
+                "Stream is {}x{} at {}fps",
+                info.width, info.height, info.fps
+            );
+            self.stream_info = Some(info);
+            self.apply_video_caps();
+        }
+    }
+
+    /// Writes a frame of video, timed by the camera's microsecond `timestamp` rather than by when
+    /// it arrived, so that network jitter doesn't show
+    pub fn write_video(&mut self, data: &[u8], timestamp: Option<u32>) -> io::Result<()> {
+        let camera_time = match timestamp {
+            Some(timestamp) => self.clock.update(timestamp, Instant::now()),
+            None => self.clock.now(),
+        };
+        let pts = self.running_time(camera_time);
+        self.vidsrc.write_timed(data, pts)
+    }
+
+    /// Writes a buffer of audio lasting `duration`.  Audio packets have no timestamps of their
+    /// own, so the audio is timed by counting samples from where the video was when it started.
+    pub fn write_audio(&mut self, data: &[u8], duration: Option<Duration>) -> io::Result<()> {
+        let video_time = self.clock.now();
+        let audio_time = match self.audio_time {
+            Some(time) if difference(time, video_time) <= MAX_AUDIO_SKEW => time,
+            _ => video_time,
+        };
+        self.audio_time = duration.map(|duration| audio_time + duration);
+        let pts = self.running_time(audio_time);
+        self.audsrc.write_timed(data, pts)
+    }
+
+    /// Ends the stream on both AppSrcs, after a problem with the camera
+    pub fn on_stream_error(&mut self) {
+        self.vidsrc.on_stream_error();
+        self.audsrc.on_stream_error();
+        self.audio_time = None;
+    }
+
+    /// Converts camera time into the running time of the pipeline.  Both AppSrcs are in the same
+    /// pipeline, so audio and video share the offset and stay in sync.
+    fn running_time(&mut self, camera_time: Duration) -> Duration {
+        let generation = self.vidsrc.generation();
+        let now = self.vidsrc.running_time();
+        let camera_nanos = camera_time.as_nanos() as i64;
+        let at_offset = |offset: i64| Duration::from_nanos((camera_nanos + offset).max(0) as u64);
+
+        if let Some((offset_generation, offset)) = self.offset {
+            let pts = at_offset(offset);
+            let in_step = now.map_or(true, |now| difference(pts, now) <= MAX_DRIFT);
+            if offset_generation == generation && in_step {
+                return pts;
+            }
+        }
+        // Until the pipeline is playing, its running time stays at zero
+        let offset = now.unwrap_or_default().as_nanos() as i64 - camera_nanos;
+        self.offset = Some((generation, offset));
+        at_offset(offset)
+    }
+
+    fn apply_video_caps(&mut self) {
+        let media_type = match self.video_format {
+            Some(StreamFormat::H264) => "video/x-h264",
+            Some(StreamFormat::H265) => "video/x-h265",
+            _ => return,
+        };
+        let info = match self.stream_info {
+            Some(info) => info,
+            None => return,
+        };
+        let mut caps = Caps::builder(media_type)
+            .field("stream-format", &"byte-stream")
+            .field("width", &(info.width as i32))
+            .field("height", &(info.height as i32));
+        if info.fps > 0 {
+            caps = caps.field("framerate", &Fraction::new(info.fps as i32, 1));
+        }
+        self.vidsrc.set_caps(caps.build());
+    }
+
     fn apply_format(&self) {
         let launch_vid = match self.video_format {
             Some(StreamFormat::H264) => {
@@ -89,20 +236,124 @@ impl GstOutputs {
             _ => "! fakesink",
         };
 
-        self.factory.set_launch(
-            &vec![
+        let launch = vec![
             "( ",
-            "appsrc name=vidsrc is-live=true block=true emit-signals=false max-bytes=52428800 do-timestamp=true format=GST_FORMAT_TIME", // 50MB max size so that it won't grow to infinite if the queue blocks
+            "appsrc name=vidsrc is-live=true block=true emit-signals=false max-bytes=52428800 format=GST_FORMAT_TIME", // 50MB max size so that it won't grow to infinite if the queue blocks
             launch_vid,
-            "appsrc name=audsrc is-live=true block=true emit-signals=false max-bytes=52428800 do-timestamp=true format=GST_FORMAT_TIME", // 50MB max size so that it won't grow to infinite if the queue blocks
+            "appsrc name=audsrc is-live=true block=true emit-signals=false max-bytes=52428800 format=GST_FORMAT_TIME", // 50MB max size so that it won't grow to infinite if the queue blocks
             launch_aud,
             ")"
         ]
-            .join(" "),
-        );
+        .join(" ");
+        self.factory.set_launch(&launch);
+        for factory in &self.playback_factories {
+            factory.set_launch(&launch);
+        }
+    }
+
+    /// Accepts an ONVIF audio backchannel from RTSP clients that ask for one.  The G.711 audio
+    /// they send is converted to signed 16-bit mono PCM at `sample_rate` and passed to `tx` as it
+    /// arrives.
+    pub fn enable_backchannel(&self, sample_rate: u32, tx: Sender<Vec<u8>>) {
+        let factory = &self.factory;
+        factory.set_backchannel_launch(Some(&format!(
+            "( capsfilter caps=\"application/x-rtp, media=audio, payload=0, clock-rate=8000, encoding-name=PCMU\" name=depay_backchannel ! rtppcmudepay ! mulawdec ! audioconvert ! audioresample ! audio/x-raw,format=S16LE,channels=1,rate={} ! appsink name=backchannel_sink sync=false async=false )",
+            sample_rate
+        )));
+
+        factory.connect_media_configure(move |_factory, media| {
+            let bin = media
+                .get_element()
+                .expect("Media should have an element")
+                .dynamic_cast::<Bin>()
+                .expect("Media source's element should be a bin");
+            // The backchannel is only present if this client asked for it
+            let app_sink = match bin.get_by_name_recurse_up("backchannel_sink") {
+                Some(sink) => sink
+                    .dynamic_cast::<AppSink>()
+                    .expect("Sink element is expected to be an appsink!"),
+                None => return,
+            };
+            debug!("RTSP: client opened an audio backchannel");
+
+            app_sink.set_callbacks(backchannel_callbacks(tx.clone()));
+        });
+    }
+
+    /// Tells `tx` whenever the stream, or one of its playback mounts, is set up for RTSP clients
+    /// and whenever those clients have all gone.  Until then, whatever is written to the stream is
+    /// thrown away, so this lets the writer connect to the camera only while it is needed.
+    pub fn watch_clients(&self, tx: Sender<ClientEvent>) {
+        let live = self.factory.upcast_ref::<RTSPMediaFactory>();
+        for factory in std::iter::once(live).chain(&self.playback_factories) {
+            let tx = tx.clone();
+            factory.connect_media_configure(move |_factory, media| {
+                debug!("RTSP: a client arrived");
+                let _ = tx.send(ClientEvent::Arrived);
+                let tx = tx.clone();
+                media.connect_unprepared(move |_media| {
+                    debug!("RTSP: the clients of a stream have gone");
+                    let _ = tx.send(ClientEvent::Left);
+                });
+            });
+        }
     }
 }
 
+/// Counts up the time on the camera's clock.  Its timestamps are in microseconds and only 32 bits
+/// long, so they wrap around every 71 minutes.
+#[derive(Default)]
+pub struct CameraClock {
+    last: Option<(u32, Instant)>,
+    elapsed: Duration,
+}
+
+impl CameraClock {
+    /// Moves on to the next `timestamp`, which arrived at `arrival`, and returns the time since
+    /// the first one.  If the camera's clock jumps, such as when it restarts the stream, the time
+    /// between arrivals is used for that step instead.
+    pub fn update(&mut self, timestamp: u32, arrival: Instant) -> Duration {
+        if let Some((last, last_arrival)) = self.last {
+            let step = Duration::from_micros(timestamp.wrapping_sub(last) as u64);
+            if step <= MAX_TIMESTAMP_STEP {
+                self.elapsed += step;
+            } else {
+                self.elapsed += arrival.saturating_duration_since(last_arrival);
+            }
+        }
+        self.last = Some((timestamp, arrival));
+        self.elapsed
+    }
+
+    /// The time of the latest timestamp since the first one
+    pub fn now(&self) -> Duration {
+        self.elapsed
+    }
+}
+
+fn difference(a: Duration, b: Duration) -> Duration {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// Passes each buffer of audio that reaches the backchannel's appsink on to `tx`
+fn backchannel_callbacks(tx: Sender<Vec<u8>>) -> AppSinkCallbacks {
+    AppSinkCallbacks::new()
+        .new_sample(move |sink| {
+            let sample = sink.pull_sample().map_err(|_| FlowError::Eos)?;
+            let buffer = sample.get_buffer().ok_or(FlowError::Error)?;
+            let map = buffer.map_readable().map_err(|_| FlowError::Error)?;
+            // Drop audio rather than hold up the RTSP server if the camera has fallen behind or
+            // isn't connected
+            let _ = tx.try_send(map.as_slice().to_vec());
+            Ok(FlowSuccess::Ok)
+        })
+        .build()
+}
+
 impl Default for RtspServer {
     fn default() -> RtspServer {
         Self::new()
@@ -113,7 +364,9 @@ impl RtspServer {
     pub fn new() -> RtspServer {
         gstreamer::init().expect("Gstreamer should not explode");
         RtspServer {
-            server: GstRTSPServer::new(),
+            // The ONVIF server understands clients that require the audio backchannel
+            server: RTSPOnvifServer::new().upcast(),
+            basics: Mutex::new(vec![]),
         }
     }
 
@@ -145,7 +398,7 @@ impl RtspServer {
                 .collect::<String>(),
             paths.join(", ")
         );
-        self.add_permitted_roles(factory, permitted_users);
+        self.add_permitted_roles(factory.upcast_ref(), permitted_users);
 
         factory.set_shared(true);
 
@@ -178,6 +431,89 @@ impl RtspServer {
         Ok(outputs)
     }
 
+    /// Adds a mount for playing back recordings.  Each client that connects gets its own media,
+    /// which is sent to `requests` along with the query string of the URL it asked for.  The
+    /// recordings are assumed to be in the same formats as `live`.
+    pub fn add_playback(
+        &self,
+        paths: &[&str],
+        permitted_users: &HashSet<&str>,
+        live: &mut GstOutputs,
+        requests: Sender<PlaybackRequest>,
+    ) {
+        let mounts = self
+            .server
+            .get_mount_points()
+            .expect("The server should have mountpoints");
+
+        let factory = RTSPMediaFactory::new();
+        self.add_permitted_roles(&factory, permitted_users);
+        factory.set_shared(false);
+
+        factory.connect_media_configure(move |_factory, media| {
+            let query = current_request_query();
+            debug!("RTSP: playback requested with {:?}", query);
+            let bin = media
+                .get_element()
+                .expect("Media should have an element")
+                .dynamic_cast::<Bin>()
+                .expect("Media source's element should be a bin");
+            let get_app_src = |name| {
+                bin.get_by_name_recurse_up(name)
+                    .expect("write_src must be present in created bin")
+                    .dynamic_cast::<AppSrc>()
+                    .expect("Source element is expected to be an appsrc!")
+            };
+            let (vidsrc, tx) = MaybeAppSrc::new_with_tx();
+            let (audsrc, tx_aud) = MaybeAppSrc::new_with_tx();
+            let _ = tx.send(get_app_src("vidsrc"));
+            let _ = tx_aud.send(get_app_src("audsrc"));
+
+            let outputs = GstOutputs::from_appsrcs(vidsrc, audsrc);
+            let request = PlaybackRequest { query, outputs };
+            if requests.try_send(request).is_err() {
+                warn!("RTSP: too many playback requests, ignoring one");
+            }
+        });
+
+        for path in paths {
+            mounts.add_factory(path, &factory);
+        }
+        live.playback_factories.push(factory);
+        live.apply_format();
+    }
+
+    /// Removes the mounts at `paths`.  Clients that are already playing them are not cut off,
+    /// but their streams end when nothing more is written to them.
+    pub fn remove_mounts(&self, paths: &[&str]) {
+        let mounts = self
+            .server
+            .get_mount_points()
+            .expect("The server should have mountpoints");
+        for path in paths {
+            mounts.remove_factory(path);
+        }
+    }
+
+    /// How many clients are playing the mount at `path`
+    pub fn client_count(&self, path: &str) -> usize {
+        let pool = match self.server.get_session_pool() {
+            Some(pool) => pool,
+            None => return 0,
+        };
+        pool.filter(None)
+            .iter()
+            .map(|session| {
+                // A session's media matches the start of a path, so insist that all of it matched
+                session
+                    .filter(None)
+                    .iter()
+                    .filter(|media| media.matches(path) == Some(path.len() as i32))
+                    .count()
+            })
+            .sum()
+    }
+
     pub fn add_permitted_roles(&self, factory: &RTSPMediaFactory, permitted_roles: &HashSet<&str>) {
         for permitted_role in permitted_roles {
             factory.add_role_from_structure(&Structure::new(
@@ -210,10 +546,16 @@ impl RtspServer {
         }
     }
 
+    /// Sets the users that can log in, replacing any set before
     pub fn set_credentials(&self, credentials: &[(&str, &str)]) -> Result<()> {
         let auth = self.server.get_auth().unwrap_or_else(RTSPAuth::new);
         auth.set_supported_methods(RTSPAuthMethod::Basic);
 
+        let mut basics = self.basics.lock().unwrap();
+        for basic in basics.drain(..) {
+            auth.remove_basic(&basic);
+        }
+
         let mut un_authtoken = RTSPToken::new(&[(*RTSP_TOKEN_MEDIA_FACTORY_ROLE, &"anonymous")]);
         auth.set_default_token(Some(&mut un_authtoken));
 
@@ -223,6 +565,7 @@ impl RtspServer {
             let token = RTSPToken::new(&[(*RTSP_TOKEN_MEDIA_FACTORY_ROLE, user)]);
             let basic = RTSPAuth::make_basic(user, pass);
             auth.add_basic(basic.as_str(), &token);
+            basics.push(basic.to_string());
         }
 
         self.server.set_auth(Some(&auth));
@@ -255,8 +598,26 @@ impl RtspServer {
     }
 }
 
+/// The query string of the URL that the RTSP client we are handling right now asked for.  The
+/// bindings don't expose the request context, so this reaches into the C struct.
+fn current_request_query() -> Option<String> {
+    unsafe {
+        let ctx = gstreamer_rtsp_server_sys::gst_rtsp_context_get_current();
+        if ctx.is_null() || (*ctx).uri.is_null() || (*(*ctx).uri).query.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*(*ctx).uri).query)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
 mod maybe_app_src {
     use super::*;
+    use gstreamer::prelude::{ClockExt, ElementExt};
+    use gstreamer::ClockTime;
     use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
 
     /// A Write implementation around AppSrc that also allows delaying the creation of the AppSrc
@@ -264,6 +625,10 @@ mod maybe_app_src {
     pub struct MaybeAppSrc {
         rx: Receiver<AppSrc>,
         app_src: Option<AppSrc>,
+        closed: bool,
+        caps: Option<Caps>,
+        /// Counts the AppSrcs that have been provided
+        generation: u64,
     }
 
     impl MaybeAppSrc {
@@ -272,7 +637,16 @@ mod maybe_app_src {
         /// into the AppSrc when write() is called.
         pub fn new_with_tx() -> (Self, SyncSender<AppSrc>) {
             let (tx, rx) = sync_channel(3); // The sender should not send very often
-            (MaybeAppSrc { rx, app_src: None }, tx)
+            (
+                MaybeAppSrc {
+                    rx,
+                    app_src: None,
+                    closed: false,
+                    caps: None,
+                    generation: 0,
+                },
+                tx,
+            )
         }
 
         /// Flushes data to Gstreamer on a problem communicating with the underlying video source.
@@ -283,29 +657,56 @@ mod maybe_app_src {
             }
         }
 
-        /// Attempts to retrieve the AppSrc that should be passed in by the caller of new_with_tx
-        /// at some point after this struct has been created.  At that point, we swap over to
-        /// owning the AppSrc directly.  This function handles either case and returns the AppSrc,
-        /// or None if the caller has not yet sent one.
-        fn try_get_src(&mut self) -> Option<&AppSrc> {
-            while let Some(src) = self.rx.try_recv().ok() {
-                self.app_src = Some(src);
+        /// Sets the caps of the data being written, now and on any AppSrc provided later
+        pub fn set_caps(&mut self, caps: Caps) {
+            if let Some(src) = self.try_get_src() {
+                src.set_caps(Some(&caps));
             }
-            self.app_src.as_ref()
+            self.caps = Some(caps);
         }
-    }
 
-    impl Write for MaybeAppSrc {
-        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-            // If we have no AppSrc yet, throw away the data and claim that it was written
+        /// True if the AppSrc has stopped taking data, such as when its client has gone, and no
+        /// new one has been provided
+        pub fn is_closed(&mut self) -> bool {
+            self.try_get_src().is_none() && self.closed
+        }
+
+        /// Changes whenever a new AppSrc is provided
+        pub fn generation(&mut self) -> u64 {
+            self.try_get_src();
+            self.generation
+        }
+
+        /// The running time of the AppSrc's pipeline, or None if it is not playing
+        pub fn running_time(&mut self) -> Option<Duration> {
+            let src = self.try_get_src()?;
+            let now = src.get_clock()?.get_time().nseconds()?;
+            let base_time = src.get_base_time().nseconds()?;
+            Some(Duration::from_nanos(now.saturating_sub(base_time)))
+        }
+
+        /// Writes a buffer with `pts` as its presentation and decoding time, which is in the
+        /// running time of the AppSrc's pipeline
+        pub fn write_timed(&mut self, buf: &[u8], pts: Duration) -> io::Result<()> {
+            self.push(buf, Some(pts));
+            Ok(())
+        }
+
+        fn push(&mut self, buf: &[u8], pts: Option<Duration>) {
+            // If we have no AppSrc yet, throw away the data
             let app_src = match self.try_get_src() {
                 Some(src) => src,
-                None => return Ok(buf.len()),
+                None => return,
             };
 
             let mut gst_buf = gstreamer::Buffer::with_size(buf.len()).unwrap();
             {
                 let gst_buf_mut = gst_buf.get_mut().unwrap();
+                if let Some(pts) = pts {
+                    let pts = ClockTime::from_nseconds(pts.as_nanos() as u64);
+                    gst_buf_mut.set_pts(pts);
+                    gst_buf_mut.set_dts(pts);
+                }
                 let mut gst_buf_data = gst_buf_mut.map_writable().unwrap();
                 gst_buf_data.copy_from_slice(buf);
             }
@@ -313,7 +714,30 @@ mod maybe_app_src {
             let res = app_src.push_buffer(gst_buf); //.map_err(|e| io::Error::new(io::ErrorKind::Other, Box::new(e)))?;
             if res.is_err() {
                 self.app_src = None;
+                self.closed = true;
             }
+        }
+
+        /// Attempts to retrieve the AppSrc that should be passed in by the caller of new_with_tx
+        /// at some point after this struct has been created.  At that point, we swap over to
+        /// owning the AppSrc directly.  This function handles either case and returns the AppSrc,
+        /// or None if the caller has not yet sent one.
+        fn try_get_src(&mut self) -> Option<&AppSrc> {
+            while let Some(src) = self.rx.try_recv().ok() {
+                if let Some(caps) = &self.caps {
+                    src.set_caps(Some(caps));
+                }
+                self.app_src = Some(src);
+                self.closed = false;
+                self.generation += 1;
+            }
+            self.app_src.as_ref()
+        }
+    }
+
+    impl Write for MaybeAppSrc {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.push(buf, None);
             Ok(buf.len())
         }
 
@@ -322,3 +746,21 @@ mod maybe_app_src {
         }
     }
 }
+
+#[test]
+fn test_camera_clock() {
+    let start = Instant::now();
+    let mut clock = CameraClock::default();
+    assert_eq!(clock.update(4_294_000_000, start), Duration::from_secs(0));
+    // Wraps around
+    assert_eq!(
+        clock.update(33_000, start + Duration::from_millis(900)),
+        Duration::from_micros(1_000_296)
+    );
+    // Jumps back when the camera restarts the stream, so the arrival time is used instead
+    assert_eq!(
+        clock.update(0, start + Duration::from_secs(3)),
+        Duration::from_micros(3_100_296)
+    );
+    assert_eq!(clock.now(), Duration::from_micros(3_100_296));
+}
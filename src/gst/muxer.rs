@@ -0,0 +1,244 @@
+use super::StreamFormat;
+use gstreamer::prelude::*;
+use gstreamer::{ClockTime, ElementFactory, MessageType, MessageView, Pipeline, State};
+use gstreamer_app::AppSrc;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long to wait for the muxer to write out the end of a file
+const FINISH_TIMEOUT: u64 = 10;
+
+/// How long each fragment of a fragmented MP4 is
+const FRAGMENT_DURATION_MS: u32 = 500;
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub enum Container {
+    Mp4,
+    /// MP4 made of fragments, each playable as it arrives, such as for HLS
+    FragmentedMp4,
+    Matroska,
+}
+
+impl Container {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => "mp4",
+            Container::Matroska => "mkv",
+        }
+    }
+
+    fn muxer(self) -> &'static str {
+        match self {
+            Container::Mp4 | Container::FragmentedMp4 => "mp4mux",
+            Container::Matroska => "matroskamux",
+        }
+    }
+
+    fn configure(self, muxer: &gstreamer::Element) -> io::Result<()> {
+        if self == Container::FragmentedMp4 {
+            set_property(muxer, "fragment-duration", &FRAGMENT_DURATION_MS.to_value())?;
+            set_property(muxer, "streamable", &true.to_value())?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes H264/H265 video, and AAC or PCM audio, into files.  The data is the same as is written
+/// to GstOutputs, but each buffer carries a timestamp, so it does not have to arrive in real
+/// time.  MP4 files cannot hold PCM audio, so it is dropped from them.
+pub struct Muxer {
+    pipeline: Pipeline,
+    vidsrc: AppSrc,
+    audsrc: Option<AppSrc>,
+    finished: bool,
+}
+
+impl Muxer {
+    /// Writes everything to a single file
+    pub fn new_file(
+        path: &Path,
+        container: Container,
+        video: StreamFormat,
+        audio: Option<StreamFormat>,
+    ) -> io::Result<Muxer> {
+        let sink = format!("{} name=mux ! filesink name=filesink", container.muxer());
+        let muxer = Muxer::new(&sink, ("video_0", "audio_0"), container, video, audio)?;
+
+        container.configure(&muxer.get_element("mux")?)?;
+        let filesink = muxer.get_element("filesink")?;
+        set_property(&filesink, "location", &path.to_string_lossy().to_value())?;
+
+        muxer.start()?;
+        Ok(muxer)
+    }
+
+    /// Writes to a series of files, starting a new one at the first keyframe after each
+    /// `segment_length`.  `location` is called with a count from 0 to name each file as it is
+    /// started.
+    pub fn new_segmented<F>(
+        location: F,
+        segment_length: Duration,
+        container: Container,
+        video: StreamFormat,
+        audio: Option<StreamFormat>,
+    ) -> io::Result<Muxer>
+    where
+        F: Fn(u32) -> PathBuf + Send + Sync + 'static,
+    {
+        let muxer = Muxer::new(
+            "splitmuxsink name=mux",
+            ("video", "audio_0"),
+            container,
+            video,
+            audio,
+        )?;
+
+        let splitmux = muxer.get_element("mux")?;
+        let file_muxer = ElementFactory::make(container.muxer(), None).map_err(gst_error)?;
+        container.configure(&file_muxer)?;
+        set_property(&splitmux, "muxer", &file_muxer.to_value())?;
+        let segment_ns = segment_length.as_nanos() as u64;
+        set_property(&splitmux, "max-size-time", &segment_ns.to_value())?;
+        splitmux
+            .connect("format-location", false, move |args| {
+                let fragment_id = args[1].get_some::<u32>().unwrap_or(0);
+                Some(location(fragment_id).to_string_lossy().to_value())
+            })
+            .map_err(gst_error)?;
+
+        muxer.start()?;
+        Ok(muxer)
+    }
+
+    fn new(
+        sink: &str,
+        (video_pad, audio_pad): (&str, &str),
+        container: Container,
+        video: StreamFormat,
+        audio: Option<StreamFormat>,
+    ) -> io::Result<Muxer> {
+        let launch_vid = match video {
+            StreamFormat::H264 => "appsrc name=vidsrc format=time caps=video/x-h264,stream-format=byte-stream ! h264parse",
+            StreamFormat::H265 => "appsrc name=vidsrc format=time caps=video/x-h265,stream-format=byte-stream ! h265parse",
+            _ => return Err(other_error("Only H264 and H265 video can be muxed")),
+        };
+        let launch_aud = match (audio, container) {
+            (Some(StreamFormat::AAC), _) => "appsrc name=audsrc format=time caps=audio/mpeg,mpegversion=4,stream-format=adts ! aacparse",
+            (Some(StreamFormat::ADPCM), Container::Matroska) => "appsrc name=audsrc format=time caps=audio/x-raw,format=S16LE,layout=interleaved,rate=8000,channels=1 ! audioconvert", // DVI4 is converted to pcm before it gets here
+            _ => "",
+        };
+
+        let mut launch = format!("{} ! queue ! mux.{} ", launch_vid, video_pad);
+        if !launch_aud.is_empty() {
+            launch += &format!("{} ! queue ! mux.{} ", launch_aud, audio_pad);
+        }
+        launch += sink;
+
+        let pipeline = gstreamer::parse_launch(&launch)
+            .map_err(gst_error)?
+            .dynamic_cast::<Pipeline>()
+            .map_err(|_| other_error("Muxer should be a pipeline"))?;
+        let get_app_src = |name| {
+            pipeline
+                .get_by_name(name)
+                .and_then(|src| src.dynamic_cast::<AppSrc>().ok())
+        };
+        let vidsrc = get_app_src("vidsrc").ok_or_else(|| other_error("No video source"))?;
+        let audsrc = get_app_src("audsrc");
+
+        Ok(Muxer {
+            pipeline,
+            vidsrc,
+            audsrc,
+            finished: false,
+        })
+    }
+
+    fn get_element(&self, name: &str) -> io::Result<gstreamer::Element> {
+        self.pipeline
+            .get_by_name(name)
+            .ok_or_else(|| other_error("Muxer element is missing"))
+    }
+
+    fn start(&self) -> io::Result<()> {
+        self.pipeline.set_state(State::Playing).map_err(gst_error)?;
+        Ok(())
+    }
+
+    /// Writes a video frame.  `pts` is measured from the start of the file.
+    pub fn write_video(&self, data: &[u8], pts: Duration) -> io::Result<()> {
+        push(&self.vidsrc, data, pts)
+    }
+
+    /// Writes audio, if the file has an audio track.  `pts` is measured from the start of the
+    /// file.
+    pub fn write_audio(&self, data: &[u8], pts: Duration) -> io::Result<()> {
+        match &self.audsrc {
+            Some(audsrc) => push(audsrc, data, pts),
+            None => Ok(()),
+        }
+    }
+
+    pub fn has_audio(&self) -> bool {
+        self.audsrc.is_some()
+    }
+
+    /// Finishes off the file, which for MP4 is what makes it playable
+    pub fn finish(mut self) -> io::Result<()> {
+        self.finish_inner()
+    }
+
+    fn finish_inner(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+
+        let _ = self.vidsrc.end_of_stream();
+        if let Some(audsrc) = &self.audsrc {
+            let _ = audsrc.end_of_stream();
+        }
+
+        let bus = self.pipeline.get_bus().expect("Pipeline should have a bus");
+        let result = match bus.timed_pop_filtered(
+            ClockTime::from_seconds(FINISH_TIMEOUT),
+            &[MessageType::Eos, MessageType::Error],
+        ) {
+            Some(msg) => match msg.view() {
+                MessageView::Error(err) => Err(gst_error(err.get_error())),
+                _ => Ok(()),
+            },
+            None => Err(other_error("Timed out finishing the file")),
+        };
+        let _ = self.pipeline.set_state(State::Null);
+        result
+    }
+}
+
+impl Drop for Muxer {
+    fn drop(&mut self) {
+        let _ = self.finish_inner();
+    }
+}
+
+fn push(app_src: &AppSrc, data: &[u8], pts: Duration) -> io::Result<()> {
+    let mut buf = gstreamer::Buffer::from_mut_slice(data.to_vec());
+    buf.get_mut()
+        .unwrap()
+        .set_pts(ClockTime::from_nseconds(pts.as_nanos() as u64));
+    app_src.push_buffer(buf).map_err(gst_error)?;
+    Ok(())
+}
+
+fn set_property(element: &gstreamer::Element, name: &str, value: &glib::Value) -> io::Result<()> {
+    element.set_property(name, value).map_err(gst_error)
+}
+
+fn gst_error<E: std::fmt::Debug>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("GStreamer: {:?}", err))
+}
+
+fn other_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
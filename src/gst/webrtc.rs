@@ -0,0 +1,251 @@
+use gstreamer::prelude::*;
+use gstreamer::{Element, Pipeline, Promise, State};
+use gstreamer_app::AppSrc;
+use gstreamer_sdp::SDPMessage;
+use gstreamer_webrtc::{
+    WebRTCICEGatheringState, WebRTCPeerConnectionState, WebRTCSDPType, WebRTCSessionDescription,
+};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long to wait for the ICE candidates to be gathered before answering without the rest
+const GATHER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Sends H264 video to one WebRTC peer.  The data is the same as is written to GstOutputs.
+///
+/// There is no trickle ICE: the answer is only made once all of the candidates are known, and
+/// only host candidates are gathered, so no STUN or TURN server is needed on a LAN.
+pub struct WebRtcSession {
+    pipeline: Pipeline,
+    vidsrc: AppSrc,
+    webrtc: Element,
+}
+
+impl WebRtcSession {
+    /// Starts a session from a peer's SDP offer, returning it and the SDP answer
+    pub fn answer(offer: &str) -> io::Result<(WebRtcSession, String)> {
+        let payload_type = h264_payload_type(offer)
+            .ok_or_else(|| other_error("The offer does not accept H264 video"))?;
+        let offer = SDPMessage::parse_buffer(offer.as_bytes())
+            .map_err(|_| other_error("The offer is not valid SDP"))?;
+
+        let launch = format!(
+            "appsrc name=vidsrc is-live=true do-timestamp=true format=time caps=video/x-h264,stream-format=byte-stream ! h264parse ! rtph264pay config-interval=-1 ! application/x-rtp,media=video,encoding-name=H264,clock-rate=90000,payload={} ! webrtcbin name=webrtc bundle-policy=max-bundle",
+            payload_type
+        );
+        let pipeline = gstreamer::parse_launch(&launch)
+            .map_err(gst_error)?
+            .dynamic_cast::<Pipeline>()
+            .map_err(|_| other_error("WebRTC session should be a pipeline"))?;
+        let vidsrc = pipeline
+            .get_by_name("vidsrc")
+            .and_then(|src| src.dynamic_cast::<AppSrc>().ok())
+            .ok_or_else(|| other_error("No video source"))?;
+        let webrtc = pipeline
+            .get_by_name("webrtc")
+            .ok_or_else(|| other_error("No webrtcbin"))?;
+        let session = WebRtcSession {
+            pipeline,
+            vidsrc,
+            webrtc,
+        };
+
+        let candidates = Arc::new(Mutex::new(vec![]));
+        let candidates_cb = candidates.clone();
+        session
+            .webrtc
+            .connect("on-ice-candidate", false, move |args| {
+                let mline_index = args[1].get_some::<u32>().ok()?;
+                let candidate = args[2].get::<String>().ok()??;
+                candidates_cb.lock().unwrap().push((mline_index, candidate));
+                None
+            })
+            .map_err(gst_error)?;
+
+        session
+            .pipeline
+            .set_state(State::Playing)
+            .map_err(gst_error)?;
+
+        let offer = WebRTCSessionDescription::new(WebRTCSDPType::Offer, offer);
+        session.call("set-remote-description", &[&offer])?;
+        let reply = session.call("create-answer", &[&None::<gstreamer::Structure>])?;
+        let answer = reply
+            .and_then(|reply| {
+                reply
+                    .get_value("answer")
+                    .ok()?
+                    .get::<WebRTCSessionDescription>()
+                    .ok()?
+            })
+            .ok_or_else(|| other_error("webrtcbin did not make an answer"))?;
+        session.call("set-local-description", &[&answer])?;
+
+        session.wait_for_candidates();
+        let answer = answer
+            .get_sdp()
+            .as_text()
+            .map_err(|_| other_error("Could not write the answer"))?;
+        let candidates = candidates.lock().unwrap();
+        Ok((session, add_candidates(&answer, &candidates)))
+    }
+
+    /// Calls a webrtcbin action signal that takes a promise as its last argument, and waits for
+    /// the reply
+    fn call(
+        &self,
+        signal: &str,
+        args: &[&dyn ToValue],
+    ) -> io::Result<Option<gstreamer::Structure>> {
+        let promise = Promise::new();
+        let mut args = args.to_vec();
+        args.push(&promise);
+        self.webrtc.emit(signal, &args).map_err(gst_error)?;
+        promise.wait();
+        Ok(promise.get_reply().map(|reply| reply.to_owned()))
+    }
+
+    fn wait_for_candidates(&self) {
+        let start = Instant::now();
+        while start.elapsed() < GATHER_TIMEOUT {
+            let state = self
+                .webrtc
+                .get_property("ice-gathering-state")
+                .ok()
+                .and_then(|state| state.get_some::<WebRTCICEGatheringState>().ok());
+            if state == Some(WebRTCICEGatheringState::Complete) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Sends a frame of H264 video
+    pub fn write_video(&self, data: &[u8]) -> io::Result<()> {
+        let buf = gstreamer::Buffer::from_mut_slice(data.to_vec());
+        self.vidsrc.push_buffer(buf).map_err(gst_error)?;
+        Ok(())
+    }
+
+    /// True once the peer has gone away
+    pub fn is_closed(&self) -> bool {
+        let state = self
+            .webrtc
+            .get_property("connection-state")
+            .ok()
+            .and_then(|state| state.get_some::<WebRTCPeerConnectionState>().ok());
+        match state {
+            Some(WebRTCPeerConnectionState::Failed)
+            | Some(WebRTCPeerConnectionState::Closed)
+            | Some(WebRTCPeerConnectionState::Disconnected) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Drop for WebRtcSession {
+    fn drop(&mut self) {
+        let _ = self.pipeline.set_state(State::Null);
+    }
+}
+
+/// Picks the payload type to send H264 with from an SDP offer, preferring the non-interleaved
+/// packetization that rtph264pay makes
+fn h264_payload_type(offer: &str) -> Option<u8> {
+    let h264_types: Vec<&str> = offer
+        .lines()
+        .filter_map(|line| line.strip_prefix("a=rtpmap:"))
+        .filter_map(|rtpmap| {
+            let mut parts = rtpmap.splitn(2, ' ');
+            let payload_type = parts.next()?;
+            let encoding = parts.next()?;
+            if encoding.to_ascii_uppercase().starts_with("H264/") {
+                Some(payload_type)
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let packetization_mode_1 = h264_types.iter().find(|payload_type| {
+        let fmtp = format!("a=fmtp:{} ", payload_type);
+        offer
+            .lines()
+            .any(|line| line.starts_with(&fmtp) && line.contains("packetization-mode=1"))
+    });
+    packetization_mode_1
+        .or_else(|| h264_types.first())
+        .and_then(|payload_type| payload_type.parse().ok())
+}
+
+/// Adds the gathered ICE candidates to the end of the media sections they belong to
+fn add_candidates(sdp: &str, candidates: &[(u32, String)]) -> String {
+    let mut result = String::new();
+    let mut mline_index = None;
+    let end_media = |result: &mut String, mline_index: Option<u32>| {
+        if let Some(index) = mline_index {
+            for (_, candidate) in candidates.iter().filter(|(i, _)| *i == index) {
+                let line = format!("a={}\r\n", candidate.trim_start_matches("a="));
+                if !sdp.contains(&line) {
+                    *result += &line;
+                }
+            }
+            *result += "a=end-of-candidates\r\n";
+        }
+    };
+
+    for line in sdp.lines() {
+        if line.starts_with("m=") {
+            end_media(&mut result, mline_index);
+            mline_index = Some(mline_index.map(|i| i + 1).unwrap_or(0));
+        }
+        if line != "a=end-of-candidates" && !line.is_empty() {
+            result += line;
+            result += "\r\n";
+        }
+    }
+    end_media(&mut result, mline_index);
+    result
+}
+
+fn gst_error<E: std::fmt::Debug>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, format!("GStreamer: {:?}", err))
+}
+
+fn other_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+#[test]
+fn test_h264_payload_type() {
+    let offer = "v=0\r\n\
+                 m=video 9 UDP/TLS/RTP/SAVPF 96 102 106\r\n\
+                 a=rtpmap:96 VP8/90000\r\n\
+                 a=rtpmap:102 H264/90000\r\n\
+                 a=fmtp:102 level-asymmetry-allowed=1;packetization-mode=0;profile-level-id=42001f\r\n\
+                 a=rtpmap:106 H264/90000\r\n\
+                 a=fmtp:106 level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f\r\n";
+    assert_eq!(h264_payload_type(offer), Some(106));
+    assert_eq!(
+        h264_payload_type("m=video 9 UDP/TLS/RTP/SAVPF 96\r\na=rtpmap:96 VP8/90000\r\n"),
+        None
+    );
+}
+
+#[test]
+fn test_add_candidates() {
+    let sdp = "v=0\r\nm=video 9 UDP/TLS/RTP/SAVPF 106\r\na=mid:video0\r\n";
+    let candidates = vec![(
+        0,
+        "candidate:1 1 UDP 2015363327 192.168.1.10 49152 typ host".to_string(),
+    )];
+    assert_eq!(
+        add_candidates(sdp, &candidates),
+        "v=0\r\n\
+         m=video 9 UDP/TLS/RTP/SAVPF 106\r\n\
+         a=mid:video0\r\n\
+         a=candidate:1 1 UDP 2015363327 192.168.1.10 49152 typ host\r\n\
+         a=end-of-candidates\r\n"
+    );
+}
@@ -0,0 +1,168 @@
+//! Health statistics for each camera.  These outlive the camera sessions, so that they can still
+//! be reported while a camera is reconnecting.
+use neolink::bc::xml::{DeviceInfo, VersionInfo};
+use neolink::bc_protocol::{MediaData, MediaDataKind};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+pub struct CameraHealth {
+    ping_latency: Mutex<Option<Duration>>,
+    status: Mutex<CameraStatus>,
+    /// Set when a connection was lost, until it is made again
+    lost: AtomicBool,
+    streams: Mutex<BTreeMap<&'static str, StreamStats>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    /// Waiting to try again after the connection failed
+    Retrying,
+    /// The camera rejected the username or password, so it is not retried
+    AuthFailed,
+    /// Not connected because nobody is watching, with idle_disconnect
+    Idle,
+}
+
+impl Default for ConnectionState {
+    fn default() -> Self {
+        ConnectionState::Connecting
+    }
+}
+
+impl ConnectionState {
+    pub fn name(self) -> &'static str {
+        match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Connected => "connected",
+            ConnectionState::Retrying => "retrying",
+            ConnectionState::AuthFailed => "auth_failed",
+            ConnectionState::Idle => "idle",
+        }
+    }
+}
+
+/// What is known about a camera, from its last session
+#[derive(Debug, Clone, Default)]
+pub struct CameraStatus {
+    pub state: ConnectionState,
+    /// How long we are waiting before reconnecting, and when that started
+    pub backoff: Option<(Duration, Instant)>,
+    pub version: Option<VersionInfo>,
+    pub device_info: Option<DeviceInfo>,
+    /// How many times the connection has been made again after it ended
+    pub reconnects: u64,
+    /// How many connections ended because the camera sent something that could not be parsed
+    pub deserialization_errors: u64,
+}
+
+/// What has been received on one of a camera's streams, since Neolink started
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    pub received: HashMap<MediaDataKind, MediaCount>,
+    pub last_iframe: Option<Instant>,
+    pub adpcm_errors: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MediaCount {
+    pub bytes: u64,
+    pub frames: u64,
+}
+
+impl CameraHealth {
+    /// The round trip time of the last keepalive ping, or None if the camera is not connected or
+    /// has not been pinged yet
+    pub fn ping_latency(&self) -> Option<Duration> {
+        *self.ping_latency.lock().unwrap()
+    }
+
+    pub fn set_ping_latency(&self, latency: Option<Duration>) {
+        *self.ping_latency.lock().unwrap() = latency;
+    }
+
+    pub fn status(&self) -> CameraStatus {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Sets the connection state.  Connecting again after connection_lost() counts as a reconnect.
+    pub fn set_state(&self, state: ConnectionState) {
+        let mut status = self.status.lock().unwrap();
+        status.state = state;
+        status.backoff = None;
+        if state == ConnectionState::Connected && self.lost.swap(false, Ordering::Relaxed) {
+            status.reconnects += 1;
+        }
+    }
+
+    /// Records that the connection failed, and we will try again after `backoff`
+    pub fn set_retrying(&self, backoff: Duration) {
+        let mut status = self.status.lock().unwrap();
+        status.state = ConnectionState::Retrying;
+        status.backoff = Some((backoff, Instant::now()));
+    }
+
+    pub fn set_device_info(&self, device_info: DeviceInfo) {
+        self.status.lock().unwrap().device_info = Some(device_info);
+    }
+
+    pub fn set_version(&self, version: VersionInfo) {
+        self.status.lock().unwrap().version = Some(version);
+    }
+
+    /// Records that a connection that had been made has failed.  However many attempts it takes
+    /// to connect again, that is one reconnect.
+    pub fn connection_lost(&self) {
+        self.lost.store(true, Ordering::Relaxed);
+    }
+
+    pub fn add_deserialization_error(&self) {
+        self.status.lock().unwrap().deserialization_errors += 1;
+    }
+
+    pub fn streams(&self) -> BTreeMap<&'static str, StreamStats> {
+        self.streams.lock().unwrap().clone()
+    }
+
+    /// Counts a media packet received on `stream`
+    pub fn record_media(&self, stream: &'static str, media: &MediaData) {
+        let mut streams = self.streams.lock().unwrap();
+        let stats = streams.entry(stream).or_default();
+        let kind = media.kind();
+        let count = stats.received.entry(kind).or_default();
+        count.bytes += (media.header().len() + media.body().len()) as u64;
+        count.frames += 1;
+        if kind == MediaDataKind::VideoDataIframe {
+            stats.last_iframe = Some(Instant::now());
+        }
+    }
+
+    pub fn add_adpcm_error(&self, stream: &'static str) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.entry(stream).or_default().adpcm_errors += 1;
+    }
+}
+
+#[test]
+fn test_reconnects() {
+    let health = CameraHealth::default();
+    health.set_state(ConnectionState::Connected);
+    assert_eq!(health.status().reconnects, 0);
+
+    // Retries that fail do not count until the connection is made again
+    health.connection_lost();
+    health.set_retrying(Duration::from_secs(1));
+    health.set_state(ConnectionState::Connecting);
+    health.set_retrying(Duration::from_secs(2));
+    health.set_state(ConnectionState::Connecting);
+    assert_eq!(health.status().reconnects, 0);
+    health.set_state(ConnectionState::Connected);
+    assert_eq!(health.status().reconnects, 1);
+
+    health.set_state(ConnectionState::Connected);
+    assert_eq!(health.status().reconnects, 1);
+}
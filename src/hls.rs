@@ -0,0 +1,359 @@
+//! Serves cameras to browsers as HLS.  The stream is cut into segments of about
+//! `SEGMENT_DURATION` at keyframes, and the last few are kept in memory for the HTTP server.
+//!
+//! Each segment is a fragmented MP4 file of its own.  The playlist points into it with byte
+//! ranges: the header is the initialization section, and the fragments after it are the media.
+//! As every segment has its own header and starts its timestamps again from zero, each one is
+//! marked as a discontinuity.
+//!
+//! Finishing a segment waits for the muxer and reads the file back, so it is done on a thread of
+//! its own rather than holding up the camera's stream.
+use crate::config::CameraConfig;
+use crate::record::{audio_format, write_to_muxer};
+use crossbeam::channel::{self, Sender};
+use log::*;
+use neolink::bc_protocol::{MediaData, MediaDataKind};
+use neolink::gst::{Container, Muxer};
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How long to make each segment.  It is rounded up to the next keyframe.
+const SEGMENT_DURATION: Duration = Duration::from_secs(2);
+
+/// How many segments to keep for clients to fetch
+const SEGMENTS: usize = 6;
+
+/// The segments that are ready to serve, shared between a camera's stream and the HTTP server
+#[derive(Default)]
+pub struct HlsPlaylist {
+    segments: Mutex<VecDeque<Segment>>,
+    /// The sequence number of the next segment to be started.  Numbering carries on from the
+    /// last connection, so that clients notice the new segments.
+    next_sequence: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct Segment {
+    pub sequence: u64,
+    pub duration: Duration,
+    /// How many bytes at the start of the file are the initialization section
+    pub init_length: usize,
+    pub data: Arc<Vec<u8>>,
+}
+
+impl HlsPlaylist {
+    /// The current playlist, or None if no segments are ready yet
+    pub fn playlist(&self) -> Option<String> {
+        let segments = self.segments.lock().unwrap();
+        if segments.is_empty() {
+            None
+        } else {
+            Some(render_playlist(segments.iter()))
+        }
+    }
+
+    pub fn segment(&self, sequence: u64) -> Option<Segment> {
+        let segments = self.segments.lock().unwrap();
+        segments
+            .iter()
+            .find(|segment| segment.sequence == sequence)
+            .cloned()
+    }
+
+    fn push(&self, segment: Segment) {
+        let mut segments = self.segments.lock().unwrap();
+        segments.push_back(segment);
+        while segments.len() > SEGMENTS {
+            segments.pop_front();
+        }
+    }
+
+    /// Drops the segments, which are from a stream that has ended
+    pub fn clear(&self) {
+        self.segments.lock().unwrap().clear();
+    }
+}
+
+/// Cuts a stream into segments for an HlsPlaylist
+pub struct HlsWriter {
+    name: String,
+    playlist: Arc<HlsPlaylist>,
+    segment: Option<Pending>,
+    seen_audio: Option<MediaDataKind>,
+    skipped_iframe: bool,
+    failed: bool,
+    /// Sends recorded segments to the thread that finishes them
+    finisher: Option<Sender<Pending>>,
+    finisher_thread: Option<JoinHandle<()>>,
+}
+
+/// A segment that is being recorded, or waiting to be finished
+struct Pending {
+    muxer: Muxer,
+    path: PathBuf,
+    sequence: u64,
+    start: Instant,
+    end: Instant,
+}
+
+impl HlsWriter {
+    pub fn new(camera_config: &CameraConfig, playlist: Arc<HlsPlaylist>) -> HlsWriter {
+        let name = camera_config.name.clone();
+        let (finisher, pending) = channel::unbounded::<Pending>();
+        let finisher_thread = {
+            let name = name.clone();
+            let playlist = playlist.clone();
+            std::thread::spawn(move || {
+                for segment in pending {
+                    let sequence = segment.sequence;
+                    match segment.finish() {
+                        Ok(segment) => {
+                            trace!("{}: HLS segment {} is ready", name, sequence);
+                            playlist.push(segment);
+                        }
+                        Err(e) => warn!("{}: Could not finish HLS segment: {}", name, e),
+                    }
+                }
+            })
+        };
+        HlsWriter {
+            name,
+            playlist,
+            segment: None,
+            seen_audio: None,
+            skipped_iframe: false,
+            failed: false,
+            finisher: Some(finisher),
+            finisher_thread: Some(finisher_thread),
+        }
+    }
+
+    /// Adds a media packet to the current segment.  If that fails, the error is logged and HLS is
+    /// off until the camera reconnects, but the stream itself carries on.
+    pub fn write_media(&mut self, media: &MediaData) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.try_write_media(media) {
+            error!(
+                "{}: HLS failed, will retry when the camera reconnects: {}",
+                self.name, e
+            );
+            self.failed = true;
+            self.discard_segment();
+        }
+    }
+
+    fn try_write_media(&mut self, media: &MediaData) -> io::Result<()> {
+        let now = Instant::now();
+        let kind = media.kind();
+        if let MediaDataKind::AudioDataAac | MediaDataKind::AudioDataAdpcm = kind {
+            self.seen_audio = Some(kind);
+        }
+
+        if kind == MediaDataKind::VideoDataIframe {
+            let segment_done = self
+                .segment
+                .as_ref()
+                .map_or(false, |segment| now - segment.start >= SEGMENT_DURATION);
+            if segment_done {
+                self.finish_segment(now);
+            }
+            // As for recordings, wait one keyframe interval to find out whether there is audio
+            if self.segment.is_none() && (self.seen_audio.is_some() || self.skipped_iframe) {
+                self.start_segment(media, now)?;
+            }
+            self.skipped_iframe = true;
+        }
+
+        match &self.segment {
+            Some(segment) => write_to_muxer(&segment.muxer, media, now - segment.start),
+            None => Ok(()),
+        }
+    }
+
+    fn start_segment(&mut self, media: &MediaData, now: Instant) -> io::Result<()> {
+        let video_format = match media.media_format() {
+            Some(format) => format,
+            None => return Ok(()),
+        };
+        // Each segment has its own file, as the last one may still be being finished
+        let sequence = self.playlist.next_sequence.fetch_add(1, Ordering::Relaxed);
+        let file_name = format!(
+            "neolink-hls-{}-{}-{}.mp4",
+            self.name,
+            std::process::id(),
+            sequence
+        );
+        let path = std::env::temp_dir().join(file_name);
+        let muxer = Muxer::new_file(
+            &path,
+            Container::FragmentedMp4,
+            video_format,
+            audio_format(self.seen_audio),
+        )?;
+        self.segment = Some(Pending {
+            muxer,
+            path,
+            sequence,
+            start: now,
+            end: now,
+        });
+        Ok(())
+    }
+
+    /// Hands the segment in progress to the finisher thread
+    fn finish_segment(&mut self, now: Instant) {
+        let mut segment = self.segment.take().expect("Segment should be in progress");
+        segment.end = now;
+        if let Some(finisher) = &self.finisher {
+            let _ = finisher.send(segment);
+        }
+    }
+
+    fn discard_segment(&mut self) {
+        if let Some(Pending { muxer, path, .. }) = self.segment.take() {
+            // Finish the muxer before deleting its file
+            drop(muxer);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+impl Drop for HlsWriter {
+    fn drop(&mut self) {
+        // The segment in progress is dropped unfinished, and the ones already recorded are waited
+        // for, so that none of them turn up in the playlist after it is cleared
+        self.discard_segment();
+        self.finisher = None;
+        if let Some(thread) = self.finisher_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Pending {
+    fn finish(self) -> io::Result<Segment> {
+        let result = self.muxer.finish().and_then(|_| fs::read(&self.path));
+        let _ = fs::remove_file(&self.path);
+        let data = result?;
+
+        let init_length = init_section_length(&data).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "Segment has no MP4 fragments")
+        })?;
+        Ok(Segment {
+            sequence: self.sequence,
+            duration: self.end - self.start,
+            init_length,
+            data: Arc::new(data),
+        })
+    }
+}
+
+/// Finds where the first fragment starts in a fragmented MP4 file, by walking the top level
+/// boxes up to the first `moof`
+fn init_section_length(data: &[u8]) -> Option<usize> {
+    let mut offset = 0;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ]) as usize;
+        if &data[offset + 4..offset + 8] == b"moof" {
+            return Some(offset);
+        }
+        // Sizes of 0 (to the end of the file) and 1 (64-bit) never come before the fragments
+        if size < 8 {
+            return None;
+        }
+        offset += size;
+    }
+    None
+}
+
+fn render_playlist<'s>(segments: impl Iterator<Item = &'s Segment> + Clone) -> String {
+    let target_duration = segments
+        .clone()
+        .map(|segment| segment.duration.as_secs_f64().ceil() as u64)
+        .max()
+        .unwrap_or(0);
+    let first_sequence = segments
+        .clone()
+        .next()
+        .map(|segment| segment.sequence)
+        .unwrap_or(0);
+
+    let mut playlist = String::new();
+    playlist += "#EXTM3U\n";
+    playlist += "#EXT-X-VERSION:7\n";
+    playlist += &format!("#EXT-X-TARGETDURATION:{}\n", target_duration);
+    playlist += &format!("#EXT-X-MEDIA-SEQUENCE:{}\n", first_sequence);
+    // Every segment is a discontinuity, so this counts the ones that have left the playlist
+    playlist += &format!("#EXT-X-DISCONTINUITY-SEQUENCE:{}\n", first_sequence);
+    for segment in segments {
+        let uri = format!("{}.mp4", segment.sequence);
+        let media_length = segment.data.len() - segment.init_length;
+        playlist += "#EXT-X-DISCONTINUITY\n";
+        playlist += &format!(
+            "#EXT-X-MAP:URI=\"{}\",BYTERANGE=\"{}@0\"\n",
+            uri, segment.init_length
+        );
+        playlist += &format!("#EXTINF:{:.3},\n", segment.duration.as_secs_f64());
+        playlist += &format!(
+            "#EXT-X-BYTERANGE:{}@{}\n",
+            media_length, segment.init_length
+        );
+        playlist += &uri;
+        playlist += "\n";
+    }
+    playlist
+}
+
+#[test]
+fn test_init_section_length() {
+    let mut mp4 = vec![];
+    for (size, kind) in &[(16u32, b"ftyp"), (24, b"moov"), (12, b"moof"), (8, b"mdat")] {
+        mp4.extend_from_slice(&size.to_be_bytes());
+        mp4.extend_from_slice(*kind);
+        mp4.resize(mp4.len() + *size as usize - 8, 0);
+    }
+    assert_eq!(init_section_length(&mp4), Some(40));
+    assert_eq!(init_section_length(&mp4[..40]), None);
+}
+
+#[test]
+fn test_render_playlist() {
+    let segment = |sequence, millis| Segment {
+        sequence,
+        duration: Duration::from_millis(millis),
+        init_length: 100,
+        data: Arc::new(vec![0; 1000]),
+    };
+    let segments = vec![segment(3, 2040), segment(4, 2500)];
+    assert_eq!(
+        render_playlist(segments.iter()),
+        "#EXTM3U\n\
+         #EXT-X-VERSION:7\n\
+         #EXT-X-TARGETDURATION:3\n\
+         #EXT-X-MEDIA-SEQUENCE:3\n\
+         #EXT-X-DISCONTINUITY-SEQUENCE:3\n\
+         #EXT-X-DISCONTINUITY\n\
+         #EXT-X-MAP:URI=\"3.mp4\",BYTERANGE=\"100@0\"\n\
+         #EXTINF:2.040,\n\
+         #EXT-X-BYTERANGE:900@100\n\
+         3.mp4\n\
+         #EXT-X-DISCONTINUITY\n\
+         #EXT-X-MAP:URI=\"4.mp4\",BYTERANGE=\"100@0\"\n\
+         #EXTINF:2.500,\n\
+         #EXT-X-BYTERANGE:900@100\n\
+         4.mp4\n"
+    );
+}
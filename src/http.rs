@@ -0,0 +1,363 @@
+//! A small HTTP server for things that RTSP cannot carry, such as still images.  It checks the
+//! same users and per-camera permitted_users as the RTSP server, using HTTP basic auth.
+//!
+//! - `/<camera name>/snapshot.jpg`: a still image from the camera's main stream
+//! - `/<camera name>/hls/index.m3u8`: the camera as HLS, if it has `hls = true`
+//! - `/<camera name>/whep`: the camera over WebRTC, by POSTing an SDP offer to it
+//! - `/api/...`: the management API, see the api module
+//! - `/metrics`: statistics for Prometheus, see the metrics module
+use crate::config::{TimeSyncConfig, UserConfig};
+use crate::health::CameraHealth;
+use crate::hls::HlsPlaylist;
+use crate::jobs::CameraJobs;
+use crate::whep::{WhepError, WhepSessions};
+use crate::Error;
+use log::*;
+use neolink::gst::RtspServer;
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Requests are handled on this many threads, so that one slow camera does not hold up the others
+const WORKERS: usize = 4;
+
+pub struct HttpCamera {
+    pub name: String,
+    pub permitted_users: HashSet<String>,
+    pub jobs: Arc<CameraJobs>,
+    pub hls: Option<Arc<HlsPlaylist>>,
+    pub whep: Arc<WhepSessions>,
+    pub health: Arc<CameraHealth>,
+    pub time_sync: TimeSyncConfig,
+    /// The camera's RTSP mounts
+    pub paths: Vec<String>,
+}
+
+/// The users and cameras can be changed while the server is running, when the config is reloaded
+pub struct HttpServer {
+    users: RwLock<Vec<UserConfig>>,
+    cameras: RwLock<Vec<Arc<HttpCamera>>>,
+}
+
+impl HttpServer {
+    pub fn new(users: &[UserConfig]) -> HttpServer {
+        HttpServer {
+            users: RwLock::new(users.to_vec()),
+            cameras: RwLock::new(vec![]),
+        }
+    }
+
+    pub fn set_users(&self, users: &[UserConfig]) {
+        *self.users.write().unwrap() = users.to_vec();
+    }
+
+    pub fn add_camera(&self, camera: HttpCamera) {
+        self.cameras.write().unwrap().push(Arc::new(camera));
+    }
+
+    pub fn remove_camera(&self, name: &str) {
+        self.cameras
+            .write()
+            .unwrap()
+            .retain(|camera| camera.name != name);
+    }
+
+    pub fn run(&self, bind_addr: &str, bind_port: u16, rtsp: &RtspServer) -> Result<(), Error> {
+        let server = Server::http((bind_addr, bind_port))
+            .map_err(|e| Error::HttpServerError(e.to_string()))?;
+        info!("HTTP server listening on {}:{}", bind_addr, bind_port);
+
+        crossbeam::scope(|s| {
+            for _ in 0..WORKERS {
+                s.spawn(|_| {
+                    for request in server.incoming_requests() {
+                        self.handle(request, rtsp);
+                    }
+                });
+            }
+        })
+        .unwrap();
+        Ok(())
+    }
+
+    fn handle(&self, mut request: Request, rtsp: &RtspServer) {
+        let path = request.url().split('?').next().unwrap_or("").to_string();
+        let mut parts = path.trim_start_matches('/').splitn(2, '/');
+        let camera_name = parts.next().unwrap_or("");
+        let resource = parts.next().unwrap_or("");
+
+        if camera_name == "api" || camera_name == "metrics" {
+            let user = self.user(&request);
+            let response = match self.permitted_cameras(user.as_deref()) {
+                Some(cameras) if camera_name == "api" => crate::api::response(
+                    request.method(),
+                    resource,
+                    &cameras,
+                    user.as_deref(),
+                    rtsp,
+                ),
+                Some(cameras) if resource.is_empty() => crate::metrics::response(&cameras, rtsp),
+                Some(_) => not_found(),
+                None => unauthorized(),
+            };
+            return respond(request, response);
+        }
+
+        let camera = self
+            .cameras
+            .read()
+            .unwrap()
+            .iter()
+            .find(|c| c.name == camera_name)
+            .cloned();
+        let camera = match camera {
+            Some(camera) => camera,
+            None => return respond(request, not_found()),
+        };
+        if !permits(&camera.permitted_users, self.user(&request).as_deref()) {
+            return respond(request, unauthorized());
+        }
+
+        if resource == "whep" || resource.starts_with("whep/") {
+            let response = whep_response(&mut request, &camera, resource);
+            return respond(request, response);
+        }
+
+        let response = match resource {
+            "snapshot.jpg" => match camera.jobs.run(|camera| camera.snapshot()) {
+                Ok(jpeg) => {
+                    let content_type =
+                        Header::from_bytes("Content-Type", "image/jpeg").expect("Header is valid");
+                    Response::from_data(jpeg).with_header(content_type)
+                }
+                Err(e) => {
+                    warn!("{}: Could not take a snapshot: {}", camera.name, e);
+                    Response::from_string(format!("Could not take a snapshot: {}", e))
+                        .with_status_code(503)
+                }
+            },
+            "hls/index.m3u8" => match camera.hls.as_deref().and_then(HlsPlaylist::playlist) {
+                Some(playlist) => {
+                    let content_type =
+                        Header::from_bytes("Content-Type", "application/vnd.apple.mpegurl")
+                            .expect("Header is valid");
+                    Response::from_string(playlist).with_header(content_type)
+                }
+                None => not_ready(camera.hls.as_deref()),
+            },
+            _ => match hls_segment(resource).and_then(|n| camera.hls.as_ref()?.segment(n)) {
+                Some(segment) => serve_range(&request, &segment.data, "video/mp4"),
+                None => not_found(),
+            },
+        };
+        respond(request, response);
+    }
+
+    /// The API and metrics show each user the cameras they may watch.  If there are users, they
+    /// need one of them to log in, or else this is None.
+    fn permitted_cameras(&self, user: Option<&str>) -> Option<Vec<Arc<HttpCamera>>> {
+        if user.is_none() && !self.users.read().unwrap().is_empty() {
+            return None;
+        }
+        let cameras = self
+            .cameras
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|camera| permits(&camera.permitted_users, user))
+            .cloned()
+            .collect();
+        Some(cameras)
+    }
+
+    /// The user whose name and password the request has, if they are right
+    fn user(&self, request: &Request) -> Option<String> {
+        let (name, pass) = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| parse_basic_auth(header.value.as_str()))?;
+        let users = self.users.read().unwrap();
+        if users
+            .iter()
+            .any(|user| user.name == name && user.pass == pass)
+        {
+            Some(name)
+        } else {
+            None
+        }
+    }
+}
+
+fn permits(permitted_users: &HashSet<String>, user: Option<&str>) -> bool {
+    match user {
+        _ if permitted_users.contains("anonymous") => true,
+        Some(name) => permitted_users.contains(name),
+        None => false,
+    }
+}
+
+fn unauthorized() -> Response<std::io::Cursor<Vec<u8>>> {
+    let challenge =
+        Header::from_bytes("WWW-Authenticate", "Basic realm=\"Neolink\"").expect("Header is valid");
+    Response::from_string("Unauthorized")
+        .with_status_code(401)
+        .with_header(challenge)
+}
+
+fn not_found() -> Response<std::io::Cursor<Vec<u8>>> {
+    Response::from_string("Not found").with_status_code(404)
+}
+
+/// Starts a WebRTC session from the offer in a POST, or ends one with a DELETE
+fn whep_response(
+    request: &mut Request,
+    camera: &HttpCamera,
+    resource: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let method = request.method().clone();
+    match (&method, resource) {
+        (Method::Post, "whep") => {
+            let mut offer = String::new();
+            if request.as_reader().read_to_string(&mut offer).is_err() {
+                return Response::from_string("Offer is not UTF-8").with_status_code(400);
+            }
+            match camera.whep.offer(&offer) {
+                Ok((id, answer)) => {
+                    let content_type = Header::from_bytes("Content-Type", "application/sdp")
+                        .expect("Header is valid");
+                    let location =
+                        Header::from_bytes("Location", format!("/{}/whep/{}", camera.name, id))
+                            .expect("Header is valid");
+                    Response::from_string(answer)
+                        .with_status_code(201)
+                        .with_header(content_type)
+                        .with_header(location)
+                }
+                Err(e @ WhepError::Answer(_)) => {
+                    warn!("{}: {}", camera.name, e);
+                    Response::from_string(e.to_string()).with_status_code(400)
+                }
+                Err(e) => Response::from_string(e.to_string()).with_status_code(503),
+            }
+        }
+        (Method::Delete, _) => {
+            let id = resource
+                .strip_prefix("whep/")
+                .and_then(|id| id.parse().ok());
+            match id {
+                Some(id) if camera.whep.delete(id) => Response::from_string(""),
+                _ => not_found(),
+            }
+        }
+        _ => Response::from_string("Method not allowed").with_status_code(405),
+    }
+}
+
+/// HLS is enabled but the first segment isn't finished yet
+fn not_ready(hls: Option<&HlsPlaylist>) -> Response<std::io::Cursor<Vec<u8>>> {
+    match hls {
+        Some(_) => Response::from_string("Stream is starting").with_status_code(503),
+        None => not_found(),
+    }
+}
+
+/// The sequence number of an HLS segment, from a path such as `hls/12.mp4`
+fn hls_segment(resource: &str) -> Option<u64> {
+    resource
+        .strip_prefix("hls/")?
+        .strip_suffix(".mp4")?
+        .parse()
+        .ok()
+}
+
+/// Responds with `data`, or the part of it asked for by a Range header, as HLS players do for
+/// byte range playlists
+fn serve_range(
+    request: &Request,
+    data: &[u8],
+    content_type: &str,
+) -> Response<std::io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", content_type).expect("Header is valid");
+    let range = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Range"))
+        .map(|header| parse_range(header.value.as_str(), data.len()));
+    match range {
+        None => Response::from_data(data.to_vec()).with_header(content_type),
+        Some(Some((start, end))) => {
+            let content_range = Header::from_bytes(
+                "Content-Range",
+                format!("bytes {}-{}/{}", start, end, data.len()),
+            )
+            .expect("Header is valid");
+            Response::from_data(data[start..=end].to_vec())
+                .with_status_code(206)
+                .with_header(content_type)
+                .with_header(content_range)
+        }
+        Some(None) => Response::from_string("Range not satisfiable").with_status_code(416),
+    }
+}
+
+/// Parses a `Range: bytes=...` header value into the first and last byte it asks for
+fn parse_range(value: &str, length: usize) -> Option<(usize, usize)> {
+    let mut bounds = value.strip_prefix("bytes=")?.trim().splitn(2, '-');
+    let (start, end) = (bounds.next()?, bounds.next()?);
+    let (start, end) = match (start, end) {
+        // The last `end` bytes
+        ("", end) => {
+            let suffix: usize = end.parse().ok()?;
+            (length.checked_sub(suffix)?, length.checked_sub(1)?)
+        }
+        (start, "") => (start.parse().ok()?, length.checked_sub(1)?),
+        (start, end) => {
+            let end: usize = end.parse().ok()?;
+            (start.parse().ok()?, end.min(length.checked_sub(1)?))
+        }
+    };
+    if start > end || end >= length {
+        return None;
+    }
+    Some((start, end))
+}
+
+fn respond<R: std::io::Read>(request: Request, response: Response<R>) {
+    if let Err(e) = request.respond(response) {
+        debug!("Could not send HTTP response: {}", e);
+    }
+}
+
+/// Extracts the user name and password from an `Authorization: Basic ...` header value
+fn parse_basic_auth(value: &str) -> Option<(String, String)> {
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(base64::decode(encoded.trim()).ok()?).ok()?;
+    let mut parts = decoded.splitn(2, ':');
+    Some((parts.next()?.to_string(), parts.next()?.to_string()))
+}
+
+#[test]
+fn test_parse_basic_auth() {
+    // "me:mepass"
+    assert_eq!(
+        parse_basic_auth("Basic bWU6bWVwYXNz"),
+        Some(("me".to_string(), "mepass".to_string()))
+    );
+    assert_eq!(parse_basic_auth("Bearer bWU6bWVwYXNz"), None);
+    // No colon
+    assert_eq!(parse_basic_auth("Basic bWU="), None);
+}
+
+#[test]
+fn test_parse_range() {
+    assert_eq!(parse_range("bytes=0-99", 1000), Some((0, 99)));
+    assert_eq!(parse_range("bytes=100-", 1000), Some((100, 999)));
+    assert_eq!(parse_range("bytes=-100", 1000), Some((900, 999)));
+    assert_eq!(parse_range("bytes=900-2000", 1000), Some((900, 999)));
+    assert_eq!(parse_range("bytes=1000-", 1000), None);
+    assert_eq!(parse_range("bytes=5-1", 1000), None);
+    assert_eq!(parse_range("items=0-99", 1000), None);
+    assert_eq!(parse_range("bytes=0-99", 0), None);
+}
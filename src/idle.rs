@@ -0,0 +1,101 @@
+//! Keeps track of whether anyone is watching a camera with `idle_disconnect`, so that it is only
+//! connected to while RTSP clients are playing it.
+use crossbeam::channel::{after, never, select, Receiver};
+use neolink::gst::ClientEvent;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub struct ClientTracker {
+    events: Receiver<ClientEvent>,
+    /// How many of the camera's streams are set up for clients
+    playing: AtomicUsize,
+    /// How long to stay connected once nobody is watching
+    timeout: Duration,
+}
+
+impl ClientTracker {
+    pub fn new(events: Receiver<ClientEvent>, timeout: Duration) -> ClientTracker {
+        ClientTracker {
+            events,
+            playing: AtomicUsize::new(0),
+            timeout,
+        }
+    }
+
+    pub fn is_watched(&self) -> bool {
+        self.playing.load(Ordering::Relaxed) > 0
+    }
+
+    /// Waits until a client starts playing one of the camera's streams.  Returns false if the
+    /// camera is stopped first.
+    pub fn wait_for_clients(&self, stop: &Receiver<()>) -> bool {
+        while !self.is_watched() {
+            select! {
+                recv(self.events) -> event => match event {
+                    Ok(event) => self.update(event),
+                    Err(_) => return false,
+                },
+                recv(stop) -> _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Waits until nobody has watched the camera for the timeout, and returns true, or returns
+    /// false if `stop` is signalled first
+    pub fn wait_until_idle(&self, stop: &Receiver<()>) -> bool {
+        loop {
+            let idle = if self.is_watched() {
+                never()
+            } else {
+                after(self.timeout)
+            };
+            select! {
+                recv(self.events) -> event => match event {
+                    Ok(event) => self.update(event),
+                    Err(_) => return false,
+                },
+                recv(stop) -> _ => return false,
+                recv(idle) -> _ => return true,
+            }
+        }
+    }
+
+    fn update(&self, event: ClientEvent) {
+        match event {
+            ClientEvent::Arrived => {
+                self.playing.fetch_add(1, Ordering::Relaxed);
+            }
+            ClientEvent::Left => {
+                let decrement = |playing: usize| playing.checked_sub(1);
+                let _ = self
+                    .playing
+                    .fetch_update(Ordering::Relaxed, Ordering::Relaxed, decrement);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_client_tracker() {
+    let (tx, rx) = crossbeam::channel::unbounded();
+    let (stop_tx, stop) = crossbeam::channel::bounded(0);
+    let clients = ClientTracker::new(rx, Duration::from_millis(10));
+    assert!(!clients.is_watched());
+
+    tx.send(ClientEvent::Arrived).unwrap();
+    assert!(clients.wait_for_clients(&stop));
+    assert!(clients.is_watched());
+
+    // A second client arrives before the first has gone, so it is not idle in between
+    tx.send(ClientEvent::Arrived).unwrap();
+    tx.send(ClientEvent::Left).unwrap();
+    tx.send(ClientEvent::Left).unwrap();
+    assert!(clients.wait_until_idle(&stop));
+    assert!(!clients.is_watched());
+
+    drop(stop_tx);
+    assert!(!clients.wait_for_clients(&stop));
+    tx.send(ClientEvent::Arrived).unwrap();
+    assert!(!clients.wait_until_idle(&stop));
+}
@@ -0,0 +1,91 @@
+//! Lets threads outside a camera's session, such as the HTTP server, use the camera.  They send
+//! a job, which runs on one of the session's threads while the camera is connected.
+use crossbeam::channel::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use neolink::bc_protocol::BcCamera;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// How long to wait for a job to finish before giving up on it
+const JOB_TIMEOUT: Duration = Duration::from_secs(15);
+
+type Job = Box<dyn FnOnce(&BcCamera) + Send>;
+
+pub struct CameraJobs {
+    tx: Sender<Job>,
+    rx: Receiver<Job>,
+    connected: AtomicBool,
+    reconnect_tx: Sender<()>,
+    reconnect_rx: Receiver<()>,
+}
+
+impl Default for CameraJobs {
+    fn default() -> Self {
+        let (tx, rx) = channel::unbounded();
+        let (reconnect_tx, reconnect_rx) = channel::bounded(1);
+        CameraJobs {
+            tx,
+            rx,
+            connected: AtomicBool::new(false),
+            reconnect_tx,
+            reconnect_rx,
+        }
+    }
+}
+
+impl CameraJobs {
+    /// Runs `job` on the camera and waits for its result.  Fails straight away if the camera is
+    /// not connected, rather than running the job whenever it reconnects.
+    pub fn run<T, F>(&self, job: F) -> Result<T, neolink::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&BcCamera) -> Result<T, neolink::Error> + Send + 'static,
+    {
+        if !self.connected.load(Ordering::Relaxed) {
+            return Err(neolink::Error::Other("Camera is not connected"));
+        }
+
+        let (result_tx, result_rx) = channel::bounded(1);
+        let job: Job = Box::new(move |camera| {
+            let _ = result_tx.send(job(camera));
+        });
+        self.tx
+            .send(job)
+            .expect("The receiver lives as long as the sender");
+
+        match result_rx.recv_timeout(JOB_TIMEOUT) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(neolink::Error::Timeout),
+            // The session ended before it got to our job
+            Err(RecvTimeoutError::Disconnected) => {
+                Err(neolink::Error::Other("Camera disconnected"))
+            }
+        }
+    }
+
+    /// Asks for the camera to be reconnected now: the session is ended if there is one, and
+    /// otherwise the wait before trying again is cut short
+    pub fn reconnect(&self) {
+        // One request is enough, however many are made
+        let _ = self.reconnect_tx.try_send(());
+    }
+
+    /// Receives the requests made with reconnect()
+    pub fn reconnects(&self) -> &Receiver<()> {
+        &self.reconnect_rx
+    }
+
+    /// Runs jobs on a logged in camera until `stop` is signalled or disconnected
+    pub fn serve(&self, camera: &BcCamera, stop: &Receiver<()>) {
+        self.connected.store(true, Ordering::Relaxed);
+        while let Err(TryRecvError::Empty) = stop.try_recv() {
+            if let Ok(job) = self.rx.recv_timeout(Duration::from_millis(200)) {
+                job(camera);
+            }
+        }
+        self.connected.store(false, Ordering::Relaxed);
+
+        // Throw away anything that arrived while we were stopping, so that it fails now instead
+        // of running on the next session
+        while self.rx.try_recv().is_ok() {}
+    }
+}
@@ -2,6 +2,7 @@
 ***REMOVED***![allow(unused_variables)]
 pub mod bc;
 pub mod bc_protocol;
+pub mod bcudp;
 pub mod gst;
 
 ***REMOVED***[derive(Debug)]
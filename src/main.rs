@@ -1,22 +1,53 @@
+use crossbeam::channel::{select, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use env_logger::Env;
 use err_derive::Error;
 use gio::TlsAuthenticationMode;
 use log::*;
-use neolink::bc_protocol::BcCamera;
-use neolink::gst::{GstOutputs, RtspServer};
+use neolink::bc::xml::DeviceInfo;
+use neolink::bc_protocol::{BcCamera, GeneralSettings};
+use neolink::gst::{GstOutputs, PlaybackRequest, RtspServer};
 use neolink::Never;
 use std::collections::HashSet;
-use std::fs;
+use std::sync::mpsc::channel;
 use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use structopt::StructOpt;
-use validator::Validate;
 
+mod api;
+mod clips;
 mod cmdline;
 mod config;
-
-use cmdline::Opt;
-use config::{CameraConfig, Config, UserConfig};
+mod discover;
+mod health;
+mod hls;
+mod http;
+mod idle;
+mod jobs;
+mod maintenance;
+mod metrics;
+mod mqtt;
+mod playback;
+mod record;
+mod reload;
+mod settings;
+mod talk;
+mod time_sync;
+mod whep;
+
+use clips::ClipRecorder;
+use cmdline::{Command, Opt};
+use config::{CameraConfig, Config, RecordingConfig, UserConfig};
+use health::{CameraHealth, ConnectionState};
+use hls::{HlsPlaylist, HlsWriter};
+use http::{HttpCamera, HttpServer};
+use idle::ClientTracker;
+use jobs::CameraJobs;
+use mqtt::Mqtt;
+use record::{RecordedStream, Recorder};
+use reload::ConfigWatcher;
+use talk::TALK_SAMPLE_RATE;
+use whep::WhepSessions;
 
 ***REMOVED***[derive(Debug, Error)]
 ***REMOVED***[allow(clippy::large_enum_variant)]
@@ -31,6 +62,20 @@ pub enum Error {
     ValidationError(***REMOVED***[error(source)] validator::ValidationErrors),
     ***REMOVED***[error(display = "ADPCM Decoding Error")]
     AdpcmDecodingError(&'static str),
+    #[error(display = "MQTT client error")]
+    MqttClientError(#[error(source)] rumqttc::ClientError),
+    #[error(display = "A config file must be given with --config")]
+    MissingConfig,
+    #[error(display = "HTTP server error: {}", _0)]
+    HttpServerError(String),
+    #[error(display = "No camera called {} in the config file", _0)]
+    UnknownCamera(String),
+    #[error(display = "Could not understand the time {}", _0)]
+    InvalidTime(String),
+    #[error(display = "Not doing the {} without --confirm", _0)]
+    NotConfirmed(&'static str),
+    #[error(display = "{} is not a firmware file", _0)]
+    InvalidFirmware(String),
 }
 
 fn main() -> Result<(), Error> {
@@ -43,9 +88,52 @@ fn main() -> Result<(), Error> {
     );
 
     let opt = Opt::from_args();
-    let config: Config = toml::from_str(&fs::read_to_string(opt.config)?)?;
+    if let Some(Command::Discover { timeout, toml }) = opt.cmd {
+        return discover::main(Duration::from_secs(timeout), toml);
+    }
 
-    config.validate()?;
+    let config_path = opt.config.ok_or(Error::MissingConfig)?;
+    let config = reload::read_config(&config_path)?;
+
+    match opt.cmd {
+        Some(Command::Recordings {
+            camera,
+            start,
+            end,
+            stream,
+        }) => return playback::list(&config, &camera, start.as_deref(), end.as_deref(), &stream),
+        Some(Command::Download {
+            camera,
+            name,
+            output,
+        }) => return playback::download(&config, &camera, &name, &output),
+        Some(Command::ConfigCamera {
+            camera,
+            name,
+            date_format,
+            time_format,
+            language,
+            time_zone,
+        }) => {
+            let changes = GeneralSettings {
+                device_name: name,
+                date_format,
+                time_format,
+                language,
+                time_zone,
+            };
+            return settings::main(&config, &camera, &changes);
+        }
+        Some(Command::Reboot { camera, confirm }) => {
+            return maintenance::reboot(&config, &camera, confirm)
+        }
+        Some(Command::Upgrade {
+            camera,
+            firmware,
+            confirm,
+        }) => return maintenance::upgrade(&config, &camera, &firmware, confirm),
+        _ => {}
+    }
 
     let rtsp = &RtspServer::new();
 
@@ -59,63 +147,303 @@ fn main() -> Result<(), Error> {
         )
     }
 
+    let http = &HttpServer::new(&config.users);
+    let mut cameras: Vec<RunningCamera> = config
+        .cameras
+        .iter()
+        .map(|camera| start_camera(camera, &config, rtsp, http))
+        .collect();
+
+    let bind_addr = config.bind_addr.clone();
+    let bind_port = config.bind_port;
     crossbeam::scope(|s| {
-        for camera in config.cameras {
-            if camera.format.is_some() {
-                warn!("The format config option of the camera has been removed in favour of auto detection.")
-            }
-            // Let subthreads share the camera object; in principle I think they could share
-            // the object as it sits in the config.cameras block, but I have not figured out the
-            // syntax for that.
-            let arc_cam = Arc::new(camera);
-
-            let permitted_users =
-                get_permitted_users(config.users.as_slice(), &arc_cam.permitted_users);
-
-            // Set up each main and substream according to all the RTSP mount paths we support
-            if ["both", "mainStream"].iter().any(|&e| e == arc_cam.stream) {
-                let paths = &[
-                    &*format!("/{}", arc_cam.name),
-                    &*format!("/{}/mainStream", arc_cam.name),
-                ];
-                let mut outputs = rtsp
-                    .add_stream(paths, &permitted_users)
-                    .unwrap();
-                let main_camera = arc_cam.clone();
-                s.spawn(move |_| camera_loop(&*main_camera, "mainStream", &mut outputs, true));
-            }
-            if ["both", "subStream"].iter().any(|&e| e == arc_cam.stream) {
-                let paths = &[&*format!("/{}/subStream", arc_cam.name)];
-                let mut outputs = rtsp
-                    .add_stream(paths, &permitted_users)
-                    .unwrap();
-                let sub_camera = arc_cam.clone();
-                let manage = arc_cam.stream == "subStream";
-                s.spawn(move |_| camera_loop(&*sub_camera, "subStream", &mut outputs, manage));
+        if let Some(http_bind_port) = config.http_bind_port {
+            if !config.users.is_empty() {
+                warn!("The HTTP server does not use TLS, so passwords will be sent in plaintext!");
             }
+            let bind_addr = &bind_addr;
+            s.spawn(move |_| {
+                if let Err(e) = http.run(bind_addr, http_bind_port, rtsp) {
+                    error!("{}", e);
+                }
+            });
         }
 
-        rtsp.run(&config.bind_addr, config.bind_port);
+        s.spawn(move |_| {
+            let mut watcher = ConfigWatcher::new(&config_path);
+            let mut config = config;
+            loop {
+                let new_config = watcher.wait();
+                apply_config(&config, &new_config, &mut cameras, rtsp, http);
+                config = new_config;
+            }
+        });
+
+        rtsp.run(&bind_addr, bind_port);
     })
     .unwrap();
 
     Ok(())
 }
 
+/// A camera that is being served, and how to stop it
+struct RunningCamera {
+    name: String,
+    /// Its RTSP mounts
+    paths: Vec<String>,
+    /// Dropped to stop the camera's thread
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+/// Everything a camera's thread uses besides its config and outputs.  Much of it is shared with
+/// the HTTP server.
+struct CameraState {
+    health: Arc<CameraHealth>,
+    jobs: Arc<CameraJobs>,
+    /// Only set with idle_disconnect
+    clients: Option<ClientTracker>,
+    hls: Option<Arc<HlsPlaylist>>,
+    whep: Arc<WhepSessions>,
+    backchannel: Option<Receiver<Vec<u8>>>,
+    playback_requests: Receiver<PlaybackRequest>,
+    recording: RecordingConfig,
+    /// Closed to stop the camera
+    stop: Receiver<()>,
+}
+
+/// Mounts a camera on the RTSP and HTTP servers, and starts streaming from it on a thread of its
+/// own
+fn start_camera(
+    camera: &CameraConfig,
+    config: &Config,
+    rtsp: &RtspServer,
+    http: &HttpServer,
+) -> RunningCamera {
+    if camera.format.is_some() {
+        warn!(
+            "The format config option of the camera has been removed in favour of auto detection."
+        )
+    }
+
+    let permitted_users = get_permitted_users(config.users.as_slice(), &camera.permitted_users);
+
+    // Set up each main and substream according to all the RTSP mount paths we support
+    let mut paths = vec![];
+    let mut outputs = vec![];
+    if ["both", "mainStream"].iter().any(|&e| e == camera.stream) {
+        let main_paths = [
+            format!("/{}", camera.name),
+            format!("/{}/mainStream", camera.name),
+        ];
+        let main_outputs = rtsp
+            .add_stream(&[&*main_paths[0], &*main_paths[1]], &permitted_users)
+            .unwrap();
+        outputs.push(("mainStream", main_outputs));
+        paths.extend_from_slice(&main_paths);
+    }
+    if ["both", "subStream"].iter().any(|&e| e == camera.stream) {
+        let sub_path = format!("/{}/subStream", camera.name);
+        let sub_outputs = rtsp.add_stream(&[&*sub_path], &permitted_users).unwrap();
+        outputs.push(("subStream", sub_outputs));
+        paths.push(sub_path);
+    }
+
+    let backchannel = if camera.talkback {
+        // A couple of seconds of audio; any more and the camera has fallen hopelessly behind
+        let (tx, rx) = crossbeam::channel::bounded(50);
+        for (_, stream_outputs) in &outputs {
+            stream_outputs.enable_backchannel(TALK_SAMPLE_RATE, tx.clone());
+        }
+        Some(rx)
+    } else {
+        None
+    };
+
+    // Recordings are played back in the format of the best stream
+    let (playback_tx, playback_rx) = crossbeam::channel::bounded(4);
+    let playback_path = format!("/{}/playback", camera.name);
+    rtsp.add_playback(
+        &[&*playback_path],
+        &permitted_users,
+        &mut outputs[0].1,
+        playback_tx,
+    );
+    paths.push(playback_path);
+
+    let clients = if !camera.idle_disconnect {
+        None
+    } else if camera.record || camera.clips.is_some() || camera.hls || camera.mqtt.is_some() {
+        warn!(
+            "{}: idle_disconnect is ignored, because record, clips, hls and mqtt need the camera all the time",
+            camera.name
+        );
+        None
+    } else {
+        if config.http_bind_port.is_some() {
+            warn!(
+                "{}: With idle_disconnect, snapshots, WebRTC and API actions only work while an RTSP client is playing the camera",
+                camera.name
+            );
+        }
+        let (tx, rx) = crossbeam::channel::unbounded();
+        for (_, stream_outputs) in &outputs {
+            stream_outputs.watch_clients(tx.clone());
+        }
+        let timeout = Duration::from_secs(camera.idle_timeout);
+        Some(ClientTracker::new(rx, timeout))
+    };
+
+    let jobs = Arc::new(CameraJobs::default());
+    let hls = if camera.hls {
+        Some(Arc::new(HlsPlaylist::default()))
+    } else {
+        None
+    };
+    if hls.is_some() && config.http_bind_port.is_none() {
+        warn!(
+            "{}: HLS is only served if http_bind_port is set",
+            camera.name
+        );
+    }
+    let whep = Arc::new(WhepSessions::default());
+    let health = Arc::new(CameraHealth::default());
+
+    http.add_camera(HttpCamera {
+        name: camera.name.clone(),
+        permitted_users: permitted_users
+            .iter()
+            .map(|user| user.to_string())
+            .collect(),
+        jobs: jobs.clone(),
+        hls: hls.clone(),
+        whep: whep.clone(),
+        health: health.clone(),
+        time_sync: camera.time_sync.clone(),
+        paths: paths.clone(),
+    });
+
+    let (stop, stop_rx) = crossbeam::channel::bounded(0);
+    let camera_config = camera.clone();
+    let state = CameraState {
+        health,
+        jobs,
+        clients,
+        hls,
+        whep,
+        backchannel,
+        playback_requests: playback_rx,
+        recording: config.recording.clone(),
+        stop: stop_rx,
+    };
+    let thread = std::thread::spawn(move || {
+        let _ = camera_loop(&camera_config, &mut outputs, &state);
+    });
+
+    RunningCamera {
+        name: camera.name.clone(),
+        paths,
+        stop,
+        thread,
+    }
+}
+
+/// Changes from running `old` to running `new`, restarting only the cameras that have to
+fn apply_config(
+    old: &Config,
+    new: &Config,
+    cameras: &mut Vec<RunningCamera>,
+    rtsp: &RtspServer,
+    http: &HttpServer,
+) {
+    let changes = reload::diff(old, new);
+    for setting in &changes.needs_restart {
+        warn!(
+            "Neolink must be restarted for the new {} to be used",
+            setting
+        );
+    }
+
+    // Take the mounts down straight away, but stop all of the cameras before waiting for any
+    let (stopped, running) = std::mem::take(cameras)
+        .into_iter()
+        .partition::<Vec<_>, _>(|camera| changes.stopped.contains(&camera.name));
+    *cameras = running;
+    let mut threads = vec![];
+    for camera in stopped {
+        info!("{}: Stopping", camera.name);
+        let paths: Vec<&str> = camera.paths.iter().map(String::as_str).collect();
+        rtsp.remove_mounts(&paths);
+        http.remove_camera(&camera.name);
+        drop(camera.stop);
+        threads.push(camera.thread);
+    }
+    for thread in threads {
+        let _ = thread.join();
+    }
+
+    if changes.users_changed {
+        set_up_users(&new.users, rtsp);
+        http.set_users(&new.users);
+    }
+
+    for camera in &changes.started {
+        info!("{}: Starting", camera.name);
+        cameras.push(start_camera(camera, new, rtsp, http));
+    }
+}
+
 fn camera_loop(
     camera_config: &CameraConfig,
-    stream_name: &str,
-    outputs: &mut GstOutputs,
-    manage: bool,
-) -> Result<Never, Error> {
+    outputs: &mut [(&'static str, GstOutputs)],
+    state: &CameraState,
+) -> Result<(), Error> {
     let min_backoff = Duration::from_secs(1);
     let max_backoff = Duration::from_secs(15);
     let mut current_backoff = min_backoff;
 
+    let mqtt = match &camera_config.mqtt {
+        Some(mqtt_config) => Some(Mqtt::new(camera_config, mqtt_config)?),
+        None => None,
+    };
+
+    // The formats of the streams are only learnt from the camera, and the RTSP mounts cannot
+    // serve clients until they know them.  So with idle_disconnect, we connect once at startup
+    // to learn them, and they are kept for clients while we are disconnected.
+    let formats_known = |outputs: &[(&'static str, GstOutputs)]| {
+        outputs
+            .iter()
+            .all(|(_, stream_outputs)| stream_outputs.has_video_format())
+    };
+
     loop {
-        let cam_err = camera_main(camera_config, stream_name, outputs, manage).unwrap_err();
-        outputs.vidsrc.on_stream_error();
-        outputs.audsrc.on_stream_error();
+        if let Some(clients) = &state.clients {
+            if !clients.is_watched() && formats_known(outputs) {
+                state.health.set_state(ConnectionState::Idle);
+                info!("{}: Waiting for an RTSP client", camera_config.name);
+                if !clients.wait_for_clients(&state.stop) {
+                    info!("{}: Stopped", camera_config.name);
+                    return Ok(());
+                }
+            }
+        }
+
+        let cam_err = camera_main(camera_config, outputs, mqtt.as_ref(), state).unwrap_err();
+        state.health.set_ping_latency(None);
+        if let Some(hls) = &state.hls {
+            hls.clear();
+        }
+        state.whep.clear();
+        for (_, stream_outputs) in outputs.iter_mut() {
+            stream_outputs.on_stream_error();
+        }
+        if let Some(mqtt) = &mqtt {
+            mqtt.set_disconnected();
+        }
+        if let Err(TryRecvError::Disconnected) = state.stop.try_recv() {
+            info!("{}: Stopped", camera_config.name);
+            return Ok(());
+        }
         // Authentication failures are permanent; we retry everything else
         if cam_err.connected {
             current_backoff = min_backoff;
@@ -126,8 +454,16 @@ fn camera_loop(
                     "Authentication failed to camera {}, not retrying",
                     camera_config.name
                 );
+                state.health.set_state(ConnectionState::AuthFailed);
                 return Err(cam_err.err.into());
             }
+            // Nobody is watching, so wait for a client rather than reconnecting straight away
+            _ if state.clients.iter().any(|clients| !clients.is_watched())
+                && formats_known(outputs) =>
+            {
+                info!("{}: Disconnected, nobody is watching", camera_config.name);
+                continue;
+            }
             _ => error!(
                 "Error streaming from camera {}, will retry in {}s: {}",
                 camera_config.name,
@@ -136,7 +472,22 @@ fn camera_loop(
             ),
         }
 
-        std::thread::sleep(current_backoff);
+        if cam_err.connected {
+            state.health.connection_lost();
+        }
+        state.health.set_retrying(current_backoff);
+        select! {
+            recv(state.stop) -> _ => {
+                info!("{}: Stopped", camera_config.name);
+                return Ok(());
+            }
+            recv(state.jobs.reconnects()) -> _ => {
+                info!("{}: Reconnecting now", camera_config.name);
+                current_backoff = min_backoff;
+                continue;
+            }
+            default(current_backoff) => {}
+        }
         current_backoff = std::cmp::min(max_backoff, current_backoff * 2);
     }
 }
@@ -193,84 +544,238 @@ fn get_permitted_users<'a>(
 
 fn camera_main(
     camera_config: &CameraConfig,
-    stream_name: &str,
-    outputs: &mut GstOutputs,
-    manage: bool,
+    outputs: &mut [(&'static str, GstOutputs)],
+    mqtt: Option<&Mqtt>,
+    state: &CameraState,
 ) -> Result<Never, CameraErr> {
     let mut connected = false;
     (|| {
-        let mut camera = BcCamera::new_with_addr(&camera_config.camera_addr, camera_config.channel_id)?;
         if camera_config.timeout.is_some() {
             warn!("The undocumented `timeout` config option has been removed and is no longer needed.");
             warn!("Please update your config file.");
         }
 
-        info!(
-            "{}: Connecting to camera at {}",
-            camera_config.name, camera_config.camera_addr
-        );
-
-        camera.login(&camera_config.username, camera_config.password.as_deref())?;
+        state.health.set_state(ConnectionState::Connecting);
+        let (mut camera, device_info) = connect_camera(camera_config)?;
 
         connected = true;
         info!("{}: Connected and logged in", camera_config.name);
-
-        if manage {
-            do_camera_management(&mut camera, camera_config)?;
-        }
-
-        info!(
-            "{}: Starting video stream {}",
-            camera_config.name, stream_name
-        );
-        camera.start_video(outputs, stream_name)
+        state.health.set_state(ConnectionState::Connected);
+        state.health.set_device_info(device_info);
+
+        do_camera_management(&mut camera, camera_config, &state.health)?;
+
+        // All streams and the MQTT bridge share this one session.  Each of them runs until the
+        // connection fails, so the first one to stop tells us why the session ended; shut the
+        // connection down so that the others notice too.
+        let camera = &camera;
+        let playback_stream = outputs[0].0;
+        crossbeam::scope(|s| {
+            let (result_tx, result_rx) = channel();
+            // Threads that do not stop by themselves when the connection fails wait on this
+            let (stop_tx, stop_rx) = crossbeam::channel::bounded::<()>(0);
+            let (motion_tx, motion_rx) = crossbeam::channel::unbounded();
+            for (i, (stream_name, stream_outputs)) in outputs.iter_mut().enumerate() {
+                let stream_name: &'static str = *stream_name;
+                let result_tx = result_tx.clone();
+                // Only the best of the streams is recorded
+                let mut stream = RecordedStream {
+                    name: stream_name,
+                    health: &state.health,
+                    outputs: stream_outputs,
+                    recorder: None,
+                    clips: None,
+                    hls: None,
+                    whep: None,
+                };
+                if i == 0 {
+                    if camera_config.record {
+                        stream.recorder = Some(Recorder::new(camera_config, &state.recording));
+                    }
+                    if let Some(hls) = &state.hls {
+                        stream.hls = Some(HlsWriter::new(camera_config, hls.clone()));
+                    }
+                    stream.whep = Some(&state.whep);
+                    if let Some(clip_config) = &camera_config.clips {
+                        stream.clips = Some(ClipRecorder::new(
+                            camera_config,
+                            &state.recording,
+                            clip_config,
+                            motion_rx.clone(),
+                        ));
+                    }
+                }
+                s.spawn(move |_| {
+                    info!(
+                        "{}: Starting video stream {}",
+                        camera_config.name, stream_name
+                    );
+                    let _ = result_tx.send(camera.start_video(&mut stream, stream_name));
+                });
+            }
+            if camera_config.clips.is_some() {
+                let result_tx = result_tx.clone();
+                s.spawn(move |_| {
+                    if let Err(err) = clips::watch_motion(camera, camera_config, motion_tx) {
+                        let _ = result_tx.send(Err(err));
+                    }
+                });
+            }
+            if let Some(mqtt) = mqtt {
+                let result_tx = result_tx.clone();
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    if let Err(err) = mqtt.run(camera, camera_config, &state.health, &stop_rx) {
+                        let _ = result_tx.send(Err(err));
+                    }
+                });
+            }
+            if camera_config.keepalive_interval > 0 {
+                let result_tx = result_tx.clone();
+                let interval = Duration::from_secs(camera_config.keepalive_interval);
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    if let Err(err) = keepalive(camera, interval, &state.health, stop_rx) {
+                        let _ = result_tx.send(Err(err));
+                    }
+                });
+            }
+            if camera_config.time_sync.mode != "off" && camera_config.time_sync.interval > 0 {
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| time_sync::sync_loop(camera, camera_config, stop_rx));
+            }
+            if let Some(backchannel) = &state.backchannel {
+                let result_tx = result_tx.clone();
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    if let Err(err) = talk::talk_loop(camera, camera_config, backchannel, &stop_rx)
+                    {
+                        let _ = result_tx.send(Err(err));
+                    }
+                });
+            }
+            {
+                let result_tx = result_tx.clone();
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    let requests = &state.playback_requests;
+                    let stream = playback_stream;
+                    if let Err(err) =
+                        playback::serve(camera, camera_config, stream, requests, &stop_rx)
+                    {
+                        let _ = result_tx.send(Err(err));
+                    }
+                });
+            }
+            if let Some(clients) = &state.clients {
+                // Ends the session once nobody has been watching for a while
+                let result_tx = result_tx.clone();
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    if clients.wait_until_idle(&stop_rx) {
+                        let idle = neolink::Error::Other("Nobody is watching");
+                        let _ = result_tx.send(Err(idle));
+                    }
+                });
+            }
+            {
+                // Ends the session when the camera is being stopped, such as for a config reload,
+                // or when asked to reconnect
+                let result_tx = result_tx.clone();
+                let stop_rx = stop_rx.clone();
+                s.spawn(move |_| {
+                    let stopped = neolink::Error::Other("Camera was stopped");
+                    let reconnect = neolink::Error::Other("Reconnect requested");
+                    select! {
+                        recv(state.stop) -> _ => {
+                            let _ = result_tx.send(Err(stopped));
+                        }
+                        recv(state.jobs.reconnects()) -> _ => {
+                            info!("{}: Reconnecting now", camera_config.name);
+                            let _ = result_tx.send(Err(reconnect));
+                        }
+                        recv(stop_rx) -> _ => {}
+                    }
+                });
+            }
+            s.spawn(move |_| state.jobs.serve(camera, &stop_rx));
+            drop(result_tx);
+
+            let result = result_rx
+                .recv()
+                .expect("At least one stream should be running");
+            drop(stop_tx);
+            if camera.deserialization_failed() {
+                state.health.add_deserialization_error();
+            }
+            camera.shutdown();
+            result
+        })
+        .unwrap()
     })()
     .map_err(|err| CameraErr { connected, err })
 }
 
+/// The config of the camera called `name`, for subcommands that are given a camera
+fn find_camera<'a>(config: &'a Config, name: &str) -> Result<&'a CameraConfig, Error> {
+    config
+        .cameras
+        .iter()
+        .find(|camera| camera.name == name)
+        .ok_or_else(|| Error::UnknownCamera(name.to_string()))
+}
+
+/// Connects to the camera and logs in, returning the DeviceInfo the camera logged in with
+fn connect_camera(camera_config: &CameraConfig) -> Result<(BcCamera, DeviceInfo), neolink::Error> {
+    let mut camera = match (&camera_config.camera_addr, &camera_config.uid) {
+        (Some(addr), _) => BcCamera::new_with_addr(addr, camera_config.channel_id)?,
+        (None, Some(uid)) => BcCamera::new_with_uid(uid, camera_config.channel_id)?,
+        (None, None) => unreachable!("Config validation requires an address or UID"),
+    };
+
+    info!(
+        "{}: Connecting to camera at {}",
+        camera_config.name,
+        camera.address()
+    );
+
+    let device_info = camera.login(&camera_config.username, camera_config.password.as_deref())?;
+    Ok((camera, device_info))
+}
+
+/// Pings the camera every `interval` until told to stop.  A camera that has gone away without
+/// closing the TCP connection would otherwise only be noticed when a video read times out, and
+/// not at all if we are not streaming.
+fn keepalive(
+    camera: &BcCamera,
+    interval: Duration,
+    health: &CameraHealth,
+    stop: Receiver<()>,
+) -> Result<(), neolink::Error> {
+    loop {
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => return Ok(()),
+        }
+        let latency = camera.ping()?;
+        trace!("Ping round trip {}ms", latency.as_millis());
+        health.set_ping_latency(Some(latency));
+    }
+}
+
 fn do_camera_management(
     camera: &mut BcCamera,
     camera_config: &CameraConfig,
+    health: &CameraHealth,
 ) -> Result<(), neolink::Error> {
-    let cam_time = camera.get_time()?;
-    if let Some(time) = cam_time {
-        info!(
-            "{}: Camera time is already set: {}",
-            camera_config.name, time
-        );
-    } else {
-        use time::OffsetDateTime;
-        // We'd like now_local() but it's deprecated - try to get the local time, but if no
-        // time zone, fall back to UTC.
-        let new_time =
-            OffsetDateTime::try_now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
-
-        warn!(
-            "{}: Camera has no time set, setting to {}",
-            camera_config.name, new_time
-        );
-        camera.set_time(new_time)?;
-        let cam_time = camera.get_time()?;
-        if let Some(time) = cam_time {
-            info!("{}: Camera time is now set: {}", camera_config.name, time);
-        } else {
-            error!(
-                "{}: Camera did not accept new time (is {} an admin?)",
-                camera_config.name, camera_config.username
-            );
-        }
-    }
+    time_sync::sync_time(camera, camera_config)?;
 
-    use neolink::bc::xml::VersionInfo;
-    if let Ok(VersionInfo {
-        firmwareVersion: firmware_version,
-        ..
-    }) = camera.version()
-    {
+    if let Ok(version) = camera.version() {
         info!(
             "{}: Camera reports firmware version {}",
-            camera_config.name, firmware_version
+            camera_config.name, version.firmwareVersion
         );
+        health.set_version(version);
     } else {
         info!(
             "{}: Could not fetch version information",
@@ -0,0 +1,75 @@
+//! The `reboot` and `upgrade` subcommands.  Both interrupt the camera, so they do nothing unless
+//! `--confirm` is given.
+use crate::config::Config;
+use crate::Error;
+use log::*;
+use std::path::Path;
+
+/// How often to log the progress of an upgrade, in percent
+const PROGRESS_STEP: usize = 10;
+
+pub fn reboot(config: &Config, camera_name: &str, confirm: bool) -> Result<(), Error> {
+    let camera_config = crate::find_camera(config, camera_name)?;
+    if !confirm {
+        return Err(Error::NotConfirmed("reboot"));
+    }
+    let (camera, _) = crate::connect_camera(camera_config)?;
+
+    camera.reboot()?;
+    info!("{}: Rebooting", camera_config.name);
+    Ok(())
+}
+
+/// Sends the firmware file at `path` to the camera, logging how much has been sent as it goes
+pub fn upgrade(
+    config: &Config,
+    camera_name: &str,
+    path: &Path,
+    confirm: bool,
+) -> Result<(), Error> {
+    let camera_config = crate::find_camera(config, camera_name)?;
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| Error::InvalidFirmware(path.display().to_string()))?;
+    let firmware = std::fs::read(path)?;
+    if firmware.is_empty() {
+        return Err(Error::InvalidFirmware(path.display().to_string()));
+    }
+    if !confirm {
+        return Err(Error::NotConfirmed("upgrade"));
+    }
+    let (camera, _) = crate::connect_camera(camera_config)?;
+
+    let version = camera.version()?;
+    info!(
+        "{}: Upgrading firmware {} to {}",
+        camera_config.name, version.firmwareVersion, file_name
+    );
+    let mut logged = 0;
+    camera.upgrade_firmware(file_name, &firmware, |sent, total| {
+        let percent = percent_sent(sent, total);
+        if percent >= logged + PROGRESS_STEP || sent == total {
+            info!("{}: Sent {}% of the firmware", camera_config.name, percent);
+            logged = percent - percent % PROGRESS_STEP;
+        }
+    })?;
+    info!(
+        "{}: The camera is installing the firmware and will reboot when it is done. \
+         Do not unplug it in the meantime.",
+        camera_config.name
+    );
+    Ok(())
+}
+
+fn percent_sent(sent: usize, total: usize) -> usize {
+    (sent as u64 * 100 / total as u64) as usize
+}
+
+#[test]
+fn test_percent_sent() {
+    assert_eq!(percent_sent(0, 19353542), 0);
+    assert_eq!(percent_sent(40960, 19353542), 0);
+    assert_eq!(percent_sent(9676771, 19353542), 50);
+    assert_eq!(percent_sent(19353542, 19353542), 100);
+}
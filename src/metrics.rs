@@ -0,0 +1,274 @@
+//! Statistics about each camera and its streams in the Prometheus text format, served by the HTTP
+//! server at `/metrics`.  Like the API, each user only sees the cameras they may watch.
+use crate::health::{CameraStatus, ConnectionState, MediaCount, StreamStats};
+use crate::http::HttpCamera;
+use neolink::bc_protocol::MediaDataKind;
+use neolink::gst::RtspServer;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io::Cursor;
+use std::sync::Arc;
+use std::time::Instant;
+use tiny_http::{Header, Response};
+
+const STATES: [ConnectionState; 5] = [
+    ConnectionState::Connecting,
+    ConnectionState::Connected,
+    ConnectionState::Retrying,
+    ConnectionState::AuthFailed,
+    ConnectionState::Idle,
+];
+
+/// A snapshot of everything reported about one camera
+struct CameraMetrics<'a> {
+    name: &'a str,
+    status: CameraStatus,
+    streams: BTreeMap<&'static str, StreamStats>,
+    /// Each RTSP mount, and how many clients are playing it
+    mounts: Vec<(&'a str, usize)>,
+}
+
+pub fn response(cameras: &[Arc<HttpCamera>], rtsp: &RtspServer) -> Response<Cursor<Vec<u8>>> {
+    let metrics: Vec<_> = cameras
+        .iter()
+        .map(|camera| CameraMetrics {
+            name: &camera.name,
+            status: camera.health.status(),
+            streams: camera.health.streams(),
+            mounts: camera
+                .paths
+                .iter()
+                .map(|path| (path.as_str(), rtsp.client_count(path)))
+                .collect(),
+        })
+        .collect();
+    let content_type =
+        Header::from_bytes("Content-Type", "text/plain; version=0.0.4").expect("Header is valid");
+    Response::from_string(render(&metrics, Instant::now())).with_header(content_type)
+}
+
+fn render(cameras: &[CameraMetrics], now: Instant) -> String {
+    let mut out = String::new();
+    let streams = || {
+        cameras.iter().flat_map(|camera| {
+            camera
+                .streams
+                .iter()
+                .map(move |(stream, stats)| (camera.name, *stream, stats))
+        })
+    };
+    let counts = || {
+        streams().flat_map(|(camera, stream, stats)| {
+            received(stats).into_iter().map(move |(kind, count)| {
+                (
+                    vec![("camera", camera), ("stream", stream), ("kind", kind)],
+                    count,
+                )
+            })
+        })
+    };
+
+    family(
+        &mut out,
+        "neolink_camera_state",
+        "gauge",
+        "Whether the camera is in each connection state",
+        cameras.iter().flat_map(|camera| {
+            STATES.iter().map(move |&state| {
+                let labels = vec![("camera", camera.name), ("state", state.name())];
+                (labels, (camera.status.state == state) as u64 as f64)
+            })
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_camera_reconnects_total",
+        "counter",
+        "How many times the connection to the camera has been made again",
+        cameras.iter().map(|camera| {
+            let labels = vec![("camera", camera.name)];
+            (labels, camera.status.reconnects as f64)
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_camera_backoff_seconds",
+        "gauge",
+        "How long Neolink is waiting between attempts to reconnect, or 0 while connected",
+        cameras.iter().map(|camera| {
+            let backoff = camera.status.backoff.map(|(backoff, _)| backoff);
+            let labels = vec![("camera", camera.name)];
+            (labels, backoff.unwrap_or_default().as_secs_f64())
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_camera_deserialization_errors_total",
+        "counter",
+        "How many connections ended because the camera sent something that could not be parsed",
+        cameras.iter().map(|camera| {
+            let labels = vec![("camera", camera.name)];
+            (labels, camera.status.deserialization_errors as f64)
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_stream_received_bytes_total",
+        "counter",
+        "Bytes of media received from the camera, by kind of packet",
+        counts().map(|(labels, count)| (labels, count.bytes as f64)),
+    );
+    family(
+        &mut out,
+        "neolink_stream_received_frames_total",
+        "counter",
+        "Media packets received from the camera, by kind of packet",
+        counts().map(|(labels, count)| (labels, count.frames as f64)),
+    );
+    family(
+        &mut out,
+        "neolink_stream_last_iframe_age_seconds",
+        "gauge",
+        "How long ago the last keyframe was received",
+        streams().filter_map(|(camera, stream, stats)| {
+            let age = now.saturating_duration_since(stats.last_iframe?);
+            let labels = vec![("camera", camera), ("stream", stream)];
+            Some((labels, age.as_secs_f64()))
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_stream_adpcm_errors_total",
+        "counter",
+        "ADPCM audio packets that could not be decoded",
+        streams().map(|(camera, stream, stats)| {
+            let labels = vec![("camera", camera), ("stream", stream)];
+            (labels, stats.adpcm_errors as f64)
+        }),
+    );
+    family(
+        &mut out,
+        "neolink_rtsp_clients",
+        "gauge",
+        "Clients playing each RTSP mount",
+        cameras.iter().flat_map(|camera| {
+            camera.mounts.iter().map(move |&(mount, clients)| {
+                let labels = vec![("camera", camera.name), ("mount", mount)];
+                (labels, clients as f64)
+            })
+        }),
+    );
+    out
+}
+
+/// The media counts of a stream, labelled and in a stable order
+fn received(stats: &StreamStats) -> Vec<(&'static str, MediaCount)> {
+    let mut received: Vec<_> = stats
+        .received
+        .iter()
+        .map(|(&kind, &count)| (kind_name(kind), count))
+        .collect();
+    received.sort_by_key(|&(kind, _)| kind);
+    received
+}
+
+fn kind_name(kind: MediaDataKind) -> &'static str {
+    match kind {
+        MediaDataKind::VideoDataIframe => "iframe",
+        MediaDataKind::VideoDataPframe => "pframe",
+        MediaDataKind::AudioDataAac => "aac",
+        MediaDataKind::AudioDataAdpcm => "adpcm",
+        MediaDataKind::InfoData => "info",
+        MediaDataKind::Unknown => "unknown",
+    }
+}
+
+/// Writes a metric and all of its samples, each of which is its labels and value
+fn family<'a>(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl Iterator<Item = (Vec<(&'a str, &'a str)>, f64)>,
+) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+    for (labels, value) in samples {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+            .collect();
+        writeln!(out, "{}{{{}}} {}", name, labels.join(","), value).unwrap();
+    }
+}
+
+/// Escapes a label value, which may be any string such as a camera name
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[test]
+fn test_render() {
+    use std::time::Duration;
+
+    let start = Instant::now();
+    let mut stats = StreamStats {
+        last_iframe: Some(start),
+        adpcm_errors: 1,
+        ..Default::default()
+    };
+    stats.received.insert(
+        MediaDataKind::VideoDataPframe,
+        MediaCount {
+            bytes: 3000,
+            frames: 20,
+        },
+    );
+    stats.received.insert(
+        MediaDataKind::VideoDataIframe,
+        MediaCount {
+            bytes: 9000,
+            frames: 1,
+        },
+    );
+    let mut streams = BTreeMap::new();
+    streams.insert("mainStream", stats);
+    let camera = CameraMetrics {
+        name: "front \"door\"",
+        status: CameraStatus {
+            state: ConnectionState::Retrying,
+            backoff: Some((Duration::from_secs(4), start)),
+            reconnects: 2,
+            ..Default::default()
+        },
+        streams,
+        mounts: vec![("/front", 1)],
+    };
+
+    let text = render(&[camera], start + Duration::from_millis(1500));
+    let samples: Vec<&str> = text.lines().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(
+        samples,
+        vec![
+            r#"neolink_camera_state{camera="front \"door\"",state="connecting"} 0"#,
+            r#"neolink_camera_state{camera="front \"door\"",state="connected"} 0"#,
+            r#"neolink_camera_state{camera="front \"door\"",state="retrying"} 1"#,
+            r#"neolink_camera_state{camera="front \"door\"",state="auth_failed"} 0"#,
+            r#"neolink_camera_state{camera="front \"door\"",state="idle"} 0"#,
+            r#"neolink_camera_reconnects_total{camera="front \"door\""} 2"#,
+            r#"neolink_camera_backoff_seconds{camera="front \"door\""} 4"#,
+            r#"neolink_camera_deserialization_errors_total{camera="front \"door\""} 0"#,
+            r#"neolink_stream_received_bytes_total{camera="front \"door\"",stream="mainStream",kind="iframe"} 9000"#,
+            r#"neolink_stream_received_bytes_total{camera="front \"door\"",stream="mainStream",kind="pframe"} 3000"#,
+            r#"neolink_stream_received_frames_total{camera="front \"door\"",stream="mainStream",kind="iframe"} 1"#,
+            r#"neolink_stream_received_frames_total{camera="front \"door\"",stream="mainStream",kind="pframe"} 20"#,
+            r#"neolink_stream_last_iframe_age_seconds{camera="front \"door\"",stream="mainStream"} 1.5"#,
+            r#"neolink_stream_adpcm_errors_total{camera="front \"door\"",stream="mainStream"} 1"#,
+            r#"neolink_rtsp_clients{camera="front \"door\"",mount="/front"} 1"#,
+        ]
+    );
+    assert!(text.contains("# TYPE neolink_rtsp_clients gauge\n"));
+}
@@ -0,0 +1,401 @@
+//! Bridges a camera to an MQTT broker.  State is published under `<topic_prefix>/<camera name>/`:
+//!
+//! - `status`: `connected`, `disconnected`, or `offline` if Neolink itself has gone away
+//! - `status/model`, `status/firmware`: from the camera's VersionInfo
+//! - `status/time`: the camera's clock at the time we connected
+//! - `status/ping`: the round trip time of the last keepalive ping, in milliseconds
+//! - `status/motion`, `status/person`, `status/vehicle`: `on` or `off`, if the camera agrees to
+//!   send alarm events
+//!
+//! Commands are accepted under `<topic_prefix>/<camera name>/control/`:
+//!
+//! - `reboot`: any payload
+//! - `ir`: `on`, `off` or `auto`
+//! - `led`: `on` or `off`
+//! - `ptz`: a direction (`up`, `down`, `left`, `right`, `leftup`, `leftdown`, `rightup`,
+//!   `rightdown`), `zoomin` or `zoomout` followed by an optional speed; `stop`; `preset <id>`; or
+//!   `savepreset <id> <name>`
+use crate::config::{CameraConfig, MqttConfig};
+use crate::health::CameraHealth;
+use crate::Error;
+use crossbeam::channel::TryRecvError;
+use log::*;
+use neolink::bc_protocol::{BcCamera, Direction, MotionKind, MotionStatus, Zoom};
+use neolink::Never;
+use rumqttc::{Client, Connection, Event, LastWill, MqttOptions, Outgoing, Packet, QoS};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_PTZ_SPEED: u32 = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+enum PtzCommand {
+    Move(Direction, u32),
+    Zoom(Zoom, u32),
+    Stop,
+    GotoPreset(u8),
+    SavePreset(u8, String),
+}
+
+/// A connection to the MQTT broker for one camera.  It outlives the camera sessions, so that
+/// the broker sees the camera go offline and come back.  Dropping it disconnects from the broker,
+/// so that a camera restarted by a config reload can connect again with the same client ID.
+pub struct Mqtt {
+    client: Client,
+    topics: Topics,
+    /// The last status published, to publish again whenever we reconnect to the broker
+    status: Arc<Mutex<&'static str>>,
+    commands: Mutex<Receiver<(String, String)>>,
+    /// Tells the event thread to stop rather than reconnect
+    closed: Arc<AtomicBool>,
+}
+
+#[derive(Clone)]
+struct Topics {
+    base: String,
+}
+
+impl Topics {
+    fn new(mqtt_config: &MqttConfig, camera_config: &CameraConfig) -> Topics {
+        Topics {
+            base: format!("{}/{}", mqtt_config.topic_prefix, camera_config.name),
+        }
+    }
+
+    fn get(&self, sub_topic: &str) -> String {
+        format!("{}/{}", self.base, sub_topic)
+    }
+
+    fn control_prefix(&self) -> String {
+        self.get("control/")
+    }
+}
+
+impl Mqtt {
+    pub fn new(camera_config: &CameraConfig, mqtt_config: &MqttConfig) -> Result<Mqtt, Error> {
+        let topics = Topics::new(mqtt_config, camera_config);
+
+        let mut options = MqttOptions::new(
+            format!("neolink_{}", camera_config.name),
+            &mqtt_config.broker_addr,
+            mqtt_config.port,
+        );
+        options.set_keep_alive(5);
+        options.set_last_will(LastWill::new(
+            topics.get("status"),
+            "offline",
+            QoS::AtLeastOnce,
+            true,
+        ));
+        if let Some((user, pass)) = &mqtt_config.credentials {
+            options.set_credentials(user, pass);
+        }
+
+        let (client, connection) = Client::new(options, 10);
+        let status = Arc::new(Mutex::new("disconnected"));
+
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let (command_tx, command_rx) = channel();
+        {
+            let client = client.clone();
+            let topics = topics.clone();
+            let status = status.clone();
+            let closed = closed.clone();
+            std::thread::spawn(move || {
+                mqtt_events(connection, client, topics, status, closed, command_tx)
+            });
+        }
+
+        Ok(Mqtt {
+            client,
+            topics,
+            status,
+            commands: Mutex::new(command_rx),
+            closed,
+        })
+    }
+
+    /// Publishes the state of a logged in camera and runs the commands sent to it, until the
+    /// camera connection fails or `stop` is closed.  Requests that the camera refuses are only
+    /// logged, so that they do not end the session.
+    pub fn run(
+        &self,
+        camera: &BcCamera,
+        camera_config: &CameraConfig,
+        health: &CameraHealth,
+        stop: &crossbeam::channel::Receiver<()>,
+    ) -> Result<(), neolink::Error> {
+        self.set_status("connected");
+
+        if let Ok(version) = camera.version() {
+            self.publish("status/model", &version.name);
+            self.publish("status/firmware", &version.firmwareVersion);
+        }
+        match camera.get_time() {
+            Ok(Some(time)) => self.publish("status/time", &time.to_string()),
+            Ok(None) => {}
+            Err(e) if e.is_connection_lost() => return Err(e),
+            Err(e) => warn!(
+                "{}: MQTT: Could not get the camera's time: {}",
+                camera_config.name, e
+            ),
+        }
+
+        let commands = self.commands.lock().unwrap();
+        crossbeam::scope(|s| {
+            let connection_lost = AtomicBool::new(false);
+            let motion = s.spawn(|_| match self.motion_loop(camera, camera_config) {
+                Err(e) if e.is_connection_lost() => {
+                    connection_lost.store(true, Ordering::Relaxed);
+                    Err(e)
+                }
+                Err(e) => {
+                    warn!(
+                        "{}: MQTT: Not publishing motion, as the camera will not send it: {}",
+                        camera_config.name, e
+                    );
+                    Ok(())
+                }
+                Ok(never) => match never {},
+            });
+
+            // The motion thread stops when the camera connection fails, which is our cue to stop
+            // too.  If the camera does not send motion, we rely on being told to stop instead.
+            let mut ping_latency = None;
+            while !connection_lost.load(Ordering::Relaxed) {
+                if let Err(TryRecvError::Disconnected) = stop.try_recv() {
+                    break;
+                }
+                let latency = health.ping_latency();
+                if latency != ping_latency {
+                    ping_latency = latency;
+                    if let Some(latency) = latency {
+                        self.publish("status/ping", &latency.as_millis().to_string());
+                    }
+                }
+
+                match commands.recv_timeout(Duration::from_secs(1)) {
+                    Ok((command, payload)) => {
+                        if let Err(e) = handle_command(camera, &command, &payload) {
+                            warn!(
+                                "{}: MQTT: Failed to run command {} {:?}: {}",
+                                camera_config.name, command, payload, e
+                            );
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+
+            motion.join().unwrap()
+        })
+        .unwrap()
+    }
+
+    pub fn set_disconnected(&self) {
+        self.set_status("disconnected");
+    }
+
+    fn set_status(&self, status: &'static str) {
+        *self.status.lock().unwrap() = status;
+        self.publish("status", status);
+    }
+
+    fn motion_loop(
+        &self,
+        camera: &BcCamera,
+        camera_config: &CameraConfig,
+    ) -> Result<Never, neolink::Error> {
+        let mut motion_sub = camera.listen_on_motion()?;
+        loop {
+            for event in motion_sub.next_motion()? {
+                if event.channel_id != camera_config.channel_id {
+                    continue;
+                }
+                let sub_topic = match event.kind {
+                    MotionKind::Motion => "status/motion",
+                    MotionKind::Person => "status/person",
+                    MotionKind::Vehicle => "status/vehicle",
+                };
+                let payload = match event.status {
+                    MotionStatus::Start => "on",
+                    MotionStatus::Stop => "off",
+                };
+                self.publish(sub_topic, payload);
+            }
+        }
+    }
+
+    fn publish(&self, sub_topic: &str, payload: &str) {
+        publish(&self.client, &self.topics.get(sub_topic), payload);
+    }
+}
+
+impl Drop for Mqtt {
+    fn drop(&mut self) {
+        // A clean disconnect also stops the broker publishing our last will.  If the broker cannot
+        // be reached, the event thread gives up instead, which lets the request go.
+        self.closed.store(true, Ordering::Relaxed);
+        if let Err(e) = self.client.disconnect() {
+            debug!("MQTT: Could not disconnect: {}", e);
+        }
+    }
+}
+
+fn publish(client: &Client, topic: &str, payload: &str) {
+    // Client needs to be mutable to publish, but it is cheap to clone and shares the connection
+    let mut client = client.clone();
+    if let Err(e) = client.publish(topic, QoS::AtLeastOnce, true, payload) {
+        warn!("MQTT: Could not publish to {}: {}", topic, e);
+    }
+}
+
+/// Drives the MQTT connection, forwarding each message received on a control topic to the camera
+/// session as a (command, payload) pair.  rumqttc reconnects to the broker by itself as long as
+/// we keep iterating, so we stop once we have disconnected, or failed to connect after `closed`
+/// is set.
+fn mqtt_events(
+    mut connection: Connection,
+    client: Client,
+    topics: Topics,
+    status: Arc<Mutex<&'static str>>,
+    closed: Arc<AtomicBool>,
+    commands: Sender<(String, String)>,
+) {
+    let control_prefix = topics.control_prefix();
+    for notification in connection.iter() {
+        match notification {
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                // The session is clean, so the broker has forgotten our subscription, and it may
+                // have published our last will.  The requests are made from another thread, as
+                // they wait for room in the queue that this loop empties.
+                let mut client = client.clone();
+                let topics = topics.clone();
+                let status = *status.lock().unwrap();
+                std::thread::spawn(move || {
+                    if let Err(e) = client.subscribe(topics.get("control/#"), QoS::AtLeastOnce) {
+                        warn!("MQTT: Could not subscribe to commands: {}", e);
+                    }
+                    publish(&client, &topics.get("status"), status);
+                });
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                if let Some(command) = publish.topic.strip_prefix(&control_prefix) {
+                    let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                    if commands.send((command.to_string(), payload)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => return,
+            Ok(_) => {}
+            Err(_) if closed.load(Ordering::Relaxed) => return,
+            Err(e) => {
+                warn!("MQTT: Connection to broker failed, retrying: {}", e);
+                std::thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
+
+fn handle_command(camera: &BcCamera, command: &str, payload: &str) -> Result<(), neolink::Error> {
+    let payload = payload.trim();
+    match command {
+        "reboot" => camera.reboot(),
+        "ir" => {
+            let state = match payload {
+                "on" => "open",
+                "off" => "close",
+                "auto" => "auto",
+                _ => return Err(neolink::Error::Other("IR state must be on, off or auto")),
+            };
+            let mut led_state = camera.get_ledstate()?;
+            led_state.state = state.to_string();
+            camera.set_ledstate(led_state)
+        }
+        "led" => {
+            let light_state = match payload {
+                "on" => "open",
+                "off" => "close",
+                _ => return Err(neolink::Error::Other("LED state must be on or off")),
+            };
+            let mut led_state = camera.get_ledstate()?;
+            led_state.light_state = light_state.to_string();
+            camera.set_ledstate(led_state)
+        }
+        "ptz" => match parse_ptz(payload) {
+            Some(PtzCommand::Move(direction, speed)) => camera.ptz_move(direction, speed),
+            Some(PtzCommand::Zoom(zoom, speed)) => camera.ptz_zoom(zoom, speed),
+            Some(PtzCommand::Stop) => camera.ptz_stop(),
+            Some(PtzCommand::GotoPreset(id)) => camera.ptz_goto_preset(id),
+            Some(PtzCommand::SavePreset(id, name)) => camera.ptz_save_preset(id, &name),
+            None => Err(neolink::Error::Other("Unrecognised PTZ command")),
+        },
+        _ => Err(neolink::Error::Other("Unrecognised command")),
+    }
+}
+
+fn parse_ptz(payload: &str) -> Option<PtzCommand> {
+    let mut words = payload.split_whitespace();
+    let command = words.next()?.to_lowercase();
+    let arg = words.next();
+
+    let speed = || match arg {
+        Some(speed) => speed.parse().ok(),
+        None => Some(DEFAULT_PTZ_SPEED),
+    };
+
+    let direction = match command.as_str() {
+        "up" => Some(Direction::Up),
+        "down" => Some(Direction::Down),
+        "left" => Some(Direction::Left),
+        "right" => Some(Direction::Right),
+        "leftup" => Some(Direction::LeftUp),
+        "leftdown" => Some(Direction::LeftDown),
+        "rightup" => Some(Direction::RightUp),
+        "rightdown" => Some(Direction::RightDown),
+        _ => None,
+    };
+    if let Some(direction) = direction {
+        return Some(PtzCommand::Move(direction, speed()?));
+    }
+
+    match command.as_str() {
+        "zoomin" => Some(PtzCommand::Zoom(Zoom::In, speed()?)),
+        "zoomout" => Some(PtzCommand::Zoom(Zoom::Out, speed()?)),
+        "stop" => Some(PtzCommand::Stop),
+        "preset" => Some(PtzCommand::GotoPreset(arg?.parse().ok()?)),
+        "savepreset" => {
+            let id = arg?.parse().ok()?;
+            let name = words.collect::<Vec<_>>().join(" ");
+            Some(PtzCommand::SavePreset(id, name))
+        }
+        _ => None,
+    }
+}
+
+#[test]
+fn test_parse_ptz() {
+    assert_eq!(
+        parse_ptz("left"),
+        Some(PtzCommand::Move(Direction::Left, DEFAULT_PTZ_SPEED))
+    );
+    assert_eq!(
+        parse_ptz("RightUp 10"),
+        Some(PtzCommand::Move(Direction::RightUp, 10))
+    );
+    assert_eq!(parse_ptz("zoomin"), Some(PtzCommand::Zoom(Zoom::In, 32)));
+    assert_eq!(parse_ptz("stop"), Some(PtzCommand::Stop));
+    assert_eq!(parse_ptz("preset 3"), Some(PtzCommand::GotoPreset(3)));
+    assert_eq!(
+        parse_ptz("savepreset 4 front gate"),
+        Some(PtzCommand::SavePreset(4, "front gate".to_string()))
+    );
+    assert_eq!(parse_ptz("preset"), None);
+    assert_eq!(parse_ptz("left fast"), None);
+    assert_eq!(parse_ptz("sideways"), None);
+}
@@ -0,0 +1,288 @@
+//! Plays back and downloads the recordings on a camera's SD card.  RTSP clients ask for a start
+//! time on the playback mount, and are sent the recordings from then on, starting from the
+//! beginning of the one that was in progress at that time.
+use crate::config::{CameraConfig, Config};
+use crate::Error;
+use crossbeam::channel::{select, Receiver};
+use log::*;
+use neolink::bc_protocol::{BcCamera, MediaData, MediaDataKind, MediaSink};
+use neolink::gst::{CameraClock, Container, GstOutputs, Muxer, PlaybackRequest};
+use std::path::Path;
+use std::time::Instant;
+use time::{Date, PrimitiveDateTime};
+
+/// How far past the requested start time to look for recordings to play back
+const PLAYBACK_SEARCH_DAYS: i64 = 1;
+
+/// How many packets to hold while waiting to see whether a download has audio
+const MAX_PENDING_PACKETS: usize = 200;
+
+/// Prints the recordings on a camera's SD card between `start` and `end`
+pub fn list(
+    config: &Config,
+    camera_name: &str,
+    start: Option<&str>,
+    end: Option<&str>,
+    stream: &str,
+) -> Result<(), Error> {
+    let camera_config = crate::find_camera(config, camera_name)?;
+    let parse = |time: &str| parse_time(time).ok_or_else(|| Error::InvalidTime(time.to_string()));
+    let start = start.map(parse).transpose()?;
+    let end = end.map(parse).transpose()?;
+
+    let (camera, _) = crate::connect_camera(camera_config)?;
+    let now = crate::time_sync::camera_now(&camera, &camera_config.time_sync)?;
+    let now = PrimitiveDateTime::new(now.date(), now.time());
+    let start = start.unwrap_or(now - time::Duration::days(1));
+    let end = end.unwrap_or(now);
+    for recording in camera.search_recordings(stream, start, end)? {
+        let size = match recording.size {
+            Some(size) => format!("{:.1}MB", size as f64 / (1024.0 * 1024.0)),
+            None => "?".to_string(),
+        };
+        println!(
+            "{}  {} to {}  {:>8}  {}",
+            recording.name,
+            recording.start.format("%Y-%m-%d %H:%M:%S"),
+            recording.end.format("%H:%M:%S"),
+            size,
+            recording.record_type
+        );
+    }
+    Ok(())
+}
+
+/// Saves the recording called `name` from the camera's SD card into an MP4 file
+pub fn download(
+    config: &Config,
+    camera_name: &str,
+    name: &str,
+    output: &Path,
+) -> Result<(), Error> {
+    let camera_config = crate::find_camera(config, camera_name)?;
+    let (camera, _) = crate::connect_camera(camera_config)?;
+
+    info!("{}: Downloading {}", camera_config.name, name);
+    let mut download = Download::new(output);
+    camera.replay(name, &mut download)?;
+    download.finish()?;
+    info!("Saved {}", output.display());
+    Ok(())
+}
+
+/// Plays back recordings to RTSP clients as they ask for them, until told to stop
+pub fn serve(
+    camera: &BcCamera,
+    camera_config: &CameraConfig,
+    stream_name: &str,
+    requests: &Receiver<PlaybackRequest>,
+    stop: &Receiver<()>,
+) -> Result<(), neolink::Error> {
+    loop {
+        let request = select! {
+            recv(stop) -> _ => return Ok(()),
+            recv(requests) -> request => match request {
+                Ok(request) => request,
+                Err(_) => return Ok(()),
+            },
+        };
+        let mut outputs = request.outputs;
+        let start = request.query.as_deref().and_then(start_from_query);
+        let result = match start {
+            Some(start) => play(camera, camera_config, stream_name, start, &mut outputs),
+            None => {
+                warn!(
+                    "{}: Playback needs a start time, such as ?start=2020-10-11T21:30:00",
+                    camera_config.name
+                );
+                Ok(())
+            }
+        };
+        outputs.on_stream_error();
+
+        match result {
+            Err(err @ neolink::Error::DroppedConnection(_))
+            | Err(err @ neolink::Error::TimeoutDisconnected)
+            | Err(err @ neolink::Error::CommunicationError(_)) => return Err(err),
+            Err(err) => warn!("{}: Playback failed: {}", camera_config.name, err),
+            Ok(()) => {}
+        }
+    }
+}
+
+fn play(
+    camera: &BcCamera,
+    camera_config: &CameraConfig,
+    stream_name: &str,
+    start: PrimitiveDateTime,
+    outputs: &mut GstOutputs,
+) -> Result<(), neolink::Error> {
+    let end = start + time::Duration::days(PLAYBACK_SEARCH_DAYS);
+    let recordings = camera.search_recordings(stream_name, start, end)?;
+    if recordings.is_empty() {
+        info!(
+            "{}: No recordings to play back after {}",
+            camera_config.name, start
+        );
+    }
+
+    let mut sink = PlaybackSink { outputs };
+    for recording in recordings {
+        info!("{}: Playing back {}", camera_config.name, recording.name);
+        match camera.replay(&recording.name, &mut sink) {
+            Err(_) if sink.outputs.vidsrc.is_closed() => {
+                debug!("{}: Playback client went away", camera_config.name);
+                break;
+            }
+            result => result?,
+        }
+    }
+    Ok(())
+}
+
+/// Stops the replay once the RTSP client has gone
+struct PlaybackSink<'a> {
+    outputs: &'a mut GstOutputs,
+}
+
+impl<'a> MediaSink for PlaybackSink<'a> {
+    fn write_media(&mut self, media: &MediaData) -> Result<(), neolink::Error> {
+        self.outputs.write_media(media)?;
+        if self.outputs.vidsrc.is_closed() {
+            return Err(neolink::Error::Other("Playback client went away"));
+        }
+        Ok(())
+    }
+}
+
+/// Muxes a recording into a file.  The camera sends recordings faster than real time, so they are
+/// timed by the timestamps in the video packets rather than by when they arrive.
+struct Download<'a> {
+    path: &'a Path,
+    muxer: Option<Muxer>,
+    pending: Vec<MediaData>,
+    audio_kind: Option<MediaDataKind>,
+    clock: CameraClock,
+}
+
+impl<'a> Download<'a> {
+    fn new(path: &'a Path) -> Download<'a> {
+        Download {
+            path,
+            muxer: None,
+            pending: vec![],
+            audio_kind: None,
+            clock: CameraClock::default(),
+        }
+    }
+
+    /// Files must start with a keyframe, and we have to know whether there is audio before we
+    /// can start one, so packets are held until the audio turns up or it's clear that it won't
+    fn start(&mut self) -> Result<(), neolink::Error> {
+        let video_format = match self.pending.first().and_then(|media| media.media_format()) {
+            Some(format) => format,
+            None => return Err(neolink::Error::Other("Recording has no video")),
+        };
+        let muxer = Muxer::new_file(
+            self.path,
+            Container::Mp4,
+            video_format,
+            crate::record::audio_format(self.audio_kind),
+        )?;
+        self.muxer = Some(muxer);
+        for media in std::mem::take(&mut self.pending) {
+            self.write(&media)?;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, media: &MediaData) -> Result<(), neolink::Error> {
+        if let Some(timestamp) = media.timestamp() {
+            self.clock.update(timestamp as u32, Instant::now());
+        }
+        let muxer = self.muxer.as_ref().expect("Download should have started");
+        crate::record::write_to_muxer(muxer, media, self.clock.now())?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<(), neolink::Error> {
+        if self.muxer.is_none() {
+            self.start()?;
+        }
+        if let Some(muxer) = self.muxer.take() {
+            muxer.finish()?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> MediaSink for Download<'a> {
+    fn write_media(&mut self, media: &MediaData) -> Result<(), neolink::Error> {
+        if self.muxer.is_some() {
+            return self.write(media);
+        }
+
+        let kind = media.kind();
+        match kind {
+            MediaDataKind::AudioDataAac | MediaDataKind::AudioDataAdpcm => {
+                self.audio_kind = Some(kind)
+            }
+            MediaDataKind::VideoDataIframe => {}
+            MediaDataKind::VideoDataPframe if !self.pending.is_empty() => {}
+            _ => return Ok(()),
+        }
+        if self.pending.is_empty() && kind != MediaDataKind::VideoDataIframe {
+            return Ok(());
+        }
+        self.pending.push(media.clone());
+
+        if self.audio_kind.is_some() || self.pending.len() >= MAX_PENDING_PACKETS {
+            self.start()?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses 2020-10-11T21:30:00, or 2020-10-11 for midnight
+fn parse_time(time: &str) -> Option<PrimitiveDateTime> {
+    PrimitiveDateTime::parse(time, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| Date::parse(time, "%Y-%m-%d").map(Date::midnight))
+        .ok()
+}
+
+/// Finds the start time in a query string such as `start=2020-10-11T21%3A30%3A00`
+fn start_from_query(query: &str) -> Option<PrimitiveDateTime> {
+    query
+        .split('&')
+        .filter_map(|pair| {
+            let mut pair = pair.splitn(2, '=');
+            match (pair.next(), pair.next()) {
+                (Some("start"), Some(value)) => Some(value),
+                _ => None,
+            }
+        })
+        .next()
+        .and_then(|value| parse_time(&value.replace("%3A", ":").replace("%3a", ":")))
+}
+
+#[test]
+fn test_start_from_query() {
+    let expected = PrimitiveDateTime::new(
+        Date::try_from_ymd(2020, 10, 11).unwrap(),
+        time::Time::try_from_hms(21, 30, 0).unwrap(),
+    );
+    assert_eq!(
+        start_from_query("start=2020-10-11T21:30:00"),
+        Some(expected)
+    );
+    assert_eq!(
+        start_from_query("speed=1&start=2020-10-11T21%3A30%3A00"),
+        Some(expected)
+    );
+    assert_eq!(
+        start_from_query("start=2020-10-11"),
+        Some(Date::try_from_ymd(2020, 10, 11).unwrap().midnight())
+    );
+    assert_eq!(start_from_query("start=yesterday"), None);
+    assert_eq!(start_from_query("end=2020-10-11"), None);
+}
@@ -0,0 +1,281 @@
+//! Records a camera's stream into files of `segment_length` seconds each, in a directory named
+//! after the camera.  Whenever a file is started, the oldest recordings are deleted to keep within
+//! `max_age_hours` and `max_size_mb`.
+use crate::clips::ClipRecorder;
+use crate::config::{CameraConfig, RecordingConfig};
+use crate::health::CameraHealth;
+use crate::hls::HlsWriter;
+use crate::whep::WhepSessions;
+use log::*;
+use neolink::bc_protocol::{adpcm_to_pcm, MediaData, MediaDataKind, MediaSink};
+use neolink::gst::{Container, GstOutputs, Muxer, StreamFormat};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use time::OffsetDateTime;
+
+pub struct Recorder {
+    name: String,
+    config: RecordingConfig,
+    dir: PathBuf,
+    muxer: Option<Muxer>,
+    start: Instant,
+    seen_audio: Option<MediaDataKind>,
+    skipped_iframe: bool,
+    failed: bool,
+}
+
+/// Sends a stream to the RTSP server, and to whichever recordings and other outputs are enabled
+pub struct RecordedStream<'a> {
+    pub name: &'static str,
+    pub health: &'a CameraHealth,
+    pub outputs: &'a mut GstOutputs,
+    pub recorder: Option<Recorder>,
+    pub clips: Option<ClipRecorder>,
+    pub hls: Option<HlsWriter>,
+    pub whep: Option<&'a WhepSessions>,
+}
+
+impl<'a> MediaSink for RecordedStream<'a> {
+    fn write_media(&mut self, media: &MediaData) -> Result<(), neolink::Error> {
+        self.health.record_media(self.name, media);
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_media(media);
+        }
+        if let Some(clips) = &mut self.clips {
+            clips.write_media(media);
+        }
+        if let Some(hls) = &mut self.hls {
+            hls.write_media(media);
+        }
+        if let Some(whep) = self.whep {
+            whep.write_media(media);
+        }
+        let result = self.outputs.write_media(media);
+        if let Err(neolink::Error::AdpcmDecodingError(_)) = result {
+            self.health.add_adpcm_error(self.name);
+        }
+        result
+    }
+}
+
+impl Recorder {
+    pub fn new(camera_config: &CameraConfig, config: &RecordingConfig) -> Recorder {
+        Recorder {
+            name: camera_config.name.clone(),
+            config: config.clone(),
+            dir: config.path.join(&camera_config.name),
+            muxer: None,
+            start: Instant::now(),
+            seen_audio: None,
+            skipped_iframe: false,
+            failed: false,
+        }
+    }
+
+    /// Records a media packet.  If recording fails, the error is logged and nothing more is
+    /// recorded until the camera reconnects, but the stream itself carries on.
+    pub fn write_media(&mut self, media: &MediaData) {
+        if self.failed {
+            return;
+        }
+        if let Err(e) = self.try_write_media(media) {
+            error!(
+                "{}: Recording failed, will retry when the camera reconnects: {}",
+                self.name, e
+            );
+            self.failed = true;
+            self.muxer = None;
+        }
+    }
+
+    fn try_write_media(&mut self, media: &MediaData) -> io::Result<()> {
+        let kind = media.kind();
+        if let MediaDataKind::AudioDataAac | MediaDataKind::AudioDataAdpcm = kind {
+            self.seen_audio = Some(kind);
+        }
+
+        if self.muxer.is_none() {
+            // Files must start with a keyframe.  The audio usually turns up within one keyframe
+            // interval, so skip the first one if we don't know yet whether there is any.
+            if kind != MediaDataKind::VideoDataIframe {
+                return Ok(());
+            }
+            if self.seen_audio.is_none() && !self.skipped_iframe {
+                self.skipped_iframe = true;
+                return Ok(());
+            }
+            let video_format = match media.media_format() {
+                Some(format) => format,
+                None => return Ok(()),
+            };
+            let audio_format = audio_format(self.seen_audio);
+
+            fs::create_dir_all(&self.dir)?;
+            info!("{}: Recording to {}", self.name, self.dir.display());
+            self.muxer = Some(Muxer::new_segmented(
+                self.segment_namer(),
+                Duration::from_secs(self.config.segment_length),
+                container(&self.config),
+                video_format,
+                audio_format,
+            )?);
+            self.start = Instant::now();
+        }
+
+        let muxer = self.muxer.as_ref().unwrap();
+        write_to_muxer(muxer, media, self.start.elapsed())
+    }
+
+    /// Clears out old files to make room for each new one
+    fn segment_namer(&self) -> impl Fn(u32) -> PathBuf + Send + Sync + 'static {
+        let name = self.name.clone();
+        let dir = self.dir.clone();
+        let config = self.config.clone();
+
+        move |_| {
+            if let Err(e) = prune(&dir, &config) {
+                warn!("{}: Could not delete old recordings: {}", name, e);
+            }
+            recording_path(&dir, &config)
+        }
+    }
+}
+
+pub fn container(config: &RecordingConfig) -> Container {
+    match config.format.as_str() {
+        "mkv" => Container::Matroska,
+        _ => Container::Mp4,
+    }
+}
+
+/// The audio track to give a file, from the kind of audio packets the camera sends
+pub fn audio_format(audio_kind: Option<MediaDataKind>) -> Option<StreamFormat> {
+    match audio_kind {
+        Some(MediaDataKind::AudioDataAac) => Some(StreamFormat::AAC),
+        Some(MediaDataKind::AudioDataAdpcm) => Some(StreamFormat::ADPCM),
+        _ => None,
+    }
+}
+
+/// Names a new file in `dir` after the local time
+pub fn recording_path(dir: &Path, config: &RecordingConfig) -> PathBuf {
+    let now = OffsetDateTime::try_now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let extension = container(config).extension();
+    dir.join(format!("{}.{}", now.format("%Y-%m-%d_%H-%M-%S"), extension))
+}
+
+/// Writes a media packet into a file, `pts` after the start of the file
+pub fn write_to_muxer(muxer: &Muxer, media: &MediaData, pts: Duration) -> io::Result<()> {
+    match media.kind() {
+        MediaDataKind::VideoDataIframe | MediaDataKind::VideoDataPframe => {
+            muxer.write_video(media.body(), pts)
+        }
+        MediaDataKind::AudioDataAac => muxer.write_audio(media.body(), pts),
+        MediaDataKind::AudioDataAdpcm if muxer.has_audio() => {
+            let pcm = adpcm_to_pcm(media.body())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            muxer.write_audio(&pcm, pts)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Deletes the recordings in `dir` that take it over the limits in `config`
+pub fn prune(dir: &Path, config: &RecordingConfig) -> io::Result<()> {
+    let max_age = config
+        .max_age_hours
+        .map(|hours| Duration::from_secs(hours * 60 * 60));
+    let max_size = config.max_size_mb.map(|mb| mb * 1024 * 1024);
+    if max_age.is_none() && max_size.is_none() {
+        return Ok(());
+    }
+
+    let mut recordings = vec![];
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let is_recording = match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) => ext == "mp4" || ext == "mkv",
+            None => false,
+        };
+        if is_recording {
+            let metadata = fs::metadata(&path)?;
+            recordings.push((path, metadata.modified()?, metadata.len()));
+        }
+    }
+
+    for path in expired_recordings(recordings, SystemTime::now(), max_age, max_size) {
+        debug!("Deleting old recording {}", path.display());
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Picks which of the (path, modified time, size) recordings to delete: those older than
+/// `max_age`, then the oldest of the rest until they fit in `max_size` bytes.
+fn expired_recordings(
+    mut recordings: Vec<(PathBuf, SystemTime, u64)>,
+    now: SystemTime,
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+) -> Vec<PathBuf> {
+    recordings.sort_by_key(|&(_, modified, _)| modified);
+
+    let mut total_size: u64 = recordings.iter().map(|&(_, _, size)| size).sum();
+    let mut expired = vec![];
+    for (path, modified, size) in recordings {
+        let too_old = match max_age {
+            Some(max_age) => now.duration_since(modified).unwrap_or_default() > max_age,
+            None => false,
+        };
+        let too_big = match max_size {
+            Some(max_size) => total_size > max_size,
+            None => false,
+        };
+        if !too_old && !too_big {
+            break;
+        }
+        total_size -= size;
+        expired.push(path);
+    }
+    expired
+}
+
+#[test]
+fn test_expired_recordings() {
+    let now = SystemTime::now();
+    let hours_ago = |hours: u64| now - Duration::from_secs(hours * 60 * 60);
+    let recordings = vec![
+        (PathBuf::from("c.mp4"), hours_ago(1), 100),
+        (PathBuf::from("a.mp4"), hours_ago(30), 100),
+        (PathBuf::from("b.mp4"), hours_ago(20), 100),
+    ];
+
+    assert_eq!(
+        expired_recordings(recordings.clone(), now, None, None),
+        Vec::<PathBuf>::new()
+    );
+    assert_eq!(
+        expired_recordings(
+            recordings.clone(),
+            now,
+            Some(Duration::from_secs(60 * 60 * 24)),
+            None
+        ),
+        vec![PathBuf::from("a.mp4")]
+    );
+    assert_eq!(
+        expired_recordings(recordings.clone(), now, None, Some(150)),
+        vec![PathBuf::from("a.mp4"), PathBuf::from("b.mp4")]
+    );
+    assert_eq!(
+        expired_recordings(
+            recordings,
+            now,
+            Some(Duration::from_secs(60 * 60 * 24)),
+            Some(250)
+        ),
+        vec![PathBuf::from("a.mp4")]
+    );
+}
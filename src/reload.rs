@@ -0,0 +1,198 @@
+//! Reloads the config file while Neolink is running, whenever the file changes or Neolink gets a
+//! SIGHUP.  The new config is compared with the running one, and only the cameras whose settings
+//! changed are restarted, so clients watching the other cameras are not disturbed.
+use crate::config::{CameraConfig, Config};
+use crate::Error;
+use log::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use validator::Validate;
+
+/// How often to check whether the config file has changed
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What has to be done to go from running one config to running another
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigChanges {
+    /// The names of the cameras to stop, which includes the ones that are restarting
+    pub stopped: Vec<String>,
+    /// The cameras to start, which includes the ones that are restarting
+    pub started: Vec<CameraConfig>,
+    pub users_changed: bool,
+    /// Settings that changed, but only take effect when Neolink is restarted
+    pub needs_restart: Vec<&'static str>,
+}
+
+pub fn read_config(path: &Path) -> Result<Config, Error> {
+    let config: Config = toml::from_str(&fs::read_to_string(path)?)?;
+    config.validate()?;
+    Ok(config)
+}
+
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    hangup: Arc<AtomicBool>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: &Path) -> ConfigWatcher {
+        let hangup = Arc::new(AtomicBool::new(false));
+        listen_for_hangup(&hangup);
+        ConfigWatcher {
+            path: path.to_path_buf(),
+            modified: modified_time(path),
+            hangup,
+        }
+    }
+
+    /// Waits for the config file to change or for a SIGHUP, and then reads the config again.  A
+    /// config that has mistakes in it is reported and otherwise ignored.
+    pub fn wait(&mut self) -> Config {
+        loop {
+            std::thread::sleep(POLL_INTERVAL);
+            let hangup = self.hangup.swap(false, Ordering::Relaxed);
+            let modified = modified_time(&self.path);
+            if !hangup && modified == self.modified {
+                continue;
+            }
+            self.modified = modified;
+
+            info!("Reloading {}", self.path.display());
+            match read_config(&self.path) {
+                Ok(config) => return config,
+                Err(e) => error!(
+                    "Not reloading {}, it has a problem: {:?}",
+                    self.path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+#[cfg(unix)]
+fn listen_for_hangup(flag: &Arc<AtomicBool>) {
+    use signal_hook::consts::SIGHUP;
+    if let Err(e) = signal_hook::flag::register(SIGHUP, flag.clone()) {
+        warn!("Could not listen for SIGHUP, only watching the file: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn listen_for_hangup(_flag: &Arc<AtomicBool>) {}
+
+/// Works out which cameras to stop and start to go from `old` to `new`.  A camera restarts if its
+/// own settings change, if a change of users changes who may watch it, or if the recording
+/// settings change and it is being recorded.
+pub fn diff(old: &Config, new: &Config) -> ConfigChanges {
+    let users_changed = old.users != new.users;
+    let recording_changed = old.recording != new.recording;
+    let restarts = |new_camera: &CameraConfig, old_camera: &CameraConfig| {
+        let permitted_users_changed = users_changed
+            && crate::get_permitted_users(&old.users, &old_camera.permitted_users)
+                != crate::get_permitted_users(&new.users, &new_camera.permitted_users);
+        let recorded = new_camera.record || new_camera.clips.is_some();
+        new_camera != old_camera || permitted_users_changed || (recording_changed && recorded)
+    };
+
+    let mut changes = ConfigChanges {
+        users_changed,
+        ..Default::default()
+    };
+    for old_camera in &old.cameras {
+        match new.cameras.iter().find(|c| c.name == old_camera.name) {
+            Some(new_camera) if !restarts(new_camera, old_camera) => {}
+            _ => changes.stopped.push(old_camera.name.clone()),
+        }
+    }
+    for new_camera in &new.cameras {
+        match old.cameras.iter().find(|c| c.name == new_camera.name) {
+            Some(old_camera) if !restarts(new_camera, old_camera) => {}
+            _ => changes.started.push(new_camera.clone()),
+        }
+    }
+
+    if old.bind_addr != new.bind_addr {
+        changes.needs_restart.push("bind");
+    }
+    if old.bind_port != new.bind_port {
+        changes.needs_restart.push("bind_port");
+    }
+    if old.certificate != new.certificate {
+        changes.needs_restart.push("certificate");
+    }
+    if old.tls_client_auth != new.tls_client_auth {
+        changes.needs_restart.push("tls_client_auth");
+    }
+    if old.http_bind_port != new.http_bind_port {
+        changes.needs_restart.push("http_bind_port");
+    }
+    changes
+}
+
+#[test]
+fn test_diff() {
+    let old: Config = toml::from_str(
+        r#"
+        [[users]]
+        name = "me"
+        pass = "mepass"
+
+        [[cameras]]
+        name = "driveway"
+        username = "admin"
+        address = "192.168.1.10:9000"
+
+        [[cameras]]
+        name = "garden"
+        username = "admin"
+        address = "192.168.1.11:9000"
+        permitted_users = ["me"]
+
+        [[cameras]]
+        name = "porch"
+        username = "admin"
+        address = "192.168.1.12:9000"
+        permitted_users = ["me"]
+
+        [[cameras]]
+        name = "yard"
+        username = "admin"
+        address = "192.168.1.13:9000"
+        permitted_users = ["me"]
+        "#,
+    )
+    .unwrap();
+
+    let mut new = old.clone();
+    assert_eq!(diff(&old, &new), ConfigChanges::default());
+
+    new.cameras[3].camera_addr = Some("192.168.1.14:9000".to_string());
+    new.cameras.remove(2);
+    new.users.push(crate::config::UserConfig {
+        name: "you".to_string(),
+        pass: "youpass".to_string(),
+    });
+    new.bind_port = 554;
+    let changes = diff(&old, &new);
+    // Anyone may watch the driveway, so the new user restarts it, but the garden is only for me
+    assert_eq!(changes.stopped, vec!["driveway", "porch", "yard"]);
+    assert_eq!(
+        changes
+            .started
+            .iter()
+            .map(|camera| &*camera.name)
+            .collect::<Vec<_>>(),
+        vec!["driveway", "yard"]
+    );
+    assert!(changes.users_changed);
+    assert_eq!(changes.needs_restart, vec!["bind_port"]);
+}
@@ -0,0 +1,109 @@
+//! The `config-camera` subcommand, which shows and changes a camera's general settings so that a
+//! new camera can be set up without the Reolink app
+use crate::config::Config;
+use crate::Error;
+use neolink::bc_protocol::{DateFormat, GeneralSettings, TimeFormat};
+use time::UtcOffset;
+
+/// Applies the settings in `changes` that are Some to the camera, and then prints them all
+pub fn main(config: &Config, camera_name: &str, changes: &GeneralSettings) -> Result<(), Error> {
+    let camera_config = crate::find_camera(config, camera_name)?;
+    let (camera, _) = crate::connect_camera(camera_config)?;
+
+    if *changes != GeneralSettings::default() {
+        camera.set_general(changes)?;
+    }
+
+    let settings = camera.get_general()?;
+    let unknown = || "?".to_string();
+    println!(
+        "Name:        {}",
+        settings.device_name.unwrap_or_else(unknown)
+    );
+    println!(
+        "Date format: {}",
+        settings.date_format.map_or("?", DateFormat::code)
+    );
+    println!(
+        "Time format: {}",
+        match settings.time_format {
+            Some(TimeFormat::TwentyFourHour) => "24 hour",
+            Some(TimeFormat::TwelveHour) => "12 hour",
+            None => "?",
+        }
+    );
+    println!("Language:    {}", settings.language.unwrap_or_else(unknown));
+    println!(
+        "Time zone:   {}",
+        settings.time_zone.map_or_else(unknown, format_time_zone)
+    );
+    Ok(())
+}
+
+pub fn parse_date_format(value: &str) -> Result<DateFormat, String> {
+    DateFormat::from_code(&value.to_uppercase())
+        .ok_or_else(|| "the date format must be DMY, MDY or YMD".to_string())
+}
+
+pub fn parse_time_format(value: &str) -> Result<TimeFormat, String> {
+    match value {
+        "24" => Ok(TimeFormat::TwentyFourHour),
+        "12" => Ok(TimeFormat::TwelveHour),
+        _ => Err("the time format must be 12 or 24".to_string()),
+    }
+}
+
+/// Parses a time zone such as `+01:00`, `-0530`, `+8` or `UTC`
+pub fn parse_time_zone(value: &str) -> Result<UtcOffset, String> {
+    let invalid = || format!("{} is not a time zone such as +01:00 or -05:30", value);
+    if value.eq_ignore_ascii_case("UTC") || value == "Z" {
+        return Ok(UtcOffset::UTC);
+    }
+    // The rest is sliced by bytes
+    if !value.is_ascii() {
+        return Err(invalid());
+    }
+    let (sign, rest) = match value.chars().next() {
+        Some('+') => (1, &value[1..]),
+        Some('-') => (-1, &value[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.find(':') {
+        Some(colon) => (&rest[..colon], &rest[colon + 1..]),
+        None if rest.len() <= 2 => (rest, "00"),
+        None if rest.len() <= 4 => rest.split_at(rest.len() - 2),
+        None => return Err(invalid()),
+    };
+    let is_number =
+        |s: &str| !s.is_empty() && s.len() <= 2 && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(hours) || minutes.len() != 2 || !is_number(minutes) {
+        return Err(invalid());
+    }
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 14 || minutes >= 60 {
+        return Err(invalid());
+    }
+    Ok(UtcOffset::seconds(sign * (hours * 3600 + minutes * 60)))
+}
+
+fn format_time_zone(offset: UtcOffset) -> String {
+    let seconds = offset.as_seconds();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    format!("{}{:02}:{:02}", sign, minutes / 60, minutes % 60)
+}
+
+#[test]
+fn test_time_zone() {
+    assert_eq!(parse_time_zone("+01:00"), Ok(UtcOffset::hours(1)));
+    assert_eq!(parse_time_zone("-0530"), Ok(UtcOffset::minutes(-330)));
+    assert_eq!(parse_time_zone("+8"), Ok(UtcOffset::hours(8)));
+    assert_eq!(parse_time_zone("UTC"), Ok(UtcOffset::UTC));
+    assert!(parse_time_zone("01:00").is_err());
+    assert!(parse_time_zone("+01:75").is_err());
+    assert!(parse_time_zone("+1:0:0").is_err());
+    assert!(parse_time_zone("+é1").is_err());
+    assert_eq!(format_time_zone(UtcOffset::minutes(-330)), "-05:30");
+    assert_eq!(format_time_zone(UtcOffset::UTC), "+00:00");
+}
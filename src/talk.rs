@@ -0,0 +1,135 @@
+//! Plays the audio that RTSP clients send on their ONVIF backchannels through the camera's
+//! speaker.  A talk session is opened when audio arrives, and closed again once the clients have
+//! been quiet for a moment.
+use crate::config::CameraConfig;
+use crossbeam::channel::{select, Receiver};
+use log::*;
+use neolink::bc::xml::{AudioConfig, TalkAbility};
+use neolink::bc_protocol::BcCamera;
+use std::time::Duration;
+
+/// The rate at which the backchannel hands us PCM.  Reolink cameras play 16kHz ADPCM.
+pub const TALK_SAMPLE_RATE: u32 = 16000;
+
+/// How long the backchannel must be silent before we stop talking
+const TALK_IDLE: Duration = Duration::from_secs(1);
+
+/// Plays audio from `backchannel` until told to stop.  Only a lost connection is returned as an
+/// error.
+pub fn talk_loop(
+    camera: &BcCamera,
+    camera_config: &CameraConfig,
+    backchannel: &Receiver<Vec<u8>>,
+    stop: &Receiver<()>,
+) -> Result<(), neolink::Error> {
+    // Anything said while we were disconnected is stale now
+    while backchannel.try_recv().is_ok() {}
+
+    let audio_config =
+        allow_refusal(camera_config, camera.talk_ability())?.and_then(find_audio_config);
+    let audio_config = match audio_config {
+        Some(audio_config) => audio_config,
+        None => {
+            warn!(
+                "{}: Camera cannot play {}Hz ADPCM audio, talkback is disabled",
+                camera_config.name, TALK_SAMPLE_RATE
+            );
+            let _ = stop.recv();
+            return Ok(());
+        }
+    };
+
+    let mut talk = None;
+    // Once the camera refuses to talk, such as while another app is talking through it, the rest
+    // of what the client says is dropped rather than asking again for every packet
+    let mut refused = false;
+    loop {
+        select! {
+            recv(stop) -> _ => break,
+            recv(backchannel) -> pcm => {
+                let pcm = match pcm {
+                    Ok(pcm) => pcm,
+                    Err(_) => break,
+                };
+                if talk.is_none() && !refused {
+                    debug!("{}: Talking through the camera", camera_config.name);
+                    talk = allow_refusal(camera_config, camera.talk_start(audio_config.clone()))?;
+                    refused = talk.is_none();
+                }
+                if let Some(active) = talk.as_mut() {
+                    let samples: Vec<i16> = pcm
+                        .chunks_exact(2)
+                        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+                        .collect();
+                    if allow_refusal(camera_config, active.send_pcm(&samples))?.is_none() {
+                        refused = true;
+                        let talk = talk.take().unwrap();
+                        allow_refusal(camera_config, talk.stop())?;
+                    }
+                }
+            }
+            default(TALK_IDLE) => {
+                refused = false;
+                if let Some(talk) = talk.take() {
+                    debug!("{}: Finished talking", camera_config.name);
+                    allow_refusal(camera_config, talk.stop())?;
+                }
+            }
+        }
+    }
+
+    if let Some(talk) = talk {
+        allow_refusal(camera_config, talk.stop())?;
+    }
+    Ok(())
+}
+
+/// Returns errors that mean the connection was lost, which camera_loop reconnects for.  Anything
+/// else is the camera refusing a request, which is logged and becomes `None`, so that one client
+/// failing to talk does not interrupt the video of everyone else.
+fn allow_refusal<T>(
+    camera_config: &CameraConfig,
+    result: Result<T, neolink::Error>,
+) -> Result<Option<T>, neolink::Error> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.is_connection_lost() => Err(e),
+        Err(e) => {
+            warn!("{}: Talkback failed: {}", camera_config.name, e);
+            Ok(None)
+        }
+    }
+}
+
+fn find_audio_config(ability: TalkAbility) -> Option<AudioConfig> {
+    ability
+        .audio_config_list
+        .into_iter()
+        .map(|list| list.audio_config)
+        .find(|config| {
+            config.audio_type.eq_ignore_ascii_case("adpcm")
+                && config.sample_rate == TALK_SAMPLE_RATE
+        })
+}
+
+#[test]
+fn test_allow_refusal() {
+    use crate::config::Config;
+
+    let config: Config = toml::from_str(
+        r#"
+        [[cameras]]
+        name = "porch"
+        username = "admin"
+        address = "192.168.1.12:9000"
+        "#,
+    )
+    .unwrap();
+    let camera_config = &config.cameras[0];
+
+    assert_eq!(allow_refusal(camera_config, Ok(3)).unwrap(), Some(3));
+    let busy: Result<(), _> = Err(neolink::Error::Other("Another app is talking"));
+    assert_eq!(allow_refusal(camera_config, busy).unwrap(), None);
+    let lost: Result<(), _> = Err(neolink::Error::TimeoutDisconnected);
+    assert!(allow_refusal(camera_config, lost).is_err());
+}
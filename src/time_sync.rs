@@ -0,0 +1,220 @@
+//! Keeps the cameras' clocks right.  A camera's clock is checked when it connects and then every
+//! `interval`, and is set if the camera has lost the time.  With the "drift" mode, it is also set
+//! again if it has drifted from this computer's by more than `max_drift`.
+//!
+//! Cameras keep their own time zones, unless `set_time_zone` is on.  Then a camera's time zone is
+//! changed to this computer's whenever they differ, such as when daylight saving time starts or
+//! ends.  A computer without time zone data, such as a container without /etc/localtime, looks
+//! like it is on UTC.
+use crate::config::{CameraConfig, TimeSyncConfig};
+use crossbeam::channel::{Receiver, RecvTimeoutError};
+use log::*;
+use neolink::bc_protocol::BcCamera;
+use std::time::Duration;
+use time::{OffsetDateTime, UtcOffset};
+
+/// Checks the camera's clock, and sets it if it is wrong according to the camera's time_sync
+/// config
+pub fn sync_time(camera: &BcCamera, camera_config: &CameraConfig) -> Result<(), neolink::Error> {
+    let config = &camera_config.time_sync;
+    if config.mode == "off" {
+        return Ok(());
+    }
+
+    let camera_time = camera.get_time()?;
+    let now = OffsetDateTime::now_utc();
+    let local_offset = local_offset(now);
+    let new_time = match correction(camera_time, now, local_offset, config) {
+        Some(new_time) => new_time,
+        None => {
+            debug!(
+                "{}: Camera time is right: {:?}",
+                camera_config.name, camera_time
+            );
+            return Ok(());
+        }
+    };
+
+    match camera_time {
+        Some(time) => warn!(
+            "{}: Camera time is {}, setting it to {}",
+            camera_config.name, time, new_time
+        ),
+        None => warn!(
+            "{}: Camera has no time set, setting to {}",
+            camera_config.name, new_time
+        ),
+    }
+    camera.set_time(new_time)?;
+    let cam_time = camera.get_time()?;
+    if let Some(time) = cam_time {
+        info!("{}: Camera time is now set: {}", camera_config.name, time);
+    } else {
+        error!(
+            "{}: Camera did not accept new time (is {} an admin?)",
+            camera_config.name, camera_config.username
+        );
+    }
+    Ok(())
+}
+
+/// Sets the camera's clock to now, whether or not it has drifted, in the time zone given by
+/// time_zone()
+pub fn set_time_now(camera: &BcCamera, config: &TimeSyncConfig) -> Result<(), neolink::Error> {
+    let camera_time = camera.get_time()?;
+    let now = OffsetDateTime::now_utc();
+    let offset = time_zone(camera_time, local_offset(now), config);
+    camera.set_time(now.to_offset(offset))
+}
+
+/// Now, in the camera's time zone as given by time_zone(), which is the one that its recordings
+/// are named in
+pub fn camera_now(
+    camera: &BcCamera,
+    config: &TimeSyncConfig,
+) -> Result<OffsetDateTime, neolink::Error> {
+    let camera_time = camera.get_time()?;
+    let now = OffsetDateTime::now_utc();
+    Ok(now.to_offset(time_zone(camera_time, local_offset(now), config)))
+}
+
+/// Checks the camera's clock every `interval` until told to stop.  Failures are only logged, as
+/// a camera that has gone away is noticed by the streams and keepalives.
+pub fn sync_loop(camera: &BcCamera, camera_config: &CameraConfig, stop: Receiver<()>) {
+    let interval = Duration::from_secs(camera_config.time_sync.interval);
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(interval) {
+        if let Err(e) = sync_time(camera, camera_config) {
+            warn!(
+                "{}: Could not check the camera's time: {}",
+                camera_config.name, e
+            );
+        }
+    }
+}
+
+/// This computer's time zone at `now`, so that it follows daylight saving time, or None if it
+/// can't be found
+fn local_offset(now: OffsetDateTime) -> Option<UtcOffset> {
+    let offset = UtcOffset::try_local_offset_at(now).ok();
+    if offset.is_none() {
+        debug!("The local time zone is unknown, so cameras keep their own time zones");
+    }
+    offset
+}
+
+/// The time zone that a camera whose clock says `camera_time` should be in.  That is the camera's
+/// own, unless it has lost the time or `set_time_zone` is on, when it is this computer's if that
+/// is known.
+fn time_zone(
+    camera_time: Option<OffsetDateTime>,
+    local_offset: Option<UtcOffset>,
+    config: &TimeSyncConfig,
+) -> UtcOffset {
+    match camera_time {
+        Some(time) if !config.set_time_zone => time.offset(),
+        _ => local_offset
+            .or_else(|| camera_time.map(|time| time.offset()))
+            .unwrap_or(UtcOffset::UTC),
+    }
+}
+
+/// The time to set on a camera whose clock says `camera_time`, or None if it is right enough
+fn correction(
+    camera_time: Option<OffsetDateTime>,
+    now: OffsetDateTime,
+    local_offset: Option<UtcOffset>,
+    config: &TimeSyncConfig,
+) -> Option<OffsetDateTime> {
+    let offset = time_zone(camera_time, local_offset, config);
+    let new_time = now.to_offset(offset);
+    let camera_time = match camera_time {
+        Some(time) => time,
+        None => return Some(new_time),
+    };
+
+    let max_drift = time::Duration::seconds(config.max_drift as i64);
+    let drifted = config.mode == "drift" && (camera_time - now).abs() > max_drift;
+    // Only possible with set_time_zone
+    let moved = camera_time.offset() != offset;
+    if drifted || moved {
+        Some(new_time)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_correction() {
+    let config = TimeSyncConfig {
+        mode: "drift".to_string(),
+        set_time_zone: true,
+        ..Default::default()
+    };
+    let now = time::date!(2021 - 03 - 28)
+        .with_time(time::time!(01:30))
+        .assume_utc();
+    let london = UtcOffset::hours(1);
+    let in_london =
+        |offset_secs: i64| (now + time::Duration::seconds(offset_secs)).to_offset(london);
+
+    // The camera has lost the time
+    assert_eq!(
+        correction(None, now, Some(london), &config),
+        Some(now.to_offset(london))
+    );
+    // Within the allowed drift, and then not
+    assert_eq!(
+        correction(Some(in_london(3)), now, Some(london), &config),
+        None
+    );
+    assert_eq!(
+        correction(Some(in_london(-60)), now, Some(london), &config),
+        Some(now.to_offset(london))
+    );
+    // The right time, but still in winter time.  Times compare equal in any time zone.
+    let in_winter_time = now.to_offset(UtcOffset::UTC);
+    let corrected = correction(Some(in_winter_time), now, Some(london), &config);
+    assert_eq!(corrected.map(|time| time.offset()), Some(london));
+    // Without the local time zone, the camera keeps its own
+    let corrected = correction(Some(in_london(-60)), now, None, &config);
+    assert_eq!(corrected, Some(now));
+    assert_eq!(corrected.map(|time| time.offset()), Some(london));
+
+    let unset_only = TimeSyncConfig {
+        mode: "unset".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        correction(Some(in_london(-60)), now, Some(london), &unset_only),
+        None
+    );
+    assert!(correction(None, now, Some(london), &unset_only).is_some());
+
+    // By default, only a camera that has lost the time is set, and it keeps its own time zone
+    let default = TimeSyncConfig::default();
+    assert_eq!(
+        correction(Some(in_winter_time), now, Some(london), &default),
+        None
+    );
+    assert_eq!(
+        correction(Some(in_london(-60)), now, Some(london), &default),
+        None
+    );
+    assert_eq!(
+        correction(None, now, Some(london), &default),
+        Some(now.to_offset(london))
+    );
+    // Correcting drift without set_time_zone leaves the camera in winter time
+    let drift_only = TimeSyncConfig {
+        mode: "drift".to_string(),
+        ..Default::default()
+    };
+    assert_eq!(
+        correction(Some(in_winter_time), now, Some(london), &drift_only),
+        None
+    );
+    let winter_drifted = in_winter_time - time::Duration::minutes(1);
+    let corrected = correction(Some(winter_drifted), now, Some(london), &drift_only);
+    assert_eq!(corrected, Some(now));
+    assert_eq!(corrected.map(|time| time.offset()), Some(UtcOffset::UTC));
+}
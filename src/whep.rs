@@ -0,0 +1,111 @@
+//! Serves cameras to browsers over WebRTC, using WHEP: the browser POSTs an SDP offer and gets
+//! back an answer, along with a URL to DELETE when it is done.  Only H264 video is sent; browsers
+//! can't play the H265 that some cameras send.
+use err_derive::Error;
+use log::*;
+use neolink::bc_protocol::{MediaData, MediaDataKind};
+use neolink::gst::{StreamFormat, WebRtcSession};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Mutex;
+
+#[derive(Debug, Error)]
+pub enum WhepError {
+    #[error(display = "The camera is not streaming")]
+    NotStreaming,
+    #[error(
+        display = "WebRTC needs H264 video, but the camera is sending {:?}",
+        _0
+    )]
+    NotH264(StreamFormat),
+    #[error(display = "Could not answer the offer: {}", _0)]
+    Answer(#[error(source)] io::Error),
+}
+
+/// The WebRTC viewers of a camera, shared between its stream and the HTTP server
+#[derive(Default)]
+pub struct WhepSessions {
+    inner: Mutex<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    video_format: Option<StreamFormat>,
+    viewers: HashMap<u64, Viewer>,
+    next_id: u64,
+}
+
+struct Viewer {
+    session: WebRtcSession,
+    /// Video is only sent from a keyframe on
+    started: bool,
+}
+
+impl WhepSessions {
+    /// Answers a viewer's SDP offer, returning its session ID and the SDP answer
+    pub fn offer(&self, offer: &str) -> Result<(u64, String), WhepError> {
+        match self.inner.lock().unwrap().video_format {
+            Some(StreamFormat::H264) => {}
+            Some(format) => return Err(WhepError::NotH264(format)),
+            None => return Err(WhepError::NotStreaming),
+        }
+
+        // Answering takes a moment, so don't hold up the stream while we do it
+        let (session, answer) = WebRtcSession::answer(offer)?;
+
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        inner.viewers.insert(
+            id,
+            Viewer {
+                session,
+                started: false,
+            },
+        );
+        Ok((id, answer))
+    }
+
+    /// Ends a viewer's session, returning false if there was no such session
+    pub fn delete(&self, id: u64) -> bool {
+        self.inner.lock().unwrap().viewers.remove(&id).is_some()
+    }
+
+    /// Sends a media packet from the camera to all of the viewers
+    pub fn write_media(&self, media: &MediaData) {
+        let kind = media.kind();
+        let keyframe = match kind {
+            MediaDataKind::VideoDataIframe => true,
+            MediaDataKind::VideoDataPframe => false,
+            _ => return,
+        };
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.video_format = media.media_format();
+        inner.viewers.retain(|id, viewer| {
+            // Checking on the peer is slow, so only do it once in a while
+            if keyframe && viewer.session.is_closed() {
+                debug!("WebRTC viewer {} has gone", id);
+                return false;
+            }
+            viewer.started |= keyframe;
+            if !viewer.started {
+                return true;
+            }
+            match viewer.session.write_video(media.body()) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("WebRTC viewer {} failed: {}", id, e);
+                    false
+                }
+            }
+        });
+    }
+
+    /// Ends all of the sessions, when the camera has disconnected
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.video_format = None;
+        inner.viewers.clear();
+    }
+}